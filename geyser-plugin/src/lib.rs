@@ -0,0 +1,636 @@
+mod records;
+mod socket;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use agave_geyser_plugin_interface::geyser_plugin_interface::{
+    GeyserPlugin, GeyserPluginError, ReplicaAccountInfoVersions, ReplicaBlockInfoVersions,
+    ReplicaTransactionInfoVersions, Result, SlotStatus,
+};
+use serde::Deserialize;
+use solana_sdk::{account::Account, clock::Slot, pubkey::Pubkey};
+
+use records::{
+    account_record, encode_transaction, slot_record, snapshot_record, BlockMeta, PendingSlot,
+};
+use socket::RecordSocket;
+
+// Loaded by the validator from the `--geyser-plugin-config` file
+#[derive(Debug, Deserialize)]
+pub struct PluginConfig {
+    // Unix socket the indexer reads with INGEST_SOCKET
+    pub socket_path: PathBuf,
+    pub programs: Vec<String>,
+}
+
+struct PluginState {
+    socket: RecordSocket,
+    socket_path: PathBuf,
+    // Accounts of the programs, an update from another owner closes or reassigns one of them
+    tracked: HashSet<Pubkey>,
+    // Newest version of each account loaded at startup, `None` when not owned by the programs
+    startup: HashMap<Pubkey, (Slot, u64, Option<Account>)>,
+    // Slots not confirmed yet, the ones left behind by a root were forked off
+    pending: BTreeMap<Slot, PendingSlot>,
+}
+
+impl PluginState {
+    // The accounts and the block of a confirmed slot, nothing of it was sent before
+    fn confirm(&mut self, slot: Slot, programs: &[Pubkey]) {
+        let Some(mut pending) = self.pending.remove(&slot) else {
+            return;
+        };
+        for (pubkey, account) in pending.take_accounts() {
+            if account.lamports > 0 && programs.contains(&account.owner) {
+                self.tracked.insert(pubkey);
+            } else {
+                self.tracked.remove(&pubkey);
+            }
+            self.socket.send(&account_record(slot, &pubkey, &account));
+        }
+        if let Some(block) = pending.block_record(slot) {
+            self.socket.send(&block);
+        }
+    }
+}
+
+// Pushes the accounts and transactions of the configured programs to the indexer, which
+// writes them like any other source. Slots are sent once confirmed, in write version order
+// and without the gaps of the websocket subscriptions.
+#[derive(Default)]
+pub struct ArgosGeyserPlugin {
+    programs: Vec<Pubkey>,
+    state: Mutex<Option<PluginState>>,
+}
+
+impl fmt::Debug for ArgosGeyserPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArgosGeyserPlugin")
+            .field("programs", &self.programs)
+            .finish()
+    }
+}
+
+struct AccountInfo<'a> {
+    pubkey: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    executable: bool,
+    rent_epoch: u64,
+    data: &'a [u8],
+    write_version: u64,
+}
+
+impl AccountInfo<'_> {
+    // Only copied for the accounts that are sent
+    fn to_account(&self) -> Account {
+        Account {
+            lamports: self.lamports,
+            data: self.data.to_vec(),
+            owner: self.owner,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        }
+    }
+}
+
+macro_rules! account_info {
+    ($info:expr) => {
+        AccountInfo {
+            pubkey: parse_pubkey($info.pubkey)?,
+            owner: parse_pubkey($info.owner)?,
+            lamports: $info.lamports,
+            executable: $info.executable,
+            rent_epoch: $info.rent_epoch,
+            data: $info.data,
+            write_version: $info.write_version,
+        }
+    };
+}
+
+fn parse_pubkey(bytes: &[u8]) -> Result<Pubkey> {
+    Pubkey::try_from(bytes).map_err(|_| GeyserPluginError::AccountsUpdateError {
+        msg: format!("Invalid pubkey {:?}", bytes),
+    })
+}
+
+fn custom_error(err: impl std::error::Error + Send + Sync + 'static) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(err))
+}
+
+impl ArgosGeyserPlugin {
+    fn with_state<T>(&self, f: impl FnOnce(&mut PluginState) -> Result<T>) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let state = state
+            .as_mut()
+            .ok_or_else(|| GeyserPluginError::Custom("Plugin is not loaded".into()))?;
+        f(state)
+    }
+}
+
+impl GeyserPlugin for ArgosGeyserPlugin {
+    fn name(&self) -> &'static str {
+        "argos-geyser-plugin"
+    }
+
+    fn on_load(&mut self, config_file: &str, _is_reload: bool) -> Result<()> {
+        let config =
+            fs::read_to_string(config_file).map_err(GeyserPluginError::ConfigFileOpenError)?;
+        let config: PluginConfig = serde_json::from_str(&config).map_err(|err| {
+            GeyserPluginError::ConfigFileReadError {
+                msg: err.to_string(),
+            }
+        })?;
+        self.programs = config
+            .programs
+            .iter()
+            .map(|program| {
+                program
+                    .parse()
+                    .map_err(|_| GeyserPluginError::ConfigFileReadError {
+                        msg: format!("Invalid program ID {}", program),
+                    })
+            })
+            .collect::<Result<_>>()?;
+
+        let socket = RecordSocket::bind(&config.socket_path).map_err(custom_error)?;
+        log::info!(
+            "Pushing {} programs to {}",
+            self.programs.len(),
+            config.socket_path.display()
+        );
+        *self.state.lock().unwrap() = Some(PluginState {
+            socket,
+            socket_path: config.socket_path,
+            tracked: HashSet::new(),
+            startup: HashMap::new(),
+            pending: BTreeMap::new(),
+        });
+        Ok(())
+    }
+
+    fn on_unload(&mut self) {
+        if let Some(state) = self.state.lock().unwrap().take() {
+            let _ = fs::remove_file(&state.socket_path);
+        }
+    }
+
+    fn update_account(
+        &self,
+        account: ReplicaAccountInfoVersions,
+        slot: Slot,
+        is_startup: bool,
+    ) -> Result<()> {
+        let info = match account {
+            ReplicaAccountInfoVersions::V0_0_1(info) => account_info!(info),
+            ReplicaAccountInfoVersions::V0_0_2(info) => account_info!(info),
+            ReplicaAccountInfoVersions::V0_0_3(info) => account_info!(info),
+        };
+        let owned = info.lamports > 0 && self.programs.contains(&info.owner);
+        let version = (slot, info.write_version);
+        self.with_state(|state| {
+            if is_startup {
+                // The validator notifies each account once from its newest storage, an older
+                // version would not replace it anyway
+                match state.startup.get(&info.pubkey) {
+                    Some((newer_slot, newer_version, _))
+                        if (*newer_slot, *newer_version) > version => {}
+                    Some(_) | None if owned => {
+                        state.startup.insert(
+                            info.pubkey,
+                            (slot, info.write_version, Some(info.to_account())),
+                        );
+                    }
+                    Some(_) => {
+                        state
+                            .startup
+                            .insert(info.pubkey, (slot, info.write_version, None));
+                    }
+                    None => {}
+                }
+                return Ok(());
+            }
+            // Tracked right away so a closure in the next slots is not missed
+            if owned {
+                state.tracked.insert(info.pubkey);
+            } else if !state.tracked.contains(&info.pubkey) {
+                return Ok(());
+            }
+            state.pending.entry(slot).or_default().push_account(
+                info.pubkey,
+                info.write_version,
+                info.to_account(),
+            );
+            Ok(())
+        })
+    }
+
+    fn notify_end_of_startup(&self) -> Result<()> {
+        self.with_state(|state| {
+            let slot = state
+                .startup
+                .values()
+                .map(|(slot, _, _)| *slot)
+                .max()
+                .unwrap_or_default();
+            let mut accounts: Vec<(Pubkey, Account)> = state
+                .startup
+                .drain()
+                .filter_map(|(pubkey, (_, _, account))| Some((pubkey, account?)))
+                .collect();
+            accounts.sort_by_key(|(pubkey, _)| *pubkey);
+            state.tracked = accounts.iter().map(|(pubkey, _)| *pubkey).collect();
+            // One per program, like the bootstrap of the other sources
+            for program_id in &self.programs {
+                let owned: Vec<(Pubkey, Account)> = accounts
+                    .iter()
+                    .filter(|(_, account)| account.owner == *program_id)
+                    .cloned()
+                    .collect();
+                state
+                    .socket
+                    .send(&snapshot_record(program_id, slot, &owned));
+            }
+            log::info!("Startup done, {} accounts indexed", state.tracked.len());
+            Ok(())
+        })
+    }
+
+    fn update_slot_status(
+        &self,
+        slot: Slot,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> Result<()> {
+        self.with_state(|state| {
+            match status {
+                SlotStatus::Processed => return Ok(()),
+                SlotStatus::Confirmed => {
+                    state.confirm(slot, &self.programs);
+                    state.socket.send(&slot_record(slot, parent, "confirmed"));
+                }
+                SlotStatus::Rooted => {
+                    // Not always notified as confirmed first
+                    state.confirm(slot, &self.programs);
+                    state.socket.send(&slot_record(slot, parent, "finalized"));
+                    let forked = state.pending.range(..slot).count();
+                    if forked > 0 {
+                        log::info!("Dropped {} forked slots before {}", forked, slot);
+                    }
+                    state.pending = state.pending.split_off(&slot);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn notify_transaction(
+        &self,
+        transaction: ReplicaTransactionInfoVersions,
+        slot: Slot,
+    ) -> Result<()> {
+        let (is_vote, tx, meta, tx_index) = match transaction {
+            ReplicaTransactionInfoVersions::V0_0_1(info) => (
+                info.is_vote,
+                info.transaction,
+                info.transaction_status_meta,
+                None,
+            ),
+            ReplicaTransactionInfoVersions::V0_0_2(info) => (
+                info.is_vote,
+                info.transaction,
+                info.transaction_status_meta,
+                Some(info.index),
+            ),
+        };
+        if is_vote
+            || !tx
+                .message()
+                .account_keys()
+                .iter()
+                .any(|key| self.programs.contains(key))
+        {
+            return Ok(());
+        }
+        let encoded = encode_transaction(tx, meta).map_err(|err| {
+            GeyserPluginError::TransactionUpdateError {
+                msg: err.to_string(),
+            }
+        })?;
+        self.with_state(|state| {
+            state
+                .pending
+                .entry(slot)
+                .or_default()
+                .push_transaction(tx_index, encoded);
+            Ok(())
+        })
+    }
+
+    fn notify_block_metadata(&self, blockinfo: ReplicaBlockInfoVersions) -> Result<()> {
+        macro_rules! block_meta {
+            ($info:expr) => {
+                (
+                    $info.slot,
+                    BlockMeta {
+                        parent_slot: $info.parent_slot,
+                        blockhash: $info.blockhash.to_string(),
+                        parent_blockhash: $info.parent_blockhash.to_string(),
+                        block_time: $info.block_time,
+                        block_height: $info.block_height,
+                    },
+                )
+            };
+        }
+        let (slot, block) = match blockinfo {
+            // The first version has no parent, the indexer does not use it
+            ReplicaBlockInfoVersions::V0_0_1(info) => (
+                info.slot,
+                BlockMeta {
+                    parent_slot: info.slot.saturating_sub(1),
+                    blockhash: info.blockhash.to_string(),
+                    parent_blockhash: String::new(),
+                    block_time: info.block_time,
+                    block_height: info.block_height,
+                },
+            ),
+            ReplicaBlockInfoVersions::V0_0_2(info) => block_meta!(info),
+            ReplicaBlockInfoVersions::V0_0_3(info) => block_meta!(info),
+            ReplicaBlockInfoVersions::V0_0_4(info) => block_meta!(info),
+        };
+        self.with_state(|state| {
+            state.pending.entry(slot).or_default().block = Some(block);
+            Ok(())
+        })
+    }
+
+    fn account_data_notifications_enabled(&self) -> bool {
+        true
+    }
+
+    fn transaction_notifications_enabled(&self) -> bool {
+        true
+    }
+}
+
+/// # Safety
+///
+/// Called by the validator plugin manager, which takes ownership of the returned plugin.
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub unsafe extern "C" fn _create_plugin() -> *mut dyn GeyserPlugin {
+    let plugin: Box<dyn GeyserPlugin> = Box::new(ArgosGeyserPlugin::default());
+    Box::into_raw(plugin)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixStream,
+        time::Duration,
+    };
+
+    use agave_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaAccountInfoV3, ReplicaBlockInfoV3, ReplicaTransactionInfoV2,
+    };
+    use serde_json::Value;
+    use solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey,
+        signature::{Keypair, Signer},
+        system_program,
+        transaction::{SanitizedTransaction, Transaction, VersionedTransaction},
+    };
+    use solana_transaction_status::{EncodedTransactionWithStatusMeta, TransactionStatusMeta};
+
+    use super::*;
+
+    const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+    // A plugin loaded on a fresh socket, with the indexer end of it
+    fn load_plugin(name: &str) -> (ArgosGeyserPlugin, BufReader<UnixStream>) {
+        let dir =
+            std::env::temp_dir().join(format!("argos-geyser-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("argos.sock");
+
+        let config_path = dir.join("config.json");
+        let config = serde_json::json!({
+            "libpath": "libargos_geyser_plugin.so",
+            "socket_path": socket_path,
+            "programs": [PROGRAM_ID.to_string()],
+        });
+        fs::write(&config_path, config.to_string()).unwrap();
+
+        let mut plugin = ArgosGeyserPlugin::default();
+        plugin
+            .on_load(config_path.to_str().unwrap(), false)
+            .unwrap();
+        let stream = UnixStream::connect(&socket_path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (plugin, BufReader::new(stream))
+    }
+
+    fn next_record(indexer: &mut BufReader<UnixStream>) -> Value {
+        let mut line = String::new();
+        indexer.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn update_account(
+        plugin: &ArgosGeyserPlugin,
+        pubkey: &Pubkey,
+        owner: &Pubkey,
+        lamports: u64,
+        data: &[u8],
+        slot: Slot,
+        write_version: u64,
+        is_startup: bool,
+    ) {
+        let info = ReplicaAccountInfoV3 {
+            pubkey: pubkey.as_ref(),
+            lamports,
+            owner: owner.as_ref(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            data,
+            write_version,
+            txn: None,
+        };
+        plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), slot, is_startup)
+            .unwrap();
+    }
+
+    #[test]
+    fn accounts_in_write_version_order() {
+        let (plugin, mut indexer) = load_plugin("accounts");
+        let proof = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        update_account(&plugin, &proof, &PROGRAM_ID, 1_000, &[0], 10, 1, true);
+        update_account(
+            &plugin,
+            &other,
+            &system_program::id(),
+            1_000,
+            &[],
+            10,
+            2,
+            true,
+        );
+        plugin.notify_end_of_startup().unwrap();
+
+        let snapshot = next_record(&mut indexer);
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["slot"], 10);
+        let accounts = snapshot["accounts"].as_array().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0]["pubkey"], proof.to_string());
+
+        update_account(&plugin, &proof, &PROGRAM_ID, 1_000, &[2], 12, 5, false);
+        // late notifications of older writes
+        update_account(&plugin, &proof, &PROGRAM_ID, 1_000, &[1], 12, 3, false);
+        update_account(&plugin, &proof, &PROGRAM_ID, 1_000, &[9], 11, 4, false);
+        plugin
+            .update_slot_status(11, Some(10), SlotStatus::Confirmed)
+            .unwrap();
+        plugin
+            .update_slot_status(12, Some(11), SlotStatus::Confirmed)
+            .unwrap();
+
+        let account = next_record(&mut indexer);
+        assert_eq!(
+            (account["type"].clone(), account["slot"].clone()),
+            ("account".into(), 11.into())
+        );
+        assert_eq!(account["account"]["data"][0], "CQ==");
+        assert_eq!(next_record(&mut indexer)["status"], "confirmed");
+        let account = next_record(&mut indexer);
+        assert_eq!(account["slot"], 12);
+        assert_eq!(account["account"]["data"][0], "Ag==");
+        assert_eq!(next_record(&mut indexer)["slot"], 12);
+
+        // closed, then no longer followed
+        update_account(&plugin, &proof, &system_program::id(), 0, &[], 13, 6, false);
+        plugin
+            .update_slot_status(13, Some(12), SlotStatus::Confirmed)
+            .unwrap();
+        update_account(&plugin, &proof, &system_program::id(), 5, &[], 14, 7, false);
+        plugin
+            .update_slot_status(14, Some(13), SlotStatus::Confirmed)
+            .unwrap();
+        let account = next_record(&mut indexer);
+        assert_eq!(account["slot"], 13);
+        assert_eq!(
+            account["account"]["owner"],
+            system_program::id().to_string()
+        );
+        assert_eq!(next_record(&mut indexer)["slot"], 13);
+        let slot = next_record(&mut indexer);
+        assert_eq!(
+            (slot["type"].clone(), slot["slot"].clone()),
+            ("slot".into(), 14.into())
+        );
+    }
+
+    #[test]
+    fn forked_slots_are_dropped() {
+        let (plugin, mut indexer) = load_plugin("forks");
+        plugin.notify_end_of_startup().unwrap();
+        assert_eq!(next_record(&mut indexer)["type"], "snapshot");
+
+        let proof = Pubkey::new_unique();
+        // 21 and 22 fork off 20, 22 gets rooted
+        update_account(&plugin, &proof, &PROGRAM_ID, 1_000, &[1], 21, 1, false);
+        update_account(&plugin, &proof, &PROGRAM_ID, 1_000, &[2], 22, 2, false);
+        plugin
+            .update_slot_status(22, Some(20), SlotStatus::Rooted)
+            .unwrap();
+        plugin
+            .update_slot_status(23, Some(22), SlotStatus::Rooted)
+            .unwrap();
+
+        let account = next_record(&mut indexer);
+        assert_eq!(account["slot"], 22);
+        let slot = next_record(&mut indexer);
+        assert_eq!(
+            (slot["slot"].clone(), slot["status"].clone()),
+            (22.into(), "finalized".into())
+        );
+        let slot = next_record(&mut indexer);
+        assert_eq!(
+            (slot["type"].clone(), slot["slot"].clone()),
+            ("slot".into(), 23.into())
+        );
+        assert!(plugin
+            .with_state(|state| Ok(state.pending.is_empty()))
+            .unwrap());
+    }
+
+    #[test]
+    fn transactions_of_indexed_programs() {
+        let (plugin, mut indexer) = load_plugin("transactions");
+        let payer = Keypair::new();
+        let proof = Pubkey::new_unique();
+        let ix =
+            Instruction::new_with_bytes(PROGRAM_ID, &[1], vec![AccountMeta::new(proof, false)]);
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Default::default(),
+        );
+        let tx = SanitizedTransaction::from_transaction_for_tests(tx);
+        let meta = TransactionStatusMeta::default();
+        let info = ReplicaTransactionInfoV2 {
+            signature: tx.signature(),
+            is_vote: false,
+            transaction: &tx,
+            transaction_status_meta: &meta,
+            index: 3,
+        };
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 20)
+            .unwrap();
+
+        let block = ReplicaBlockInfoV3 {
+            parent_slot: 19,
+            parent_blockhash: "",
+            slot: 20,
+            blockhash: "",
+            rewards: &[],
+            block_time: Some(1_729_300_000),
+            block_height: Some(18),
+            executed_transaction_count: 4,
+            entry_count: 1,
+        };
+        plugin
+            .notify_block_metadata(ReplicaBlockInfoVersions::V0_0_3(&block))
+            .unwrap();
+        plugin
+            .update_slot_status(20, Some(19), SlotStatus::Rooted)
+            .unwrap();
+
+        let record = next_record(&mut indexer);
+        assert_eq!(record["type"], "block");
+        assert_eq!(record["txIndexes"], serde_json::json!([3]));
+        assert_eq!(record["block"]["blockTime"], 1_729_300_000);
+        assert_eq!(record["block"]["parentSlot"], 19);
+        let transactions: Vec<EncodedTransactionWithStatusMeta> =
+            serde_json::from_value(record["block"]["transactions"].clone()).unwrap();
+        let decoded: VersionedTransaction = transactions[0].transaction.decode().unwrap();
+        assert_eq!(decoded.signatures[0], *tx.signature());
+        assert!(transactions[0].meta.is_some());
+        assert_eq!(next_record(&mut indexer)["status"], "finalized");
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_sdk::{
+    account::Account, clock::Slot, pubkey::Pubkey, transaction::SanitizedTransaction,
+};
+use solana_transaction_status::{
+    EncodeError, EncodedTransactionWithStatusMeta, TransactionStatusMeta, UiTransactionEncoding,
+    VersionedTransactionWithStatusMeta,
+};
+
+// Records are lines in the shape of the indexer's `IngestRecord`, which writes them like those
+// of any other source. A snapshot stands for the bootstrap.
+pub fn snapshot_record(program_id: &Pubkey, slot: Slot, accounts: &[(Pubkey, Account)]) -> Value {
+    let accounts: Vec<Value> = accounts
+        .iter()
+        .map(|(pubkey, account)| {
+            json!({"pubkey": pubkey.to_string(), "account": ui_account(pubkey, account)})
+        })
+        .collect();
+    json!({
+        "type": "snapshot",
+        "programId": program_id.to_string(),
+        "slot": slot,
+        "accounts": accounts,
+    })
+}
+
+pub fn account_record(slot: Slot, pubkey: &Pubkey, account: &Account) -> Value {
+    json!({
+        "type": "account",
+        "slot": slot,
+        "pubkey": pubkey.to_string(),
+        "account": ui_account(pubkey, account),
+    })
+}
+
+pub fn slot_record(slot: Slot, parent: Option<Slot>, status: &str) -> Value {
+    json!({"type": "slot", "slot": slot, "parent": parent, "status": status})
+}
+
+fn ui_account(pubkey: &Pubkey, account: &Account) -> UiAccount {
+    UiAccount::encode(pubkey, account, UiAccountEncoding::Base64, None, None)
+}
+
+pub struct BlockMeta {
+    pub parent_slot: Slot,
+    pub blockhash: String,
+    pub parent_blockhash: String,
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+}
+
+// Notifications of a slot that is not confirmed yet
+#[derive(Default)]
+pub struct PendingSlot {
+    // Latest write of each account
+    accounts: HashMap<Pubkey, (u64, Account)>,
+    transactions: Vec<(Option<usize>, EncodedTransactionWithStatusMeta)>,
+    pub block: Option<BlockMeta>,
+}
+
+impl PendingSlot {
+    pub fn push_account(&mut self, pubkey: Pubkey, write_version: u64, account: Account) {
+        match self.accounts.get(&pubkey) {
+            Some((newer, _)) if *newer > write_version => {}
+            _ => {
+                self.accounts.insert(pubkey, (write_version, account));
+            }
+        }
+    }
+
+    pub fn push_transaction(
+        &mut self,
+        tx_index: Option<usize>,
+        tx: EncodedTransactionWithStatusMeta,
+    ) {
+        self.transactions.push((tx_index, tx));
+    }
+
+    // Accounts in write order
+    pub fn take_accounts(&mut self) -> Vec<(Pubkey, Account)> {
+        let mut accounts: Vec<(Pubkey, u64, Account)> = self
+            .accounts
+            .drain()
+            .map(|(pubkey, (write_version, account))| (pubkey, write_version, account))
+            .collect();
+        accounts.sort_by_key(|(_, write_version, _)| *write_version);
+        accounts
+            .into_iter()
+            .map(|(pubkey, _, account)| (pubkey, account))
+            .collect()
+    }
+
+    // The transactions in block order with their positions, which the indexer can not get
+    // from the signatures of the block
+    pub fn block_record(mut self, slot: Slot) -> Option<Value> {
+        let block = self.block?;
+        self.transactions
+            .sort_by_key(|(tx_index, _)| tx_index.unwrap_or(usize::MAX));
+        let (tx_indexes, transactions): (Vec<Option<usize>>, Vec<_>) =
+            self.transactions.into_iter().unzip();
+        Some(json!({
+            "type": "block",
+            "slot": slot,
+            "txIndexes": tx_indexes,
+            "block": {
+                "previousBlockhash": block.parent_blockhash,
+                "blockhash": block.blockhash,
+                "parentSlot": block.parent_slot,
+                "transactions": transactions,
+                "blockTime": block.block_time,
+                "blockHeight": block.block_height,
+            },
+        }))
+    }
+}
+
+pub fn encode_transaction(
+    tx: &SanitizedTransaction,
+    meta: &TransactionStatusMeta,
+) -> Result<EncodedTransactionWithStatusMeta, EncodeError> {
+    VersionedTransactionWithStatusMeta {
+        transaction: tx.to_versioned_transaction(),
+        meta: meta.clone(),
+    }
+    .encode(UiTransactionEncoding::Base64, Some(0), false)
+}
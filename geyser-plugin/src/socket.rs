@@ -0,0 +1,71 @@
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::net::UnixListener,
+    path::Path,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+
+use serde_json::Value;
+
+// Lines waiting for the indexer, past it the validator waits for room rather than
+// leaving a gap the indexer could not see
+const QUEUE_SIZE: usize = 100_000;
+
+// Unix socket the indexer connects to, one record per line in the shape of its recordings.
+// Lines queue up while it is not connected, the one a broken connection failed on is sent
+// again to the next one.
+pub struct RecordSocket {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl RecordSocket {
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        // Left over by a previous run
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        thread::Builder::new()
+            .name("argos-socket".to_string())
+            .spawn(move || write_lines(listener, receiver))?;
+        Ok(Self { sender })
+    }
+
+    pub fn send(&self, record: &Value) {
+        let mut line = record.to_string().into_bytes();
+        line.push(b'\n');
+        if self.sender.send(line).is_err() {
+            log::error!("[!] Record socket is closed");
+        }
+    }
+}
+
+fn write_lines(listener: UnixListener, receiver: Receiver<Vec<u8>>) {
+    let mut unsent = None;
+    loop {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::error!("[!] Failed to accept the indexer : {}", err);
+                continue;
+            }
+        };
+        log::info!("Indexer connected");
+        loop {
+            let line = match unsent.take() {
+                Some(line) => line,
+                None => match receiver.recv() {
+                    Ok(line) => line,
+                    // The plugin was unloaded
+                    Err(_) => return,
+                },
+            };
+            if let Err(err) = stream.write_all(&line) {
+                log::error!("[!] Indexer disconnected : {}", err);
+                unsent = Some(line);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::SqlitePool;
+
+const DEFAULT_LIMIT: i64 = 1000;
+const MAX_LIMIT: i64 = 10_000;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceChangesQuery {
+    // a mint address, or SOL for lamports
+    mint: Option<String>,
+    from_slot: Option<i64>,
+    to_slot: Option<i64>,
+    limit: Option<i64>,
+}
+
+// Changes of `address` itself and, for a wallet, of the token accounts it owns
+pub async fn balance_changes(
+    Extension(pool): Extension<SqlitePool>,
+    Path(address): Path<String>,
+    Query(query): Query<BalanceChangesQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let address = Pubkey::from_str(&address)
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+        .to_string();
+    let from_slot = query.from_slot.unwrap_or(0);
+    let to_slot = query.to_slot.unwrap_or(i64::MAX);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let rows = sqlx::query!(
+        r#"SELECT b.signature, b.slot, t.block_time, b.account, b.mint, b.owner,
+            b.pre, b.post, b.delta, b.decimals
+        FROM balance_changes b JOIN transactions t ON t.signature = b.signature
+        WHERE (b.account = ?1 OR b.owner = ?1) AND (?2 IS NULL OR b.mint = ?2)
+        AND b.slot BETWEEN ?3 AND ?4
+        ORDER BY b.slot DESC, t.tx_index DESC LIMIT ?5"#,
+        address,
+        query.mint,
+        from_slot,
+        to_slot,
+        limit,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|err| {
+        tracing::error!("[!] Balance changes query failed : {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let changes: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            json!({
+                "signature": row.signature,
+                "slot": row.slot,
+                "blockTime": row.block_time,
+                "account": row.account,
+                "mint": row.mint,
+                "owner": row.owner,
+                "pre": row.pre,
+                "post": row.post,
+                "delta": row.delta,
+                "decimals": row.decimals,
+            })
+        })
+        .collect();
+    Ok(Json(json!({"address": address, "changes": changes})))
+}
@@ -0,0 +1,186 @@
+mod anchor_idl;
+mod ore_accounts;
+mod ore_instructions;
+
+pub use anchor_idl::*;
+pub use ore_accounts::*;
+pub use ore_instructions::*;
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use solana_account_decoder::{
+    parse_account_data::ParsedAccount, UiAccount, UiAccountData, UiAccountEncoding,
+};
+use solana_rpc_client_api::config::RpcAccountInfoConfig;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqliteExecutor};
+
+use crate::services::{IndexedInstruction, IndexedTransaction};
+
+#[derive(Debug, Clone)]
+pub struct DecodedAccount {
+    pub account_type: String,
+    pub data: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedInstruction {
+    pub name: String,
+    pub data: Value,
+}
+
+// Ore has its own decoders, any other program needs a registered or fetched IDL
+pub fn decode_account(
+    program_id: &Pubkey,
+    idl: Option<&AnchorIdl>,
+    data: &[u8],
+) -> Option<DecodedAccount> {
+    if program_id == &ORE_PROGRAM_ID {
+        let account = OreAccount::decode(data)?;
+        return Some(DecodedAccount {
+            account_type: account.account_type().to_string(),
+            data: serde_json::to_value(&account).ok()?,
+        });
+    }
+    let (account_type, data) = idl?.decode_account(data)?;
+    Some(DecodedAccount { account_type, data })
+}
+
+pub fn decode_instruction(
+    idl: Option<&AnchorIdl>,
+    ix: &IndexedInstruction,
+) -> Option<DecodedInstruction> {
+    if ix.program_id == ORE_PROGRAM_ID {
+        let decoded = OreInstruction::decode(&ix.data)?;
+        return Some(DecodedInstruction {
+            name: decoded.kind().to_string(),
+            data: serde_json::to_value(&decoded).ok()?,
+        });
+    }
+    let (name, data) = idl?.decode_instruction(&ix.data, &ix.accounts)?;
+    Some(DecodedInstruction { name, data })
+}
+
+// `jsonParsed` account data, in the shape the RPC uses for the programs it knows
+pub fn parse_account(
+    program_id: &Pubkey,
+    idl: Option<&AnchorIdl>,
+    data: &[u8],
+) -> Option<ParsedAccount> {
+    let decoded = decode_account(program_id, idl, data)?;
+    let program = match idl {
+        _ if program_id == &ORE_PROGRAM_ID => "ore".to_string(),
+        Some(idl) => idl.name.clone(),
+        None => program_id.to_string(),
+    };
+    Some(ParsedAccount {
+        program,
+        parsed: json!({"type": decoded.account_type, "info": decoded.data}),
+        space: data.len() as u64,
+    })
+}
+
+// UiAccount::encode only parses the programs the validator knows about, this adds ours
+pub fn encode_account(
+    pubkey: &Pubkey,
+    account: &Account,
+    config: &RpcAccountInfoConfig,
+    idl: Option<&AnchorIdl>,
+) -> UiAccount {
+    let encoding = config.encoding.unwrap_or(UiAccountEncoding::Base64);
+    let mut encoded = UiAccount::encode(pubkey, account, encoding, None, config.data_slice);
+    if encoding == UiAccountEncoding::JsonParsed {
+        if let Some(parsed) = parse_account(&account.owner, idl, &account.data) {
+            encoded.data = UiAccountData::Json(parsed);
+        }
+    }
+    encoded
+}
+
+// Ore never needs one, this saves a lookup per account update
+pub async fn load_decoder_idl(
+    db: impl SqliteExecutor<'_>,
+    program_id: &Pubkey,
+) -> Result<Option<AnchorIdl>, sqlx::Error> {
+    if program_id == &ORE_PROGRAM_ID {
+        return Ok(None);
+    }
+    load_idl(db, program_id).await
+}
+
+// Stores the JSON projection of the `(id, slot, data)` accounts a decoder knows about
+pub async fn index_decoded_accounts(
+    conn: &mut SqliteConnection,
+    program_id: &Pubkey,
+    accounts: &[(String, u64, &[u8])],
+) -> Result<(), sqlx::Error> {
+    let idl = load_decoder_idl(&mut *conn, program_id).await?;
+    let decoded: Vec<(&String, u64, DecodedAccount)> = accounts
+        .iter()
+        .filter_map(|(id, slot, data)| {
+            decode_account(program_id, idl.as_ref(), data).map(|decoded| (id, *slot, decoded))
+        })
+        .collect();
+
+    for chunk in decoded.chunks(1_000) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO decoded_accounts (id, slot, program_id, account_type, data) ",
+        );
+        query_builder.push_values(chunk, |mut b, (id, slot, decoded)| {
+            b.push_bind(id.to_string())
+                .push_bind(*slot as i64)
+                .push_bind(program_id.to_string())
+                .push_bind(decoded.account_type.clone())
+                .push_bind(decoded.data.to_string());
+        });
+        query_builder.build().execute(&mut *conn).await?;
+    }
+
+    if program_id == &ORE_PROGRAM_ID {
+        let proofs = decoded
+            .iter()
+            .filter(|(_, _, decoded)| decoded.account_type == "proof");
+        for (id, slot, _) in proofs {
+            update_mine_rewards(conn, id, *slot).await?;
+        }
+    }
+    Ok(())
+}
+
+// Fills the name and JSON arguments of the instructions `index_instructions` stored
+pub async fn index_decoded_instructions(
+    conn: &mut SqliteConnection,
+    txs: &[IndexedTransaction],
+) -> Result<(), sqlx::Error> {
+    let mut idls: HashMap<Pubkey, Option<AnchorIdl>> = HashMap::new();
+    let mut decoded = vec![];
+    for indexed in txs {
+        for ix in &indexed.instructions {
+            if !idls.contains_key(&ix.program_id) {
+                let idl = load_decoder_idl(&mut *conn, &ix.program_id).await?;
+                idls.insert(ix.program_id, idl);
+            }
+            if let Some(instruction) = decode_instruction(idls[&ix.program_id].as_ref(), ix) {
+                decoded.push((&indexed.signature, ix, instruction));
+            }
+        }
+    }
+
+    for (signature, ix, instruction) in decoded {
+        let ix_index = ix.ix_index as i64;
+        let inner_index = ix.inner_index.map(|i| i as i64).unwrap_or(-1);
+        let data = instruction.data.to_string();
+        sqlx::query!(
+            "UPDATE instructions SET name = ?, decoded = ? WHERE signature = ? AND ix_index = ? AND inner_index = ?",
+            instruction.name,
+            data,
+            signature,
+            ix_index,
+            inner_index,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
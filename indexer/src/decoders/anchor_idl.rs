@@ -0,0 +1,474 @@
+use std::{collections::HashMap, io::Read};
+
+use flate2::read::ZlibDecoder;
+use serde_json::{json, Map, Value};
+use solana_account_decoder::UiAccount;
+use solana_sdk::{account::Account, hash::hashv, pubkey::Pubkey};
+use sqlx::{SqliteExecutor, SqlitePool};
+
+use crate::services::LimitedRequestClient;
+
+const DISCRIMINATOR_LEN: usize = 8;
+const IDL_SEED: &str = "anchor:idl";
+// discriminator, authority, then the u32 length of the zlib compressed IDL
+const IDL_HEADER_LEN: usize = DISCRIMINATOR_LEN + 32;
+
+#[derive(Debug, Clone)]
+struct IdlAccount {
+    name: String,
+    discriminator: Vec<u8>,
+    ty: Value,
+}
+
+#[derive(Debug, Clone)]
+struct IdlInstruction {
+    name: String,
+    discriminator: Vec<u8>,
+    args: Vec<Value>,
+    accounts: Vec<String>,
+}
+
+// Both the legacy (< 0.30) and the current Anchor IDL formats, the legacy one has no
+// explicit discriminators and keeps account layouts inline
+#[derive(Debug, Clone)]
+pub struct AnchorIdl {
+    pub name: String,
+    accounts: Vec<IdlAccount>,
+    instructions: Vec<IdlInstruction>,
+    types: HashMap<String, Value>,
+}
+
+impl AnchorIdl {
+    pub fn from_json(idl: &Value) -> Result<Self, String> {
+        let name = idl["metadata"]["name"]
+            .as_str()
+            .or(idl["name"].as_str())
+            .ok_or("IDL has no name")?
+            .to_string();
+
+        let types: HashMap<String, Value> = array(&idl["types"])
+            .iter()
+            .filter_map(|ty| Some((ty["name"].as_str()?.to_string(), ty["type"].clone())))
+            .collect();
+
+        let accounts = array(&idl["accounts"])
+            .iter()
+            .map(|account| {
+                let name = account["name"].as_str().ok_or("Account without name")?;
+                let ty = match account.get("type") {
+                    Some(ty) => ty.clone(),
+                    None => types
+                        .get(name)
+                        .cloned()
+                        .ok_or(format!("Account type {} not found", name))?,
+                };
+                Ok(IdlAccount {
+                    name: name.to_string(),
+                    discriminator: discriminator(account, "account", name)?,
+                    ty,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let instructions = array(&idl["instructions"])
+            .iter()
+            .map(|ix| {
+                let name = ix["name"].as_str().ok_or("Instruction without name")?;
+                let mut accounts = vec![];
+                flatten_accounts(&ix["accounts"], &mut accounts);
+                Ok(IdlInstruction {
+                    name: name.to_string(),
+                    discriminator: discriminator(ix, "global", &snake_case(name))?,
+                    args: array(&ix["args"]).to_vec(),
+                    accounts,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            name,
+            accounts,
+            instructions,
+            types,
+        })
+    }
+
+    pub fn decode_account(&self, data: &[u8]) -> Option<(String, Value)> {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| data.starts_with(&account.discriminator))?;
+        let mut reader = &data[account.discriminator.len()..];
+        // accounts are usually allocated bigger than their layout, trailing bytes are fine
+        let value = self.decode_defined(&account.ty, &mut reader)?;
+        Some((account.name.clone(), value))
+    }
+
+    pub fn decode_instruction(&self, data: &[u8], accounts: &[Pubkey]) -> Option<(String, Value)> {
+        let ix = self
+            .instructions
+            .iter()
+            .find(|ix| data.starts_with(&ix.discriminator))?;
+        let mut reader = &data[ix.discriminator.len()..];
+        let mut args = Map::new();
+        for arg in &ix.args {
+            let name = arg["name"].as_str()?;
+            args.insert(
+                name.to_string(),
+                self.decode_type(&arg["type"], &mut reader)?,
+            );
+        }
+        // remaining accounts are not named by the IDL
+        let named_accounts: Map<String, Value> = ix
+            .accounts
+            .iter()
+            .zip(accounts)
+            .map(|(name, pubkey)| (name.clone(), json!(pubkey.to_string())))
+            .collect();
+        Some((
+            ix.name.clone(),
+            json!({"args": args, "accounts": named_accounts}),
+        ))
+    }
+
+    fn decode_type(&self, ty: &Value, reader: &mut &[u8]) -> Option<Value> {
+        if let Some(primitive) = ty.as_str() {
+            return decode_primitive(primitive, reader);
+        }
+        if let Some(inner) = ty.get("vec") {
+            let len = u32::from_le_bytes(take(reader)?);
+            return (0..len)
+                .map(|_| self.decode_type(inner, reader))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array);
+        }
+        if let Some(inner) = ty.get("option") {
+            let [tag] = take::<1>(reader)?;
+            return match tag {
+                0 => Some(Value::Null),
+                _ => self.decode_type(inner, reader),
+            };
+        }
+        if let Some(inner) = ty.get("coption") {
+            let tag = u32::from_le_bytes(take(reader)?);
+            return match tag {
+                0 => Some(Value::Null),
+                _ => self.decode_type(inner, reader),
+            };
+        }
+        if let Some([inner, len]) = ty
+            .get("array")
+            .and_then(|a| a.as_array())
+            .map(|a| a.as_slice())
+        {
+            return (0..len.as_u64()?)
+                .map(|_| self.decode_type(inner, reader))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array);
+        }
+        if let Some(defined) = ty.get("defined") {
+            let name = defined.as_str().or(defined["name"].as_str())?;
+            return self.decode_defined(self.types.get(name)?, reader);
+        }
+        None
+    }
+
+    fn decode_defined(&self, ty: &Value, reader: &mut &[u8]) -> Option<Value> {
+        match ty["kind"].as_str()? {
+            "struct" => self.decode_fields(&ty["fields"], reader),
+            "enum" => {
+                let [tag] = take::<1>(reader)?;
+                let variant = ty["variants"].as_array()?.get(tag as usize)?;
+                let name = variant["name"].as_str()?.to_string();
+                let fields = self.decode_fields(&variant["fields"], reader)?;
+                Some(json!({ name: fields }))
+            }
+            "type" => self.decode_type(&ty["alias"], reader),
+            _ => None,
+        }
+    }
+
+    // Named fields give an object, tuple fields an array
+    fn decode_fields(&self, fields: &Value, reader: &mut &[u8]) -> Option<Value> {
+        let fields = array(fields);
+        if fields.iter().all(|field| field.get("name").is_some()) {
+            let mut object = Map::new();
+            for field in fields {
+                let name = field["name"].as_str()?.to_string();
+                object.insert(name, self.decode_type(&field["type"], reader)?);
+            }
+            Some(Value::Object(object))
+        } else {
+            fields
+                .iter()
+                .map(|ty| self.decode_type(ty, reader))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array)
+        }
+    }
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(|a| a.as_slice()).unwrap_or_default()
+}
+
+fn discriminator(item: &Value, namespace: &str, name: &str) -> Result<Vec<u8>, String> {
+    match item.get("discriminator") {
+        Some(discriminator) => serde_json::from_value(discriminator.clone())
+            .map_err(|err| format!("Invalid discriminator of {} : {}", name, err)),
+        None => Ok(
+            hashv(&[format!("{}:{}", namespace, name).as_bytes()]).to_bytes()[..DISCRIMINATOR_LEN]
+                .to_vec(),
+        ),
+    }
+}
+
+// Legacy IDLs name instructions in camelCase, their sighash uses the rust name
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+// Legacy IDLs can nest account groups
+fn flatten_accounts(accounts: &Value, names: &mut Vec<String>) {
+    for account in array(accounts) {
+        match account.get("accounts") {
+            Some(group) => flatten_accounts(group, names),
+            None => names.extend(account["name"].as_str().map(|n| n.to_string())),
+        }
+    }
+}
+
+fn take<const N: usize>(reader: &mut &[u8]) -> Option<[u8; N]> {
+    let (bytes, rest) = reader.split_at_checked(N)?;
+    *reader = rest;
+    bytes.try_into().ok()
+}
+
+// 128 bits integers do not fit JSON numbers, they are serialized as strings
+fn decode_primitive(ty: &str, reader: &mut &[u8]) -> Option<Value> {
+    let value = match ty {
+        "bool" => json!(take::<1>(reader)?[0] != 0),
+        "u8" => json!(u8::from_le_bytes(take(reader)?)),
+        "i8" => json!(i8::from_le_bytes(take(reader)?)),
+        "u16" => json!(u16::from_le_bytes(take(reader)?)),
+        "i16" => json!(i16::from_le_bytes(take(reader)?)),
+        "u32" => json!(u32::from_le_bytes(take(reader)?)),
+        "i32" => json!(i32::from_le_bytes(take(reader)?)),
+        "f32" => json!(f32::from_le_bytes(take(reader)?)),
+        "u64" => json!(u64::from_le_bytes(take(reader)?)),
+        "i64" => json!(i64::from_le_bytes(take(reader)?)),
+        "f64" => json!(f64::from_le_bytes(take(reader)?)),
+        "u128" => json!(u128::from_le_bytes(take(reader)?).to_string()),
+        "i128" => json!(i128::from_le_bytes(take(reader)?).to_string()),
+        "publicKey" | "pubkey" => json!(Pubkey::new_from_array(take(reader)?).to_string()),
+        "string" | "bytes" => {
+            let len = u32::from_le_bytes(take(reader)?) as usize;
+            let (bytes, rest) = reader.split_at_checked(len)?;
+            *reader = rest;
+            if ty == "string" {
+                json!(std::str::from_utf8(bytes).ok()?)
+            } else {
+                json!(bytes)
+            }
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+pub fn idl_address(program_id: &Pubkey) -> Pubkey {
+    let (base, _) = Pubkey::find_program_address(&[], program_id);
+    Pubkey::create_with_seed(&base, IDL_SEED, program_id).unwrap()
+}
+
+pub async fn load_idl(
+    db: impl SqliteExecutor<'_>,
+    program_id: &Pubkey,
+) -> Result<Option<AnchorIdl>, sqlx::Error> {
+    let program_id = program_id.to_string();
+    let row = sqlx::query!(
+        r#"SELECT idl AS "idl: String" FROM idls WHERE program_id = ?"#,
+        program_id
+    )
+    .fetch_optional(db)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let idl = serde_json::from_str(&row.idl)
+        .map_err(|err| err.to_string())
+        .and_then(|idl| AnchorIdl::from_json(&idl));
+    match idl {
+        Ok(idl) => Ok(Some(idl)),
+        Err(err) => {
+            tracing::error!("[!] Invalid stored IDL of {} : {}", program_id, err);
+            Ok(None)
+        }
+    }
+}
+
+// `source` is "registered" for user provided IDLs and "onchain" for fetched ones
+pub async fn store_idl(
+    db: &SqlitePool,
+    program_id: &Pubkey,
+    source: &str,
+    idl: &Value,
+) -> Result<AnchorIdl, String> {
+    let parsed = AnchorIdl::from_json(idl)?;
+    let program_id = program_id.to_string();
+    let idl = idl.to_string();
+    sqlx::query!(
+        "INSERT OR REPLACE INTO idls (program_id, source, idl, updated_at) VALUES (?, ?, ?, unixepoch())",
+        program_id,
+        source,
+        idl,
+    )
+    .execute(db)
+    .await
+    .map_err(|err| err.to_string())?;
+    Ok(parsed)
+}
+
+pub async fn fetch_onchain_idl(
+    client: &LimitedRequestClient,
+    program_id: &Pubkey,
+) -> Result<Value, String> {
+    let address = idl_address(program_id);
+    let resp = client
+        .proxy_request(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getAccountInfo",
+            "params": [address.to_string(), {"encoding": "base64"}],
+        }))
+        .await?;
+    if let Some(err) = resp.get("error") {
+        return Err(format!("[!] IDL account error {:?}", err));
+    }
+    let value = resp["result"]["value"].clone();
+    if value.is_null() {
+        return Err(format!("No IDL account {} for {}", address, program_id));
+    }
+    let account = serde_json::from_value::<UiAccount>(value)
+        .map_err(|err| err.to_string())?
+        .decode::<Account>()
+        .ok_or("Invalid IDL account data")?;
+    decode_idl_account(&account.data)
+}
+
+fn decode_idl_account(data: &[u8]) -> Result<Value, String> {
+    let mut reader = data.get(IDL_HEADER_LEN..).ok_or("IDL account too short")?;
+    let len = u32::from_le_bytes(take(&mut reader).ok_or("IDL account too short")?) as usize;
+    let compressed = reader.get(..len).ok_or("Truncated IDL account")?;
+    let mut idl = String::new();
+    ZlibDecoder::new(compressed)
+        .read_to_string(&mut idl)
+        .map_err(|err| err.to_string())?;
+    serde_json::from_str(&idl).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    const COUNTER: &str = include_str!("../../fixtures/anchor/counter.json");
+    const COUNTER_LEGACY: &str = include_str!("../../fixtures/anchor/counter_legacy.json");
+
+    fn counter_account(discriminator: &[u8]) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(&[7u8; 32]);
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(&(-5i64).to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[1, 2]);
+        data.push(1);
+        data.extend_from_slice(&[0u8; 16]);
+        data
+    }
+
+    #[test]
+    fn decode_account_with_explicit_discriminator() {
+        let idl = AnchorIdl::from_json(&serde_json::from_str(COUNTER).unwrap()).unwrap();
+        assert_eq!(idl.name, "counter");
+        let data = counter_account(&[255, 176, 4, 245, 188, 253, 124, 25]);
+        let (name, value) = idl.decode_account(&data).unwrap();
+        assert_eq!(name, "Counter");
+        assert_eq!(
+            value,
+            json!({
+                "authority": Pubkey::new_from_array([7u8; 32]).to_string(),
+                "count": 42,
+                "lastDelta": -5,
+                "history": [1, 2],
+                "mode": {"Paused": {}},
+            })
+        );
+        assert!(idl.decode_account(&data[..40]).is_none());
+        assert!(idl.decode_account(&[0u8; 64]).is_none());
+    }
+
+    #[test]
+    fn legacy_idl_uses_sighash_discriminators() {
+        let idl = AnchorIdl::from_json(&serde_json::from_str(COUNTER_LEGACY).unwrap()).unwrap();
+        let account_discriminator = &hashv(&[b"account:Counter"]).to_bytes()[..8];
+        let (name, _) = idl
+            .decode_account(&counter_account(account_discriminator))
+            .unwrap();
+        assert_eq!(name, "Counter");
+
+        let mut data = hashv(&[b"global:increment_by"]).to_bytes()[..8].to_vec();
+        data.extend_from_slice(&3u64.to_le_bytes());
+        let accounts = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (name, value) = idl.decode_instruction(&data, &accounts).unwrap();
+        assert_eq!(name, "incrementBy");
+        assert_eq!(value["args"], json!({"amount": 3}));
+        assert_eq!(value["accounts"]["counter"], accounts[0].to_string());
+        assert_eq!(value["accounts"]["authority"], accounts[1].to_string());
+    }
+
+    #[test]
+    fn decode_instruction_args() {
+        let idl = AnchorIdl::from_json(&serde_json::from_str(COUNTER).unwrap()).unwrap();
+        let mut data = vec![175, 175, 109, 31, 13, 152, 155, 237];
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"test");
+        data.push(1);
+        data.extend_from_slice(&u128::MAX.to_le_bytes());
+        let (name, value) = idl.decode_instruction(&data, &[]).unwrap();
+        assert_eq!(name, "initialize");
+        assert_eq!(
+            value["args"],
+            json!({"label": "test", "limit": u128::MAX.to_string()})
+        );
+    }
+
+    #[test]
+    fn decode_compressed_idl_account() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(COUNTER.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut data = vec![0u8; IDL_HEADER_LEN];
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        // the account is allocated bigger than the IDL
+        data.extend_from_slice(&[0u8; 64]);
+        let idl = decode_idl_account(&data).unwrap();
+        assert_eq!(idl["metadata"]["name"], "counter");
+    }
+}
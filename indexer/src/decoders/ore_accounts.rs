@@ -0,0 +1,296 @@
+use serde::{Serialize, Serializer};
+use solana_sdk::{pubkey, pubkey::Pubkey};
+
+pub const ORE_PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+// Ore v2 accounts start with an 8 bytes header, the first byte is the discriminator
+const HEADER_LEN: usize = 8;
+const BUS_DISCRIMINATOR: u8 = 100;
+const CONFIG_DISCRIMINATOR: u8 = 101;
+const PROOF_DISCRIMINATOR: u8 = 102;
+const TREASURY_DISCRIMINATOR: u8 = 103;
+const PROOF_SEED: &[u8] = b"proof";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bus {
+    pub id: u64,
+    pub rewards: u64,
+    pub theoretical_rewards: u64,
+    pub top_balance: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    // only present in the early v2 layout
+    #[serde(serialize_with = "serialize_option_pubkey")]
+    pub admin: Option<Pubkey>,
+    pub base_reward_rate: u64,
+    pub last_reset_at: i64,
+    pub min_difficulty: u64,
+    pub top_balance: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Proof {
+    #[serde(serialize_with = "serialize_pubkey")]
+    pub authority: Pubkey,
+    pub balance: u64,
+    #[serde(serialize_with = "serialize_hash")]
+    pub challenge: [u8; 32],
+    #[serde(serialize_with = "serialize_hash")]
+    pub last_hash: [u8; 32],
+    pub last_hash_at: i64,
+    pub last_stake_at: i64,
+    #[serde(serialize_with = "serialize_pubkey")]
+    pub miner: Pubkey,
+    pub total_hashes: u64,
+    pub total_rewards: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Treasury {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum OreAccount {
+    Bus(Bus),
+    Config(Config),
+    Proof(Proof),
+    Treasury(Treasury),
+}
+
+impl OreAccount {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (header, body) = data.split_at_checked(HEADER_LEN)?;
+        let mut reader = Reader(body);
+        let account = match header[0] {
+            BUS_DISCRIMINATOR => OreAccount::Bus(Bus {
+                id: reader.u64()?,
+                rewards: reader.u64()?,
+                theoretical_rewards: reader.u64()?,
+                top_balance: reader.u64()?,
+            }),
+            CONFIG_DISCRIMINATOR => OreAccount::Config(Config {
+                admin: if body.len() >= 64 {
+                    Some(reader.pubkey()?)
+                } else {
+                    None
+                },
+                base_reward_rate: reader.u64()?,
+                last_reset_at: reader.i64()?,
+                min_difficulty: reader.u64()?,
+                top_balance: reader.u64()?,
+            }),
+            PROOF_DISCRIMINATOR => OreAccount::Proof(Proof {
+                authority: reader.pubkey()?,
+                balance: reader.u64()?,
+                challenge: reader.bytes32()?,
+                last_hash: reader.bytes32()?,
+                last_hash_at: reader.i64()?,
+                last_stake_at: reader.i64()?,
+                miner: reader.pubkey()?,
+                total_hashes: reader.u64()?,
+                total_rewards: reader.u64()?,
+            }),
+            TREASURY_DISCRIMINATOR => OreAccount::Treasury(Treasury {}),
+            _ => return None,
+        };
+        Some(account)
+    }
+
+    pub fn account_type(&self) -> &'static str {
+        match self {
+            OreAccount::Bus(_) => "bus",
+            OreAccount::Config(_) => "config",
+            OreAccount::Proof(_) => "proof",
+            OreAccount::Treasury(_) => "treasury",
+        }
+    }
+}
+
+pub fn proof_pda(authority: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[PROOF_SEED, authority.as_ref()], &ORE_PROGRAM_ID).0
+}
+
+// Little endian cursor over the account body
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_at_checked(N)?;
+        self.0 = rest;
+        bytes.try_into().ok()
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take::<8>().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take::<8>().map(i64::from_le_bytes)
+    }
+
+    fn bytes32(&mut self) -> Option<[u8; 32]> {
+        self.take::<32>()
+    }
+
+    fn pubkey(&mut self) -> Option<Pubkey> {
+        self.take::<32>().map(Pubkey::new_from_array)
+    }
+}
+
+fn serialize_pubkey<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&pubkey.to_string())
+}
+
+fn serialize_option_pubkey<S: Serializer>(
+    pubkey: &Option<Pubkey>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match pubkey {
+        Some(pubkey) => serialize_pubkey(pubkey, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_hash<S: Serializer>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bs58::encode(hash).into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUS: &[u8] = include_bytes!("../../fixtures/ore/bus.bin");
+    const CONFIG: &[u8] = include_bytes!("../../fixtures/ore/config.bin");
+    const PROOF: &[u8] = include_bytes!("../../fixtures/ore/proof.bin");
+    const TREASURY: &[u8] = include_bytes!("../../fixtures/ore/treasury.bin");
+
+    #[test]
+    fn decode_bus() {
+        let Some(OreAccount::Bus(bus)) = OreAccount::decode(BUS) else {
+            panic!("not a bus");
+        };
+        assert_eq!(bus.id, 3);
+        assert_eq!(bus.rewards, 1_283_946_511_332);
+        assert_eq!(bus.theoretical_rewards, 1_377_106_425_066);
+        assert_eq!(bus.top_balance, 412_385_221_467_093);
+    }
+
+    #[test]
+    fn decode_config() {
+        let Some(OreAccount::Config(config)) = OreAccount::decode(CONFIG) else {
+            panic!("not a config");
+        };
+        assert_eq!(config.admin, None);
+        assert_eq!(config.base_reward_rate, 2_421);
+        assert_eq!(config.last_reset_at, 1_729_245_302);
+        assert_eq!(config.min_difficulty, 8);
+        assert_eq!(config.top_balance, 412_385_221_467_093);
+    }
+
+    #[test]
+    fn decode_proof() {
+        let Some(OreAccount::Proof(proof)) = OreAccount::decode(PROOF) else {
+            panic!("not a proof");
+        };
+        assert_eq!(
+            proof.authority,
+            pubkey!("DUAgphZJHh6CDtUYq4VD6agYVNGZFYESyXYeUScN1j3")
+        );
+        assert_eq!(
+            proof.miner,
+            pubkey!("DUAgphZJHh6CDtUYq4VD6agYVNGZFYESyXYeUScN1j3")
+        );
+        assert_eq!(proof.balance, 3_517_322_807);
+        assert_eq!(proof.last_hash_at, 1_729_245_361);
+        assert_eq!(proof.last_stake_at, 1_727_104_885);
+        assert_eq!(proof.total_hashes, 91_842);
+        assert_eq!(proof.total_rewards, 5_214_093_368);
+    }
+
+    #[test]
+    fn decode_treasury() {
+        assert_eq!(
+            OreAccount::decode(TREASURY),
+            Some(OreAccount::Treasury(Treasury {}))
+        );
+    }
+
+    #[test]
+    fn reject_truncated_and_unknown() {
+        assert_eq!(OreAccount::decode(&PROOF[..PROOF.len() - 1]), None);
+        assert_eq!(OreAccount::decode(&[7, 0, 0, 0, 0, 0, 0, 0]), None);
+        assert_eq!(OreAccount::decode(&[]), None);
+    }
+
+    // Refreshes the fixtures from mainnet, recording where each one was taken :
+    // ORE_FIXTURES_RPC=<url> cargo test dump_mainnet_fixtures -- --ignored
+    // The decode tests then need the values of the dumped accounts.
+    #[tokio::test]
+    #[ignore]
+    async fn dump_mainnet_fixtures() {
+        use std::time::Duration;
+
+        use solana_account_decoder::UiAccount;
+        use solana_sdk::account::Account;
+
+        use crate::services::LimitedRequestClient;
+
+        let rpc_url = std::env::var("ORE_FIXTURES_RPC").expect("ORE_FIXTURES_RPC is not set");
+        let client = LimitedRequestClient::new(&rpc_url, 10, Duration::from_secs(1));
+        let pda = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, &ORE_PROGRAM_ID).0;
+        let authority = pubkey!("DUAgphZJHh6CDtUYq4VD6agYVNGZFYESyXYeUScN1j3");
+        let accounts = [
+            ("bus.bin", pda(&[b"bus".as_slice(), &[3]])),
+            ("config.bin", pda(&[b"config".as_slice()])),
+            ("proof.bin", proof_pda(&authority)),
+            ("treasury.bin", pda(&[b"treasury".as_slice()])),
+        ];
+        let keys: Vec<String> = accounts.iter().map(|(_, key)| key.to_string()).collect();
+        let resp = client
+            .proxy_request(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "getMultipleAccounts",
+                "params": [keys, {"encoding": "base64", "commitment": "finalized"}],
+            }))
+            .await
+            .unwrap();
+        let slot = resp["result"]["context"]["slot"].as_u64().unwrap();
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/ore");
+        let mut sources = serde_json::Map::new();
+        for ((file, key), value) in accounts
+            .iter()
+            .zip(resp["result"]["value"].as_array().unwrap())
+        {
+            let account = serde_json::from_value::<UiAccount>(value.clone())
+                .unwrap()
+                .decode::<Account>()
+                .unwrap();
+            std::fs::write(format!("{}/{}", dir, file), &account.data).unwrap();
+            sources.insert(
+                file.to_string(),
+                serde_json::json!({"pubkey": key.to_string(), "slot": slot, "synthetic": false}),
+            );
+        }
+        let sources = serde_json::to_string_pretty(&sources).unwrap();
+        std::fs::write(format!("{}/sources.json", dir), sources + "\n").unwrap();
+    }
+
+    #[test]
+    fn proof_json_projection() {
+        let proof = OreAccount::decode(PROOF).unwrap();
+        let json = serde_json::to_value(&proof).unwrap();
+        assert_eq!(proof.account_type(), "proof");
+        assert_eq!(
+            json["authority"],
+            "DUAgphZJHh6CDtUYq4VD6agYVNGZFYESyXYeUScN1j3"
+        );
+        assert_eq!(json["totalRewards"], 5_214_093_368u64);
+    }
+}
@@ -0,0 +1,299 @@
+use serde::Serialize;
+use solana_sdk::{keccak, pubkey::Pubkey};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+
+use crate::services::{IndexedInstruction, IndexedTransaction};
+
+use super::ORE_PROGRAM_ID;
+
+const CLAIM: u8 = 0;
+const CLOSE: u8 = 1;
+const MINE: u8 = 2;
+const OPEN: u8 = 3;
+const RESET: u8 = 4;
+const STAKE: u8 = 5;
+const UPDATE: u8 = 6;
+const UPGRADE: u8 = 7;
+const INITIALIZE: u8 = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum OreInstruction {
+    Claim { amount: u64 },
+    Close,
+    Mine { digest: [u8; 16], nonce: [u8; 8] },
+    Open { bump: u8 },
+    Reset,
+    Stake { amount: u64 },
+    Update,
+    Upgrade { amount: u64 },
+    Initialize,
+}
+
+impl OreInstruction {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (discriminator, args) = data.split_first()?;
+        let ix = match *discriminator {
+            CLAIM => OreInstruction::Claim {
+                amount: u64::from_le_bytes(args.get(..8)?.try_into().ok()?),
+            },
+            CLOSE => OreInstruction::Close,
+            MINE => OreInstruction::Mine {
+                digest: args.get(..16)?.try_into().ok()?,
+                nonce: args.get(16..24)?.try_into().ok()?,
+            },
+            OPEN => OreInstruction::Open {
+                bump: *args.first()?,
+            },
+            RESET => OreInstruction::Reset,
+            STAKE => OreInstruction::Stake {
+                amount: u64::from_le_bytes(args.get(..8)?.try_into().ok()?),
+            },
+            UPDATE => OreInstruction::Update,
+            UPGRADE => OreInstruction::Upgrade {
+                amount: u64::from_le_bytes(args.get(..8)?.try_into().ok()?),
+            },
+            INITIALIZE => OreInstruction::Initialize,
+            _ => return None,
+        };
+        Some(ix)
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OreInstruction::Claim { .. } => "claim",
+            OreInstruction::Close => "close",
+            OreInstruction::Mine { .. } => "mine",
+            OreInstruction::Open { .. } => "open",
+            OreInstruction::Reset => "reset",
+            OreInstruction::Stake { .. } => "stake",
+            OreInstruction::Update => "update",
+            OreInstruction::Upgrade { .. } => "upgrade",
+            OreInstruction::Initialize => "initialize",
+        }
+    }
+
+    // Position of the proof account in the instruction accounts
+    fn proof_position(&self) -> Option<usize> {
+        match self {
+            OreInstruction::Claim { .. } => Some(2),
+            OreInstruction::Close => Some(1),
+            OreInstruction::Mine { .. } => Some(3),
+            OreInstruction::Open { .. } => Some(3),
+            OreInstruction::Stake { .. } => Some(1),
+            OreInstruction::Update => Some(2),
+            _ => None,
+        }
+    }
+
+    pub fn amount(&self) -> Option<u64> {
+        match self {
+            OreInstruction::Claim { amount }
+            | OreInstruction::Stake { amount }
+            | OreInstruction::Upgrade { amount } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    // Leading zero bits of the drillx hash of the submitted solution
+    pub fn difficulty(&self) -> Option<u32> {
+        let OreInstruction::Mine { digest, nonce } = self else {
+            return None;
+        };
+        let hash = keccak::hashv(&[&sorted_digest(digest), nonce]).to_bytes();
+        let mut difficulty = 0;
+        for byte in hash {
+            difficulty += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        Some(difficulty)
+    }
+}
+
+// drillx hashes the digest with its 8 u16 sorted, to make solutions order independent
+fn sorted_digest(digest: &[u8; 16]) -> [u8; 16] {
+    let mut values: Vec<u16> = digest
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    values.sort_unstable();
+    let mut sorted = [0u8; 16];
+    for (chunk, value) in sorted.chunks_exact_mut(2).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    sorted
+}
+
+#[derive(Debug, Clone)]
+pub struct OreEvent<'a> {
+    pub signature: &'a str,
+    pub instruction: &'a IndexedInstruction,
+    pub decoded: OreInstruction,
+}
+
+impl OreEvent<'_> {
+    pub fn signer(&self) -> Option<&Pubkey> {
+        self.instruction.accounts.first()
+    }
+
+    pub fn proof(&self) -> Option<&Pubkey> {
+        self.instruction
+            .accounts
+            .get(self.decoded.proof_position()?)
+    }
+
+    pub fn bus(&self) -> Option<&Pubkey> {
+        match self.decoded {
+            OreInstruction::Mine { .. } => self.instruction.accounts.get(1),
+            _ => None,
+        }
+    }
+}
+
+pub fn ore_events(txs: &[IndexedTransaction]) -> Vec<OreEvent<'_>> {
+    txs.iter()
+        .flat_map(|indexed| {
+            indexed
+                .instructions
+                .iter()
+                .filter(|ix| ix.program_id == ORE_PROGRAM_ID)
+                .filter_map(|ix| {
+                    let decoded = OreInstruction::decode(&ix.data)?;
+                    Some(OreEvent {
+                        signature: &indexed.signature,
+                        instruction: ix,
+                        decoded,
+                    })
+                })
+        })
+        .collect()
+}
+
+pub async fn index_ore_events(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    txs: &[IndexedTransaction],
+) -> Result<(), sqlx::Error> {
+    let events = ore_events(txs);
+    for chunk in events.chunks(500) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO ore_events (signature, slot, ix_index, inner_index, kind, signer, proof, bus, amount, difficulty, data) ",
+        );
+        query_builder.push_values(chunk, |mut b, event| {
+            let ix = event.instruction;
+            b.push_bind(event.signature.to_string())
+                .push_bind(slot as i64)
+                .push_bind(ix.ix_index as i64)
+                .push_bind(ix.inner_index.map(|i| i as i64).unwrap_or(-1))
+                .push_bind(event.decoded.kind())
+                .push_bind(event.signer().map(|s| s.to_string()).unwrap_or_default())
+                .push_bind(event.proof().map(|p| p.to_string()))
+                .push_bind(event.bus().map(|b| b.to_string()))
+                .push_bind(event.decoded.amount().map(|a| a as i64))
+                .push_bind(event.decoded.difficulty().map(|d| d as i64))
+                .push_bind(serde_json::to_string(&event.decoded).unwrap_or_default());
+        });
+        query_builder.build().execute(&mut *conn).await?;
+    }
+
+    let mut mined_proofs: Vec<String> = events
+        .iter()
+        .filter(|event| matches!(event.decoded, OreInstruction::Mine { .. }))
+        .filter_map(|event| event.proof().map(|p| p.to_string()))
+        .collect();
+    mined_proofs.sort();
+    mined_proofs.dedup();
+    for proof in mined_proofs {
+        update_mine_rewards(conn, &proof, slot).await?;
+    }
+    Ok(())
+}
+
+// Reward of a mine is the growth of the proof `totalRewards` over the slot.
+// Both the event and the proof versions can land first, this runs after each.
+// When several mines hit the same proof in one slot the split is unknown, they stay NULL.
+pub async fn update_mine_rewards(
+    conn: &mut SqliteConnection,
+    proof: &str,
+    slot: u64,
+) -> Result<(), sqlx::Error> {
+    let slot = slot as i64;
+    sqlx::query!(
+        "UPDATE ore_events SET reward = (
+            SELECT json_extract(after.data, '$.totalRewards') - json_extract(before.data, '$.totalRewards')
+            FROM decoded_accounts after, decoded_accounts before
+            WHERE after.id = ?1 AND after.slot = ?2
+            AND before.id = ?1 AND before.slot = (
+                SELECT max(slot) FROM decoded_accounts WHERE id = ?1 AND slot < ?2
+            )
+        )
+        WHERE proof = ?1 AND slot = ?2 AND kind = 'mine'
+        AND (SELECT count(*) FROM ore_events WHERE proof = ?1 AND slot = ?2 AND kind = 'mine') = 1",
+        proof,
+        slot,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_mine() {
+        let mut data = vec![MINE];
+        data.extend_from_slice(&[1u8; 16]);
+        data.extend_from_slice(&[2u8; 8]);
+        let ix = OreInstruction::decode(&data).unwrap();
+        assert_eq!(
+            ix,
+            OreInstruction::Mine {
+                digest: [1u8; 16],
+                nonce: [2u8; 8]
+            }
+        );
+        assert_eq!(ix.kind(), "mine");
+        assert!(OreInstruction::decode(&data[..20]).is_none());
+    }
+
+    #[test]
+    fn decode_amounts() {
+        let mut data = vec![CLAIM];
+        data.extend_from_slice(&42u64.to_le_bytes());
+        assert_eq!(OreInstruction::decode(&data).unwrap().amount(), Some(42));
+        data[0] = STAKE;
+        assert_eq!(
+            OreInstruction::decode(&data),
+            Some(OreInstruction::Stake { amount: 42 })
+        );
+        assert_eq!(OreInstruction::decode(&[42]), None);
+    }
+
+    #[test]
+    fn sorted_digest_orders_u16() {
+        let digest = [3, 0, 1, 0, 2, 0, 0, 1, 0, 0, 5, 0, 4, 0, 6, 0];
+        assert_eq!(
+            sorted_digest(&digest),
+            [0, 0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn difficulty_counts_leading_zero_bits() {
+        let ix = OreInstruction::Mine {
+            digest: [0; 16],
+            nonce: [0; 8],
+        };
+        let hash = keccak::hashv(&[&[0u8; 16], &[0u8; 8]]).to_bytes();
+        let expected = hash
+            .iter()
+            .position(|b| *b != 0)
+            .map(|i| i as u32 * 8 + hash[i].leading_zeros())
+            .unwrap();
+        assert_eq!(ix.difficulty(), Some(expected));
+    }
+}
@@ -0,0 +1,28 @@
+use axum::{extract::Query, Extension, Json};
+use http::StatusCode;
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+use crate::services::{self, DiffError, DiffRequest};
+
+// GET /diff?address=..&fromSlot=..&toSlot=.. or /diff?signature=..
+pub async fn state_diff(
+    Extension(pool): Extension<SqlitePool>,
+    Query(request): Query<DiffRequest>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    match services::state_diff(&pool, &request).await {
+        Ok(diff) => serde_json::to_value(diff).map(Json).map_err(|err| {
+            tracing::error!("[!] Failed to serialize diff : {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Serialization error".into(),
+            )
+        }),
+        Err(DiffError::BadRequest(message)) => Err((StatusCode::BAD_REQUEST, message)),
+        Err(DiffError::NotFound(message)) => Err((StatusCode::NOT_FOUND, message)),
+        Err(DiffError::Database(err)) => {
+            tracing::error!("[!] Diff query failed : {}", err);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error".into()))
+        }
+    }
+}
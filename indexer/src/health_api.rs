@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use axum::{Extension, Json};
+use http::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+
+use crate::services::{
+    IngestState, StreamState, Supervisor, TaskState, TaskStatus, MAX_SLOTS_BEHIND,
+};
+
+// A stream without updates for this long is considered stuck
+const STALL_TIMEOUT: Duration = Duration::from_secs(120);
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StreamReport {
+    connected: bool,
+    last_slot: Option<u64>,
+    seconds_since_update: Option<u64>,
+    stalled: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IngestReport {
+    database: bool,
+    upstream_reachable: bool,
+    upstream_slot: Option<u64>,
+    slots_behind: Option<u64>,
+    streams: BTreeMap<String, StreamReport>,
+    tasks: BTreeMap<String, TaskStatus>,
+}
+
+impl IngestReport {
+    fn lagging(&self) -> bool {
+        self.slots_behind
+            .is_some_and(|behind| behind > MAX_SLOTS_BEHIND)
+    }
+
+    // Restarting helps : a stream is stuck, ingestion fell behind or the database is gone.
+    // Streams get `STALL_TIMEOUT` after startup to show up.
+    fn healthy(&self, uptime: Duration) -> bool {
+        let started = !self.streams.is_empty() || uptime < STALL_TIMEOUT;
+        self.database
+            && started
+            && !self.lagging()
+            && self.streams.values().all(|stream| !stream.stalled)
+    }
+
+    // Serving is worth it : every stream is connected and caught up with upstream, and no
+    // task is waiting to be restarted
+    fn ready(&self) -> bool {
+        self.database
+            && self.upstream_reachable
+            && self.slots_behind.is_some()
+            && !self.lagging()
+            && !self.streams.is_empty()
+            && self
+                .streams
+                .values()
+                .all(|stream| stream.connected && stream.last_slot.is_some() && !stream.stalled)
+            && self
+                .tasks
+                .values()
+                .all(|task| matches!(task.state, TaskState::Running | TaskState::Finished))
+    }
+}
+
+fn stream_report(stream: &StreamState, started: Instant, now: Instant) -> StreamReport {
+    let since_update = now.saturating_duration_since(stream.last_update.unwrap_or(started));
+    StreamReport {
+        connected: stream.connected,
+        last_slot: stream.last_slot,
+        seconds_since_update: stream
+            .last_update
+            .map(|last| now.saturating_duration_since(last).as_secs()),
+        stalled: since_update > STALL_TIMEOUT,
+    }
+}
+
+async fn ingest_report(
+    pool: &SqlitePool,
+    state: &IngestState,
+    supervisor: &Supervisor,
+) -> IngestReport {
+    let database = tokio::time::timeout(DATABASE_TIMEOUT, sqlx::query("SELECT 1").execute(pool))
+        .await
+        .is_ok_and(|res| res.is_ok());
+    let now = Instant::now();
+    IngestReport {
+        database,
+        upstream_reachable: state.upstream_reachable(),
+        upstream_slot: state.upstream_slot(),
+        slots_behind: state.slots_behind(),
+        streams: state
+            .streams()
+            .iter()
+            .map(|(name, stream)| (name.clone(), stream_report(stream, state.started(), now)))
+            .collect(),
+        tasks: supervisor.tasks(),
+    }
+}
+
+fn response(ok: bool, report: IngestReport) -> (StatusCode, Json<Value>) {
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let mut body = json!(report);
+    body["status"] = json!(if ok { "ok" } else { "unavailable" });
+    (status, Json(body))
+}
+
+// Liveness, for the orchestrator to restart a stuck indexer
+pub async fn health(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(state): Extension<IngestState>,
+    Extension(supervisor): Extension<Supervisor>,
+) -> (StatusCode, Json<Value>) {
+    let report = ingest_report(&pool, &state, &supervisor).await;
+    response(report.healthy(state.started().elapsed()), report)
+}
+
+pub async fn ready(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(state): Extension<IngestState>,
+    Extension(supervisor): Extension<Supervisor>,
+) -> (StatusCode, Json<Value>) {
+    let report = ingest_report(&pool, &state, &supervisor).await;
+    response(report.ready(), report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(streams: Vec<(&str, StreamReport)>) -> IngestReport {
+        IngestReport {
+            database: true,
+            upstream_reachable: true,
+            upstream_slot: Some(1_000),
+            slots_behind: Some(2),
+            streams: streams
+                .into_iter()
+                .map(|(name, stream)| (name.to_string(), stream))
+                .collect(),
+            tasks: BTreeMap::new(),
+        }
+    }
+
+    fn stream(connected: bool, last_slot: Option<u64>, stalled: bool) -> StreamReport {
+        StreamReport {
+            connected,
+            last_slot,
+            seconds_since_update: None,
+            stalled,
+        }
+    }
+
+    #[test]
+    fn stalls_after_timeout() {
+        let started = Instant::now();
+        let now = started + STALL_TIMEOUT + Duration::from_secs(1);
+        let never_updated = StreamState::default();
+        assert!(stream_report(&never_updated, started, now).stalled);
+        assert!(!stream_report(&never_updated, started, started).stalled);
+
+        let updated = StreamState {
+            connected: true,
+            last_slot: Some(10),
+            last_update: Some(now - Duration::from_secs(5)),
+        };
+        let updated = stream_report(&updated, started, now);
+        assert!(!updated.stalled);
+        assert_eq!(updated.seconds_since_update, Some(5));
+    }
+
+    #[test]
+    fn health_and_readiness() {
+        let uptime = STALL_TIMEOUT * 2;
+        let up = report(vec![("blocks", stream(true, Some(998), false))]);
+        assert!(up.healthy(uptime) && up.ready());
+
+        // Reconnecting, not stuck yet
+        let reconnecting = report(vec![("blocks", stream(false, Some(998), false))]);
+        assert!(reconnecting.healthy(uptime) && !reconnecting.ready());
+
+        let stuck = report(vec![("blocks", stream(false, Some(998), true))]);
+        assert!(!stuck.healthy(uptime) && !stuck.ready());
+
+        let mut lagging = report(vec![("blocks", stream(true, Some(998), false))]);
+        lagging.slots_behind = Some(MAX_SLOTS_BEHIND + 1);
+        assert!(!lagging.healthy(uptime) && !lagging.ready());
+
+        let mut upstream_down = report(vec![("blocks", stream(true, Some(998), false))]);
+        upstream_down.upstream_reachable = false;
+        assert!(upstream_down.healthy(uptime) && !upstream_down.ready());
+
+        let mut restarting = report(vec![("blocks", stream(true, Some(998), false))]);
+        let task = |state| TaskStatus {
+            state,
+            restarts: 1,
+            last_error: Some("database error".to_string()),
+        };
+        restarting
+            .tasks
+            .insert("indexer".to_string(), task(TaskState::Restarting));
+        assert!(restarting.healthy(uptime) && !restarting.ready());
+        restarting
+            .tasks
+            .insert("indexer".to_string(), task(TaskState::Running));
+        assert!(restarting.ready());
+
+        let starting = report(vec![]);
+        assert!(starting.healthy(Duration::from_secs(1)) && !starting.ready());
+        assert!(!starting.healthy(uptime));
+    }
+}
@@ -0,0 +1,76 @@
+use std::str::FromStr;
+
+use axum::{extract::Path, Extension, Json};
+use http::StatusCode;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::SqlitePool;
+
+use crate::{decoders, services::LimitedRequestClient};
+
+fn parse_program_id(program_id: &str) -> Result<Pubkey, StatusCode> {
+    Pubkey::from_str(program_id).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+pub async fn get_idl(
+    Extension(pool): Extension<SqlitePool>,
+    Path(program_id): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let program_id = parse_program_id(&program_id)?.to_string();
+    let row = sqlx::query!(
+        r#"SELECT source, idl AS "idl: String", updated_at FROM idls WHERE program_id = ?"#,
+        program_id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|err| {
+        tracing::error!("[!] Failed to read IDL of {} : {}", program_id, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let idl: Value =
+        serde_json::from_str(&row.idl).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({
+        "programId": program_id,
+        "source": row.source,
+        "updatedAt": row.updated_at,
+        "idl": idl,
+    })))
+}
+
+pub async fn register_idl(
+    Extension(pool): Extension<SqlitePool>,
+    Path(program_id): Path<String>,
+    Json(idl): Json<Value>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let program_id =
+        parse_program_id(&program_id).map_err(|code| (code, "Invalid program ID".into()))?;
+    let parsed = decoders::store_idl(&pool, &program_id, "registered", &idl)
+        .await
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    tracing::info!("Registered IDL {} for {}", parsed.name, program_id);
+    Ok(Json(
+        json!({"programId": program_id.to_string(), "source": "registered", "name": parsed.name}),
+    ))
+}
+
+// Reads the IDL account `anchor idl init` created for the program
+pub async fn fetch_idl(
+    Extension(client): Extension<LimitedRequestClient>,
+    Extension(pool): Extension<SqlitePool>,
+    Path(program_id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let program_id =
+        parse_program_id(&program_id).map_err(|code| (code, "Invalid program ID".into()))?;
+    let idl = decoders::fetch_onchain_idl(&client, &program_id)
+        .await
+        .map_err(|err| (StatusCode::BAD_GATEWAY, err))?;
+    let parsed = decoders::store_idl(&pool, &program_id, "onchain", &idl)
+        .await
+        .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err))?;
+    tracing::info!("Fetched IDL {} for {}", parsed.name, program_id);
+    Ok(Json(
+        json!({"programId": program_id.to_string(), "source": "onchain", "name": parsed.name}),
+    ))
+}
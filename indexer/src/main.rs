@@ -1,12 +1,26 @@
+mod balance_api;
+mod decoders;
+mod diff_api;
+mod health_api;
+mod idl_api;
+mod metrics_api;
+mod ore_api;
 mod services;
+mod solana_pubsub_proxy;
 mod solana_rpc_proxy;
 
-use std::time::Duration;
+use std::{future::IntoFuture, str::FromStr, time::Duration};
 
-use axum::{routing::post, Extension, Router};
-use services::LimitedRequestClient;
+use axum::{
+    routing::{get, post},
+    Extension, Router,
+};
+use services::{IngestError, IngestState, LimitedRequestClient, PubsubHub, Supervisor};
+use solana_account_decoder::UiAccountEncoding;
 use solana_program::pubkey::Pubkey;
-use solana_sdk::pubkey;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 pub const SOLANA_RPC: &str =
@@ -15,6 +29,9 @@ pub const SOLANA_ACCOUNT_RPC_WS: &str =
     "wss://mainnet.helius-rpc.com/?api-key=7ea98130-baee-4b31-94e3-20d9da28ddc3";
 pub const SOLANA_BLOCKS_RPC_WS : &str = "wss://divine-frequent-log.solana-mainnet.quiknode.pro/9abcf81e71af059e052f4c8f9636cc7536ade363";
 
+// Past it the process exits without waiting for the rest
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(30);
+
 //pub const PROGRAM_ID : Pubkey = pubkey!("KswapMzo937QtKugWqNPYcqqiN17XWnbjEqjsEfPZM8");
 pub const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
 
@@ -28,25 +45,239 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let db_url = format!("{}.db?mode=rwc", PROGRAM_ID);
-    let db = sqlx::SqlitePool::connect(&db_url)
+    // WAL so the proxy reads don't wait for the ingestion writes
+    let db_options = SqliteConnectOptions::new()
+        .filename(format!("{}.db", PROGRAM_ID))
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Normal)
+        .busy_timeout(Duration::from_secs(5));
+    let db = sqlx::SqlitePool::connect_with(db_options)
         .await
         .expect("Can not create db");
     //sqlx::migrate!("./migrations").run(&db).await.unwrap();
 
-    tokio::task::spawn(services::account_indexer(db.clone(), &PROGRAM_ID));
-    tokio::task::spawn(services::block_tx_indexer(db.clone(), &PROGRAM_ID));
+    let hub = PubsubHub::new(vec![PROGRAM_ID]);
+    let state = IngestState::default();
+    let rpc_client = LimitedRequestClient::new(SOLANA_RPC, 45, Duration::from_secs(1));
+    let shutdown = CancellationToken::new();
+
+    // Full snapshot archive first, then the incremental ones, comma separated. The bootstrap
+    // of the source still reconciles it with upstream, but only writes what changed since.
+    if let Ok(paths) = std::env::var("SNAPSHOT_ARCHIVES") {
+        let archives = paths
+            .split(',')
+            .map(services::SnapshotArchive::from_path)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Invalid snapshot archive");
+        let slot = services::import_snapshot(&db, archives, vec![PROGRAM_ID])
+            .await
+            .expect("Snapshot import failed");
+        tracing::info!("Accounts seeded from the snapshot of slot {}", slot);
+    }
 
-    let rpc_client = LimitedRequestClient::new(45, Duration::from_secs(1));
+    // Trains on the archived accounts, new writes of the program then compress with it
+    if std::env::var("ZSTD_TRAIN_DICTIONARY").is_ok() {
+        match services::train_dictionary(&db, &PROGRAM_ID).await {
+            Ok(dict_id) => tracing::info!("Trained zstd dictionary {}", dict_id),
+            Err(err) => tracing::error!("[!] Failed to train the zstd dictionary : {}", err),
+        }
+    }
+
+    let recorder = match std::env::var("INGEST_RECORD") {
+        Ok(path) => Some(
+            services::Recorder::create(&path)
+                .await
+                .expect("Failed to create recording"),
+        ),
+        Err(_) => None,
+    };
+    let supervisor = Supervisor::default();
+    let indexer = supervisor.spawn("indexer", shutdown.clone(), {
+        let (db, hub, state, shutdown) = (db.clone(), hub.clone(), state.clone(), shutdown.clone());
+        let rpc_client = rpc_client.clone();
+        move || {
+            let recorder = recorder.clone();
+            let resolver = services::LookupTableResolver::new(db.clone(), rpc_client.clone());
+            let (db, hub, state, shutdown, rpc_client) = (
+                db.clone(),
+                hub.clone(),
+                state.clone(),
+                shutdown.clone(),
+                rpc_client.clone(),
+            );
+            async move {
+                let source = ingest_source(&db, recorder, rpc_client).await?;
+                services::indexer(db, source, resolver, hub, state, shutdown).await
+            }
+        }
+    });
+    supervisor.spawn("upstream_slot_tracker", shutdown.clone(), {
+        let (rpc_client, state) = (rpc_client.clone(), state.clone());
+        move || {
+            let tracker = services::upstream_slot_tracker(rpc_client.clone(), state.clone());
+            async move {
+                tracker.await;
+                Ok::<_, IngestError>(())
+            }
+        }
+    });
 
     let app = Router::new()
-        .route("/", post(solana_rpc_proxy::rpx_proxy))
-        .layer(Extension(rpc_client))
-        .with_state(db);
+        .route(
+            "/",
+            post(solana_rpc_proxy::rpx_proxy).get(solana_pubsub_proxy::pubsub_ws),
+        )
+        .route(
+            "/ore/miners/:authority/timeline",
+            get(ore_api::miner_timeline),
+        )
+        .route(
+            "/ore/miners/:authority/rewards",
+            get(ore_api::miner_rewards),
+        )
+        .route(
+            "/ore/miners/:authority/difficulty",
+            get(ore_api::miner_difficulty),
+        )
+        .route("/ore/miners/:authority/stakes", get(ore_api::miner_stakes))
+        .route("/ore/miners/:authority/claims", get(ore_api::miner_claims))
+        .route("/ore/leaderboard", get(ore_api::leaderboard))
+        .route(
+            "/idl/:program_id",
+            get(idl_api::get_idl).put(idl_api::register_idl),
+        )
+        .route("/idl/:program_id/fetch", post(idl_api::fetch_idl))
+        .route("/diff", get(diff_api::state_diff))
+        .route("/balances/:address", get(balance_api::balance_changes))
+        .route("/metrics", get(metrics_api::metrics))
+        .route("/health", get(health_api::health))
+        .route("/ready", get(health_api::ready))
+        .layer(Extension(rpc_client.clone()))
+        .layer(Extension(hub))
+        .layer(Extension(state))
+        .layer(Extension(supervisor))
+        .layer(Extension(db.clone()))
+        .layer(Extension(shutdown.clone()))
+        .with_state(db.clone());
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    tokio::task::spawn(shutdown_signal(shutdown.clone()));
+    let server = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+        .into_future();
+    let server = tokio::task::spawn(server);
+
+    shutdown.cancelled().await;
+    // In-flight requests and the events already received are finished first
+    let drained = tokio::time::timeout(SHUTDOWN_DEADLINE, async {
+        let (server, indexer) = tokio::join!(server, indexer);
+        if let Ok(Err(err)) = server {
+            tracing::error!("[!] Server failed : {}", err);
+        }
+        if let Err(err) = indexer {
+            tracing::error!("[!] Indexer supervisor failed : {}", err);
+        }
+        rpc_client.drain().await;
+        db.close().await;
+    })
+    .await;
+    if drained.is_err() {
+        tracing::error!("[!] Shutdown took longer than {:?}", SHUTDOWN_DEADLINE);
+        std::process::exit(1);
+    }
+    tracing::info!("Shut down");
+}
+
+// Ctrl-C or SIGTERM
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
+    shutdown.cancel();
+}
+
+// INGEST_REPLAY replays a recorded file (at INGEST_REPLAY_SPEED times the recorded pace if set),
+// INGEST_SOCKET reads the socket of the geyser plugin of a local validator, GEYSER_ENDPOINT
+// streams from Yellowstone gRPC, the RPC websockets otherwise, recorded to
+// INGEST_RECORD if set, subscribed as `account_subscription` reads it and backfilling the
+// blocks after the cursor of the last run
+async fn ingest_source(
+    db: &sqlx::SqlitePool,
+    recorder: Option<services::Recorder>,
+    client: LimitedRequestClient,
+) -> Result<Box<dyn services::IngestSource>, IngestError> {
+    if let Ok(path) = std::env::var("INGEST_REPLAY") {
+        let mut source = services::FileReplaySource::new(path);
+        source.speed = match std::env::var("INGEST_REPLAY_SPEED") {
+            Ok(speed) => Some(speed.parse().map_err(|_| {
+                IngestError::Source(format!("Invalid INGEST_REPLAY_SPEED {}", speed))
+            })?),
+            Err(_) => None,
+        };
+        return Ok(Box::new(source));
+    }
+    #[cfg(unix)]
+    if let Ok(path) = std::env::var("INGEST_SOCKET") {
+        return Ok(Box::new(services::SocketSource::new(
+            path,
+            vec![PROGRAM_ID],
+        )));
+    }
+    #[cfg(feature = "geyser")]
+    if let Ok(endpoint) = std::env::var("GEYSER_ENDPOINT") {
+        let mut source = services::GeyserSource::new(endpoint, vec![PROGRAM_ID]);
+        source.x_token = std::env::var("GEYSER_X_TOKEN").ok();
+        source.load_tracked(db).await?;
+        return Ok(Box::new(source));
+    }
+    let mut source = services::WebsocketSource::new(PROGRAM_ID, client);
+    source.accounts = account_subscription()?;
+    source.block_cursor = services::load_cursors(db)
+        .await?
+        .into_iter()
+        .find(|(stream, _)| stream == services::BLOCK_STREAM)
+        .map(|(_, slot)| slot);
+    source.recorder = recorder;
+    Ok(Box::new(source))
+}
+
+// ACCOUNT_FILTERS is a JSON array of getProgramAccounts filters, ACCOUNT_ENCODING base64 or
+// base64+zstd, ACCOUNT_COMMITMENT processed, confirmed or finalized
+fn account_subscription() -> Result<services::AccountSubscription, IngestError> {
+    let invalid =
+        |name: &str, value: &str| IngestError::Source(format!("Invalid {} {}", name, value));
+    let mut accounts = services::AccountSubscription::default();
+    if let Ok(filters) = std::env::var("ACCOUNT_FILTERS") {
+        accounts.filters =
+            Some(serde_json::from_str(&filters).map_err(|_| invalid("ACCOUNT_FILTERS", &filters))?);
+    }
+    if let Ok(encoding) = std::env::var("ACCOUNT_ENCODING") {
+        accounts.encoding = match serde_json::from_value(serde_json::json!(encoding)) {
+            Ok(encoding @ (UiAccountEncoding::Base64 | UiAccountEncoding::Base64Zstd)) => encoding,
+            _ => return Err(invalid("ACCOUNT_ENCODING", &encoding)),
+        };
+    }
+    if let Ok(commitment) = std::env::var("ACCOUNT_COMMITMENT") {
+        accounts.commitment = Some(
+            CommitmentConfig::from_str(&commitment)
+                .map_err(|_| invalid("ACCOUNT_COMMITMENT", &commitment))?,
+        );
+    }
+    Ok(accounts)
 }
@@ -0,0 +1,12 @@
+use axum::{response::IntoResponse, Extension};
+use http::header::CONTENT_TYPE;
+
+use crate::services::{IngestState, METRICS};
+
+// Prometheus text exposition format
+pub async fn metrics(Extension(state): Extension<IngestState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(&state),
+    )
+}
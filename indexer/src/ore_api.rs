@@ -0,0 +1,353 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::SqlitePool;
+
+use crate::decoders::proof_pda;
+
+const DEFAULT_LIMIT: i64 = 1000;
+const MAX_LIMIT: i64 = 10_000;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn seconds(self) -> i64 {
+        match self {
+            Bucket::Hour => 3600,
+            Bucket::Day => 86400,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaderboardSort {
+    #[default]
+    Rewards,
+    Mines,
+    Difficulty,
+}
+
+impl LeaderboardSort {
+    fn as_str(self) -> &'static str {
+        match self {
+            LeaderboardSort::Rewards => "rewards",
+            LeaderboardSort::Mines => "mines",
+            LeaderboardSort::Difficulty => "difficulty",
+        }
+    }
+}
+
+// Shared by every endpoint, `from` and `to` are inclusive unix timestamps of the block
+#[derive(Deserialize, Debug, Default)]
+pub struct AnalyticsQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+    bucket: Option<Bucket>,
+    sort: Option<LeaderboardSort>,
+}
+
+impl AnalyticsQuery {
+    fn bounds(&self) -> (i64, i64, i64) {
+        (
+            self.from.unwrap_or(0),
+            self.to.unwrap_or(i64::MAX),
+            self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+        )
+    }
+}
+
+fn parse_authority(authority: &str) -> Result<(String, String), StatusCode> {
+    let authority = Pubkey::from_str(authority).map_err(|_| StatusCode::BAD_REQUEST)?;
+    Ok((authority.to_string(), proof_pda(&authority).to_string()))
+}
+
+fn db_error(err: sqlx::Error) -> StatusCode {
+    tracing::error!("[!] Ore api query failed : {}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+pub async fn miner_timeline(
+    Extension(pool): Extension<SqlitePool>,
+    Path(authority): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (authority, proof) = parse_authority(&authority)?;
+    let (from, to, limit) = query.bounds();
+    let rows = sqlx::query!(
+        r#"SELECT e.signature, e.slot, t.block_time, e.bus, e.difficulty, e.reward
+        FROM ore_events e JOIN transactions t ON t.signature = e.signature
+        WHERE e.proof = ?1 AND e.kind = 'mine' AND t.block_time BETWEEN ?2 AND ?3
+        ORDER BY e.slot DESC LIMIT ?4"#,
+        proof,
+        from,
+        to,
+        limit,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let points: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            json!({
+                "signature": row.signature,
+                "slot": row.slot,
+                "blockTime": row.block_time,
+                "bus": row.bus,
+                "difficulty": row.difficulty,
+                "reward": row.reward,
+            })
+        })
+        .collect();
+    Ok(Json(
+        json!({"authority": authority, "proof": proof, "points": points}),
+    ))
+}
+
+pub async fn miner_rewards(
+    Extension(pool): Extension<SqlitePool>,
+    Path(authority): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (authority, proof) = parse_authority(&authority)?;
+    let (from, to, limit) = query.bounds();
+    let bucket = query.bucket.unwrap_or_default().seconds();
+    let rows = sqlx::query!(
+        r#"SELECT (t.block_time / ?5) * ?5 AS "bucket!: i64",
+            sum(e.reward) AS "rewards: i64",
+            count(*) AS "mines!: i64",
+            avg(e.difficulty) AS "avg_difficulty: f64"
+        FROM ore_events e JOIN transactions t ON t.signature = e.signature
+        WHERE e.proof = ?1 AND e.kind = 'mine' AND t.block_time BETWEEN ?2 AND ?3
+        GROUP BY 1 ORDER BY 1 LIMIT ?4"#,
+        proof,
+        from,
+        to,
+        limit,
+        bucket,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let points: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            json!({
+                "time": row.bucket,
+                "rewards": row.rewards.unwrap_or_default(),
+                "mines": row.mines,
+                "avgDifficulty": row.avg_difficulty,
+            })
+        })
+        .collect();
+    Ok(Json(json!({
+        "authority": authority,
+        "proof": proof,
+        "bucketSeconds": bucket,
+        "points": points,
+    })))
+}
+
+pub async fn miner_difficulty(
+    Extension(pool): Extension<SqlitePool>,
+    Path(authority): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (authority, proof) = parse_authority(&authority)?;
+    let (from, to, _) = query.bounds();
+    let rows = sqlx::query!(
+        r#"SELECT e.difficulty AS "difficulty!: i64", count(*) AS "count!: i64"
+        FROM ore_events e JOIN transactions t ON t.signature = e.signature
+        WHERE e.proof = ?1 AND e.kind = 'mine' AND e.difficulty IS NOT NULL
+        AND t.block_time BETWEEN ?2 AND ?3
+        GROUP BY e.difficulty ORDER BY e.difficulty"#,
+        proof,
+        from,
+        to,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let points: Vec<Value> = rows
+        .into_iter()
+        .map(|row| json!({"difficulty": row.difficulty, "count": row.count}))
+        .collect();
+    Ok(Json(
+        json!({"authority": authority, "proof": proof, "points": points}),
+    ))
+}
+
+pub async fn miner_stakes(
+    Extension(pool): Extension<SqlitePool>,
+    Path(authority): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (authority, proof) = parse_authority(&authority)?;
+    let (from, to, limit) = query.bounds();
+    let stakes = sqlx::query!(
+        r#"SELECT e.signature, e.slot, t.block_time, e.amount
+        FROM ore_events e JOIN transactions t ON t.signature = e.signature
+        WHERE e.proof = ?1 AND e.kind = 'stake' AND t.block_time BETWEEN ?2 AND ?3
+        ORDER BY e.slot DESC LIMIT ?4"#,
+        proof,
+        from,
+        to,
+        limit,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    // the proof balance is the staked amount, its history covers every way it moves.
+    // Versions have no block time, the bounds go through the slots of the transactions.
+    let balances = sqlx::query!(
+        r#"SELECT slot,
+            json_extract(data, '$.balance') AS "balance: i64",
+            json_extract(data, '$.lastStakeAt') AS "last_stake_at: i64"
+        FROM decoded_accounts WHERE id = ?1
+            AND (?2 IS NULL OR slot >= (SELECT min(slot) FROM transactions WHERE block_time >= ?2))
+            AND (?3 IS NULL OR slot <= (SELECT max(slot) FROM transactions WHERE block_time <= ?3))
+        ORDER BY slot DESC LIMIT ?4"#,
+        proof,
+        query.from,
+        query.to,
+        limit,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let stakes: Vec<Value> = stakes
+        .into_iter()
+        .map(|row| {
+            json!({
+                "signature": row.signature,
+                "slot": row.slot,
+                "blockTime": row.block_time,
+                "amount": row.amount,
+            })
+        })
+        .collect();
+    let balances: Vec<Value> = balances
+        .into_iter()
+        .map(|row| {
+            json!({
+                "slot": row.slot,
+                "balance": row.balance,
+                "lastStakeAt": row.last_stake_at,
+            })
+        })
+        .collect();
+    Ok(Json(json!({
+        "authority": authority,
+        "proof": proof,
+        "stakes": stakes,
+        "balances": balances,
+    })))
+}
+
+pub async fn miner_claims(
+    Extension(pool): Extension<SqlitePool>,
+    Path(authority): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (authority, proof) = parse_authority(&authority)?;
+    let (from, to, limit) = query.bounds();
+    let rows = sqlx::query!(
+        r#"SELECT e.signature, e.slot, t.block_time, e.amount
+        FROM ore_events e JOIN transactions t ON t.signature = e.signature
+        WHERE e.proof = ?1 AND e.kind = 'claim' AND t.block_time BETWEEN ?2 AND ?3
+        ORDER BY e.slot DESC LIMIT ?4"#,
+        proof,
+        from,
+        to,
+        limit,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let points: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            json!({
+                "signature": row.signature,
+                "slot": row.slot,
+                "blockTime": row.block_time,
+                "amount": row.amount,
+            })
+        })
+        .collect();
+    Ok(Json(
+        json!({"authority": authority, "proof": proof, "points": points}),
+    ))
+}
+
+pub async fn leaderboard(
+    Extension(pool): Extension<SqlitePool>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (from, to, limit) = query.bounds();
+    let sort = query.sort.unwrap_or_default().as_str();
+    let rows = sqlx::query!(
+        r#"SELECT e.proof AS "proof!",
+            (SELECT json_extract(a.data, '$.authority') FROM decoded_accounts a
+                WHERE a.id = e.proof ORDER BY a.slot DESC LIMIT 1) AS "authority: String",
+            sum(e.reward) AS "rewards: i64",
+            count(*) AS "mines!: i64",
+            max(e.difficulty) AS "best_difficulty: i64",
+            avg(e.difficulty) AS "avg_difficulty: f64"
+        FROM ore_events e JOIN transactions t ON t.signature = e.signature
+        WHERE e.kind = 'mine' AND e.proof IS NOT NULL AND t.block_time BETWEEN ?1 AND ?2
+        GROUP BY e.proof
+        ORDER BY CASE ?4
+            WHEN 'mines' THEN count(*)
+            WHEN 'difficulty' THEN max(e.difficulty)
+            ELSE sum(e.reward) END DESC
+        LIMIT ?3"#,
+        from,
+        to,
+        limit,
+        sort,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let miners: Vec<Value> = rows
+        .into_iter()
+        .enumerate()
+        .map(|(rank, row)| {
+            json!({
+                "rank": rank + 1,
+                "proof": row.proof,
+                "authority": row.authority,
+                "rewards": row.rewards.unwrap_or_default(),
+                "mines": row.mines,
+                "bestDifficulty": row.best_difficulty,
+                "avgDifficulty": row.avg_difficulty,
+            })
+        })
+        .collect();
+    Ok(Json(
+        json!({"from": from, "to": to, "sort": sort, "miners": miners}),
+    ))
+}
@@ -1,7 +1,39 @@
+mod account_diff;
 mod account_indexer;
+mod address_lookup;
+mod balance_ledger;
+mod batch_writer;
 mod block_tx_indexer;
+mod data_codec;
+mod indexer;
+mod ingest_error;
+mod ingest_source;
+mod ingest_state;
+mod instruction_index;
+mod memo;
+mod metrics;
+mod pubsub_hub;
 mod rate_limit_rpc;
+mod snapshot_import;
+mod supervisor;
+mod write_attribution;
 
+pub use account_diff::*;
 pub use account_indexer::*;
+pub use address_lookup::*;
+pub use balance_ledger::*;
+pub use batch_writer::*;
 pub use block_tx_indexer::*;
+pub use data_codec::*;
+pub use indexer::*;
+pub use ingest_error::*;
+pub use ingest_source::*;
+pub use ingest_state::*;
+pub use instruction_index::*;
+pub use memo::*;
+pub use metrics::*;
+pub use pubsub_hub::*;
 pub use rate_limit_rpc::*;
+pub use snapshot_import::*;
+pub use supervisor::*;
+pub use write_attribution::*;
@@ -0,0 +1,480 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::SqlitePool;
+
+use crate::decoders::{self, AnchorIdl, DecodedAccount};
+
+use super::{account_writer, decode_account_data, AccountWrite};
+
+// A program diff stops after this many accounts
+const MAX_DIFF_ACCOUNTS: i64 = 1000;
+
+// Either `address` with the two slots, or a transaction `signature`
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffRequest {
+    pub address: Option<String>,
+    pub from_slot: Option<u64>,
+    pub to_slot: Option<u64>,
+    pub signature: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum DiffError {
+    BadRequest(String),
+    NotFound(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for DiffError {
+    fn from(err: sqlx::Error) -> Self {
+        DiffError::Database(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AccountVersion {
+    slot: u64,
+    data: Vec<u8>,
+    lamports: u64,
+    owner: Pubkey,
+    executable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ByteChange {
+    pub offset: usize,
+    #[serde(serialize_with = "serialize_hex")]
+    pub before: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub after: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub path: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDiff {
+    pub pubkey: String,
+    pub before: Value,
+    pub after: Value,
+    pub bytes: Vec<ByteChange>,
+    pub account_type: Option<String>,
+    // `None` when no decoder knows both versions
+    pub fields: Option<Vec<FieldChange>>,
+    // Last transaction of the `after` slot that wrote the account, it produced the version
+    pub writer: Option<AccountWrite>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    pub from_slot: u64,
+    pub to_slot: u64,
+    pub accounts: Vec<AccountDiff>,
+}
+
+pub async fn state_diff(db: &SqlitePool, request: &DiffRequest) -> Result<StateDiff, DiffError> {
+    match request {
+        DiffRequest {
+            signature: Some(signature),
+            ..
+        } => diff_transaction(db, signature).await,
+        DiffRequest {
+            address: Some(address),
+            from_slot: Some(from_slot),
+            to_slot: Some(to_slot),
+            ..
+        } => {
+            let address = Pubkey::from_str(address)
+                .map_err(|_| DiffError::BadRequest("Invalid address".into()))?;
+            if from_slot > to_slot {
+                return Err(DiffError::BadRequest("fromSlot is after toSlot".into()));
+            }
+            diff_slots(db, &address, *from_slot, *to_slot).await
+        }
+        _ => Err(DiffError::BadRequest(
+            "Expected a signature, or an address with fromSlot and toSlot".into(),
+        )),
+    }
+}
+
+// `address` is an account or a program, for a program every account it owns that
+// changed in (from_slot, to_slot] is compared
+async fn diff_slots(
+    db: &SqlitePool,
+    address: &Pubkey,
+    from_slot: u64,
+    to_slot: u64,
+) -> Result<StateDiff, DiffError> {
+    let address = address.to_string();
+    let (from, to) = (from_slot as i64, to_slot as i64);
+    let ids: Vec<String> = sqlx::query_scalar!(
+        "SELECT DISTINCT id FROM accounts_history
+        WHERE (id = ?1 OR owner = ?1) AND slot > ?2 AND slot <= ?3
+        ORDER BY id LIMIT ?4",
+        address,
+        from,
+        to,
+        MAX_DIFF_ACCOUNTS,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut idls = IdlCache::default();
+    let mut accounts = vec![];
+    for id in ids {
+        let before = version_at(db, &id, from).await?;
+        let after = version_at(db, &id, to).await?;
+        accounts.push(diff_versions(db, &mut idls, id, before, after).await?);
+    }
+    Ok(StateDiff {
+        signature: None,
+        from_slot,
+        to_slot,
+        accounts,
+    })
+}
+
+// Versions are stored per slot, when several transactions of the slot write the same
+// account the diff covers all of them and `writer` is the last one
+async fn diff_transaction(db: &SqlitePool, signature: &str) -> Result<StateDiff, DiffError> {
+    let slot = sqlx::query_scalar!(
+        "SELECT slot FROM transactions WHERE signature = ?",
+        signature
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(DiffError::NotFound(format!(
+        "Transaction {} not indexed",
+        signature
+    )))?;
+    let slot = slot as u64;
+    let ids: Vec<String> = sqlx::query_scalar!(
+        "SELECT account FROM transaction_accounts WHERE signature = ? AND writable ORDER BY position",
+        signature
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut idls = IdlCache::default();
+    let mut accounts = vec![];
+    for id in ids {
+        let Some(after) = version_at(db, &id, slot as i64)
+            .await?
+            .filter(|v| v.slot == slot)
+        else {
+            continue;
+        };
+        let before = match slot.checked_sub(1) {
+            Some(previous) => version_at(db, &id, previous as i64).await?,
+            None => None,
+        };
+        accounts.push(diff_versions(db, &mut idls, id, before, Some(after)).await?);
+    }
+    Ok(StateDiff {
+        signature: Some(signature.to_string()),
+        from_slot: slot.saturating_sub(1),
+        to_slot: slot,
+        accounts,
+    })
+}
+
+async fn version_at(
+    db: &SqlitePool,
+    id: &str,
+    slot: i64,
+) -> Result<Option<AccountVersion>, sqlx::Error> {
+    let mut conn = db.acquire().await?;
+    let row = sqlx::query!(
+        "SELECT slot, data, encoding, base_slot, base_hash, lamports, owner, executable FROM accounts_history
+        WHERE id = ? AND slot <= ? ORDER BY slot DESC LIMIT 1",
+        id,
+        slot,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let Ok(owner) = Pubkey::from_str(&row.owner) else {
+        return Ok(None);
+    };
+    let data = decode_account_data(
+        &mut conn,
+        id,
+        row.encoding,
+        row.base_slot,
+        row.base_hash,
+        row.data,
+    )
+    .await?;
+    Ok(Some(AccountVersion {
+        slot: row.slot as u64,
+        data,
+        lamports: row.lamports as u64,
+        owner,
+        executable: row.executable,
+    }))
+}
+
+#[derive(Default)]
+struct IdlCache(HashMap<Pubkey, Option<AnchorIdl>>);
+
+impl IdlCache {
+    async fn get(
+        &mut self,
+        db: &SqlitePool,
+        program_id: &Pubkey,
+    ) -> Result<Option<&AnchorIdl>, sqlx::Error> {
+        if !self.0.contains_key(program_id) {
+            let idl = decoders::load_decoder_idl(db, program_id).await?;
+            self.0.insert(*program_id, idl);
+        }
+        Ok(self.0[program_id].as_ref())
+    }
+}
+
+async fn diff_versions(
+    db: &SqlitePool,
+    idls: &mut IdlCache,
+    pubkey: String,
+    before: Option<AccountVersion>,
+    after: Option<AccountVersion>,
+) -> Result<AccountDiff, sqlx::Error> {
+    let bytes = byte_diff(
+        before
+            .as_ref()
+            .map(|v| v.data.as_slice())
+            .unwrap_or_default(),
+        after
+            .as_ref()
+            .map(|v| v.data.as_slice())
+            .unwrap_or_default(),
+    );
+
+    let decoded_before = decode_version(db, idls, &before).await?;
+    let decoded_after = decode_version(db, idls, &after).await?;
+    let account_type = decoded_after
+        .as_ref()
+        .or(decoded_before.as_ref())
+        .map(|decoded| decoded.account_type.clone());
+    let fields = match (&decoded_before, &decoded_after) {
+        (Some(before), Some(after)) => Some(field_diff(&before.data, &after.data)),
+        _ => None,
+    };
+    let writer = match &after {
+        Some(after) => account_writer(db, &pubkey, after.slot).await?,
+        None => None,
+    };
+
+    Ok(AccountDiff {
+        pubkey,
+        before: summary(&before),
+        after: summary(&after),
+        bytes,
+        account_type,
+        fields,
+        writer,
+    })
+}
+
+async fn decode_version(
+    db: &SqlitePool,
+    idls: &mut IdlCache,
+    version: &Option<AccountVersion>,
+) -> Result<Option<DecodedAccount>, sqlx::Error> {
+    let Some(version) = version else {
+        return Ok(None);
+    };
+    let idl = idls.get(db, &version.owner).await?;
+    Ok(decoders::decode_account(&version.owner, idl, &version.data))
+}
+
+fn summary(version: &Option<AccountVersion>) -> Value {
+    match version {
+        Some(version) => json!({
+            "slot": version.slot,
+            "lamports": version.lamports,
+            "owner": version.owner.to_string(),
+            "executable": version.executable,
+            "space": version.data.len(),
+        }),
+        None => Value::Null,
+    }
+}
+
+// Contiguous runs of changed bytes, a resize shows as a run at the end
+pub fn byte_diff(before: &[u8], after: &[u8]) -> Vec<ByteChange> {
+    let len = before.len().max(after.len());
+    let mut changes = vec![];
+    let mut start = None;
+    for i in 0..=len {
+        let differs = i < len && before.get(i) != after.get(i);
+        match (differs, start) {
+            (true, None) => start = Some(i),
+            (false, Some(offset)) => {
+                changes.push(ByteChange {
+                    offset,
+                    before: range(before, offset, i),
+                    after: range(after, offset, i),
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+fn range(data: &[u8], start: usize, end: usize) -> Vec<u8> {
+    data[start.min(data.len())..end.min(data.len())].to_vec()
+}
+
+// Leaf level changes between two decoded accounts, paths look like `a.b[2]`
+pub fn field_diff(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let mut changes = vec![];
+    diff_values("", before, after, &mut changes);
+    changes
+}
+
+fn diff_values(path: &str, before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    match (before, after) {
+        (Value::Object(b), Value::Object(a)) => {
+            let keys: BTreeSet<&String> = b.keys().chain(a.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                let before = b.get(key).unwrap_or(&Value::Null);
+                let after = a.get(key).unwrap_or(&Value::Null);
+                diff_values(&path, before, after, changes);
+            }
+        }
+        (Value::Array(b), Value::Array(a)) if b.len() == a.len() => {
+            for (i, (before, after)) in b.iter().zip(a).enumerate() {
+                diff_values(&format!("{}[{}]", path, i), before, after, changes);
+            }
+        }
+        _ if before != after => changes.push(FieldChange {
+            path: path.to_string(),
+            before: before.clone(),
+            after: after.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    serializer.serialize_str(&hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_diff_groups_runs() {
+        let before = [0, 1, 2, 3, 4, 5];
+        let after = [0, 9, 9, 3, 4, 7, 8];
+        assert_eq!(
+            byte_diff(&before, &after),
+            vec![
+                ByteChange {
+                    offset: 1,
+                    before: vec![1, 2],
+                    after: vec![9, 9]
+                },
+                ByteChange {
+                    offset: 5,
+                    before: vec![5],
+                    after: vec![7, 8]
+                },
+            ]
+        );
+        assert!(byte_diff(&before, &before).is_empty());
+        assert_eq!(
+            serde_json::to_value(&byte_diff(&[], &[0xab, 0x01])).unwrap(),
+            json!([{"offset": 0, "before": "", "after": "ab01"}])
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn transaction_at_genesis(db: SqlitePool) {
+        let account = Pubkey::new_unique().to_string();
+        sqlx::query(
+            "INSERT INTO transactions (signature, slot, tx_index) VALUES ('genesis', 0, 0)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO transaction_accounts (signature, position, account, slot, writable)
+            VALUES ('genesis', 0, ?1, 0, true)",
+        )
+        .bind(&account)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO accounts_history (id, slot, data, executable, lamports, owner, rent_epoch)
+            VALUES (?1, 0, x'01', false, 1, ?2, 0)",
+        )
+        .bind(&account)
+        .bind(Pubkey::default().to_string())
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let request = DiffRequest {
+            signature: Some("genesis".into()),
+            ..Default::default()
+        };
+        let diff = state_diff(&db, &request).await.unwrap();
+        assert_eq!((diff.from_slot, diff.to_slot), (0, 0));
+        assert_eq!(diff.accounts.len(), 1);
+        assert_eq!(diff.accounts[0].before, Value::Null);
+    }
+
+    #[test]
+    fn field_diff_paths() {
+        let before = json!({"balance": 1, "nested": {"a": [1, 2], "b": "x"}, "gone": true});
+        let after = json!({"balance": 2, "nested": {"a": [1, 3], "b": "x"}, "new": null});
+        assert_eq!(
+            field_diff(&before, &after),
+            vec![
+                FieldChange {
+                    path: "balance".into(),
+                    before: json!(1),
+                    after: json!(2)
+                },
+                FieldChange {
+                    path: "gone".into(),
+                    before: json!(true),
+                    after: Value::Null
+                },
+                FieldChange {
+                    path: "nested.a[1]".into(),
+                    before: json!(2),
+                    after: json!(3)
+                },
+            ]
+        );
+    }
+}
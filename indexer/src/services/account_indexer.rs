@@ -1,81 +1,281 @@
-use solana_account_decoder::UiAccountEncoding;
-use solana_client::{
-    nonblocking::rpc_client::RpcClient,
-    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
 };
-use solana_sdk::pubkey::Pubkey;
-use sqlx::{QueryBuilder, Sqlite, SqlitePool};
 
-pub async fn account_indexer(db: SqlitePool, program_id: &Pubkey) {
-    let rpc_client = RpcClient::new(crate::SOLANA_RPC.to_string());
-    let slot = rpc_client.get_slot().await.expect("faild to get slot");
-    let config = RpcProgramAccountsConfig {
-        sort_results: None,
-        filters: None,
-        account_config: RpcAccountInfoConfig {
-            encoding: Some(UiAccountEncoding::Base64Zstd),
-            data_slice: None,
-            commitment: None,
-            min_context_slot: None,
-        },
-        with_context: Some(true),
-    };
-    tracing::info!("Fetching accounts");
-    let accounts = rpc_client
-        .get_program_accounts_with_config(program_id, config)
-        .await
-        .expect("Failed to fetch accounts");
-    tracing::info!("Got {} accounts", accounts.len());
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+
+use crate::decoders;
+
+use super::{
+    decode_data, encode_data, encode_delta, link_account_writes, program_dictionary, rebase_deltas,
+    AccountUpdate, EncodedData, METRICS,
+};
+
+// Writes a version of the account, publishing it is left to after the commit
+pub async fn index_account_update(
+    conn: &mut SqliteConnection,
+    update: &AccountUpdate,
+) -> Result<(), sqlx::Error> {
+    let id = update.pubkey.to_string();
+    let slot = update.slot as i64;
+    let lamports = update.account.lamports as i64;
+    let rent_epoch = update.account.rent_epoch as i64;
+    let owner = update.account.owner.to_string();
+    let started = Instant::now();
+    let dictionary = program_dictionary(conn, &update.account.owner).await?;
+    let full = encode_data(&update.account.data, dictionary.as_deref());
+    // History may keep a delta, the archive keeps the standalone version for the proxy reads
+    let version = encode_delta(conn, &id, update.slot, &update.account.data, &full)
+        .await?
+        .unwrap_or_else(|| full.clone());
+    sqlx::query!(
+        "INSERT or REPLACE into accounts_archive (id, slot, data, executable, lamports, owner, rent_epoch, encoding) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        id,
+        slot,
+        full.data,
+        update.account.executable,
+        lamports,
+        owner,
+        rent_epoch,
+        full.encoding,
+    )
+    .execute(&mut *conn)
+    .await?;
+    rebase_deltas(conn, &id, update.slot, dictionary.as_deref()).await?;
+    sqlx::query!(
+        "INSERT or REPLACE into accounts_history (id, slot, data, executable, lamports, owner, rent_epoch, encoding, base_slot, base_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        id,
+        slot,
+        version.data,
+        update.account.executable,
+        lamports,
+        owner,
+        rent_epoch,
+        version.encoding,
+        version.base_slot,
+        version.base_hash,
+    )
+    .execute(&mut *conn)
+    .await?;
+    link_account_writes(conn, update.slot, Some(&id)).await?;
+    decoders::index_decoded_accounts(
+        conn,
+        &update.account.owner,
+        &[(id.clone(), update.slot, update.account.data.as_slice())],
+    )
+    .await?;
+    METRICS.observe_db_write("account", started);
+    METRICS.account_updates.inc();
+    tracing::info!("Updated account {}", id);
+    Ok(())
+}
+
+// Latest version, history and decoded projection of `program_id` accounts at `slot`, the
+// whole set of them : archived ones missing from it were closed or reassigned before `slot`
+pub async fn store_accounts(
+    conn: &mut SqliteConnection,
+    program_id: &Pubkey,
+    slot: u64,
+    accounts: &[(Pubkey, Account)],
+) -> Result<(), sqlx::Error> {
+    let started = Instant::now();
+    let dictionary = program_dictionary(conn, program_id).await?;
     for chunk in accounts.chunks(10_000) {
-        let mut query_builder: QueryBuilder<Sqlite> =
-            QueryBuilder::new("INSERT or REPLACE INTO accounts_archive(id, slot, data) ");
-        query_builder.push_values(chunk, |mut b, (id, account)| {
-            b.push_bind(id.to_string())
-                .push_bind(slot as i32)
-                .push_bind(account.data.clone());
-        });
+        let chunk = changed_accounts(conn, slot, chunk).await?;
+        if chunk.is_empty() {
+            continue;
+        }
+        let encoded: Vec<EncodedData> = chunk
+            .iter()
+            .map(|(_, account)| encode_data(&account.data, dictionary.as_deref()))
+            .collect();
+        for id in delta_bases(conn, slot, &chunk).await? {
+            rebase_deltas(conn, &id, slot, dictionary.as_deref()).await?;
+        }
+        for table in ["accounts_archive", "accounts_history"] {
+            let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+                "INSERT or REPLACE INTO {}(id, slot, data, executable, lamports, owner, rent_epoch, encoding) ",
+                table
+            ));
+            query_builder.push_values(
+                chunk.iter().zip(&encoded),
+                |mut b, ((id, account), encoded)| {
+                    b.push_bind(id.to_string())
+                        .push_bind(slot as i64)
+                        .push_bind(encoded.data.clone())
+                        .push_bind(account.executable)
+                        .push_bind(account.lamports as i64)
+                        .push_bind(account.owner.to_string())
+                        .push_bind(account.rent_epoch as i64)
+                        .push_bind(encoded.encoding);
+                },
+            );
+            query_builder.build().execute(&mut *conn).await?;
+        }
 
-        query_builder
-            .build()
-            .execute(&db)
-            .await
-            .expect("Accouts bulk insert failed");
+        let decoded: Vec<(String, u64, &[u8])> = chunk
+            .iter()
+            .map(|(id, account)| (id.to_string(), slot, account.data.as_slice()))
+            .collect();
+        decoders::index_decoded_accounts(conn, program_id, &decoded).await?;
         tracing::info!("Indexed {} accounts", chunk.len());
     }
 
-    let sub_config = RpcProgramAccountsConfig {
-        sort_results: None,
-        filters: None,
-        account_config: RpcAccountInfoConfig {
-            encoding: Some(UiAccountEncoding::Base64),
-            data_slice: None,
-            commitment: None,
-            min_context_slot: None,
-        },
-        with_context: Some(true),
-    };
-    let (_tx, rx) = solana_client::pubsub_client::PubsubClient::program_subscribe(
-        crate::SOLANA_ACCOUNT_RPC_WS,
-        program_id,
-        Some(sub_config),
+    // Those written after `slot` are newer than the set
+    let owner = program_id.to_string();
+    let slot = slot as i64;
+    let older = sqlx::query_scalar!(
+        "SELECT id FROM accounts_archive WHERE owner = $1 AND slot < $2",
+        owner,
+        slot
     )
-    .unwrap();
+    .fetch_all(&mut *conn)
+    .await?;
+    let stored: HashSet<String> = accounts.iter().map(|(id, _)| id.to_string()).collect();
+    for id in older.iter().filter(|id| !stored.contains(*id)) {
+        sqlx::query!("DELETE FROM accounts_archive WHERE id = $1", id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    METRICS.observe_db_write("snapshot", started);
+    Ok(())
+}
+
+// Accounts whose version at `slot` is the base of later deltas
+async fn delta_bases(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    accounts: &[&(Pubkey, Account)],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut query_builder: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT DISTINCT id FROM accounts_history WHERE base_slot = ");
+    query_builder.push_bind(slot as i64).push(" AND id IN (");
+    let mut ids = query_builder.separated(", ");
+    for (id, _) in accounts {
+        ids.push_bind(id.to_string());
+    }
+    ids.push_unseparated(")");
+    query_builder.build_query_scalar().fetch_all(conn).await
+}
+
+// id, slot, data, lamports, owner and encoding
+type ArchivedRow = (String, i64, Vec<u8>, i64, String, Option<i64>);
+
+// A bootstrap after a reconnect returns every account again, only the ones whose data,
+// lamports or owner differ from the archived version make a new version. One archived
+// after `slot` is newer and kept.
+async fn changed_accounts<'a>(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    accounts: &'a [(Pubkey, Account)],
+) -> Result<Vec<&'a (Pubkey, Account)>, sqlx::Error> {
+    let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "SELECT id, slot, data, lamports, owner, encoding FROM accounts_archive WHERE id IN (",
+    );
+    let mut ids = query_builder.separated(", ");
+    for (id, _) in accounts {
+        ids.push_bind(id.to_string());
+    }
+    ids.push_unseparated(")");
+    let rows: Vec<ArchivedRow> = query_builder.build_query_as().fetch_all(&mut *conn).await?;
+    let mut archived = HashMap::with_capacity(rows.len());
+    for (id, archived_slot, data, lamports, owner, encoding) in rows {
+        let data = decode_data(&mut *conn, encoding, data).await?;
+        archived.insert(id, (archived_slot as u64, data, lamports as u64, owner));
+    }
+    Ok(accounts
+        .iter()
+        .filter(|(id, account)| match archived.get(&id.to_string()) {
+            Some((archived_slot, _, _, _)) if *archived_slot > slot => false,
+            Some((_, data, lamports, owner)) => {
+                *data != account.data
+                    || *lamports != account.lamports
+                    || *owner != account.owner.to_string()
+            }
+            None => true,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn bootstrap_again(db: SqlitePool) {
+        let program_id = Pubkey::new_unique();
+        let account = |data: Vec<u8>| Account {
+            lamports: 1_000,
+            data,
+            owner: program_id,
+            executable: false,
+            rent_epoch: 0,
+        };
+        let (unchanged, changed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (closed, newer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut conn = db.acquire().await.unwrap();
+        store_accounts(
+            &mut conn,
+            &program_id,
+            10,
+            &[
+                (unchanged, account(vec![1; 64])),
+                (changed, account(vec![2; 64])),
+                (closed, account(vec![3; 64])),
+                (newer, account(vec![4; 64])),
+            ],
+        )
+        .await
+        .unwrap();
+        // Streamed after the slot of the next bootstrap
+        let update = AccountUpdate {
+            pubkey: newer,
+            account: account(vec![5; 64]),
+            slot: 25,
+        };
+        index_account_update(&mut conn, &update).await.unwrap();
+        store_accounts(
+            &mut conn,
+            &program_id,
+            20,
+            &[
+                (unchanged, account(vec![1; 64])),
+                (changed, account(vec![6; 64])),
+                (newer, account(vec![4; 64])),
+            ],
+        )
+        .await
+        .unwrap();
 
-    while let Ok(msg) = rx.recv() {
-        let slot = msg.context.slot as i64;
-        if let Some(data) = msg.value.account.data.decode() {
-            sqlx::query!(
-                "INSERT or REPLACE into accounts_archive (id, slot, data) VALUES ($1, $2, $3)",
-                msg.value.pubkey,
-                slot,
-                data,
-            )
-            .execute(&db)
-            .await
-            .expect("Failt to insert account");
-            tracing::info!("Updated account {}", msg.value.pubkey);
-        } else {
-            tracing::error!("Failed to decode account {}", msg.value.pubkey);
+        let mut versions = vec![];
+        for id in [unchanged, changed, closed, newer] {
+            let history: Vec<i64> =
+                sqlx::query_scalar("SELECT slot FROM accounts_history WHERE id = ? ORDER BY slot")
+                    .bind(id.to_string())
+                    .fetch_all(&mut *conn)
+                    .await
+                    .unwrap();
+            let archived: Option<i64> =
+                sqlx::query_scalar("SELECT slot FROM accounts_archive WHERE id = ?")
+                    .bind(id.to_string())
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .unwrap();
+            versions.push((history, archived));
         }
+        assert_eq!(
+            versions,
+            vec![
+                (vec![10], Some(10)),
+                (vec![10, 20], Some(20)),
+                // Closed while disconnected
+                (vec![10], None),
+                (vec![10, 25], Some(25)),
+            ]
+        );
     }
 }
@@ -0,0 +1,458 @@
+use std::collections::BTreeSet;
+
+use serde_json::json;
+use solana_account_decoder::UiAccount;
+use solana_sdk::{
+    account::Account,
+    address_lookup_table::state::AddressLookupTable,
+    message::v0::{LoadedAddresses, MessageAddressTableLookup},
+    pubkey::Pubkey,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status::{option_serializer::OptionSerializer, UiTransactionStatusMeta};
+use sqlx::{SqliteConnection, SqlitePool};
+
+use super::{IndexedTransaction, LimitedRequestClient};
+
+// Resolves the addresses loaded by v0 transactions. The entries the streamed transactions
+// loaded come first, then the lookup table history, fetching and recording new table
+// versions when needed. Tables are append only, so any version holding the referenced
+// indexes resolves them the same way, unless the stream saw the table hold other
+// addresses in between.
+pub struct LookupTableResolver {
+    db: SqlitePool,
+    client: LimitedRequestClient,
+}
+
+impl LookupTableResolver {
+    pub fn new(db: SqlitePool, client: LimitedRequestClient) -> Self {
+        Self { db, client }
+    }
+
+    pub async fn resolve(
+        &self,
+        tx: &VersionedTransaction,
+        meta: Option<&UiTransactionStatusMeta>,
+        slot: u64,
+    ) -> Result<LoadedAddresses, String> {
+        let lookups = match tx.message.address_table_lookups() {
+            Some(lookups) if !lookups.is_empty() => lookups,
+            _ => return Ok(LoadedAddresses::default()),
+        };
+        let from_meta = meta.and_then(loaded_addresses_from_meta);
+
+        let mut loaded = LoadedAddresses::default();
+        for lookup in lookups {
+            let (writable, readonly) = match self.lookup_addresses(lookup, slot).await {
+                Ok(addresses) => addresses,
+                // the table may be closed since, meta still knows the answer
+                Err(err) => {
+                    return from_meta.ok_or(err);
+                }
+            };
+            loaded.writable.extend(writable);
+            loaded.readonly.extend(readonly);
+        }
+
+        if let Some(from_meta) = from_meta {
+            if from_meta != loaded {
+                tracing::error!(
+                    "[!] Lookup table resolution mismatch for {}, using meta",
+                    tx.signatures[0]
+                );
+                return Ok(from_meta);
+            }
+        }
+        Ok(loaded)
+    }
+
+    // The writable and readonly addresses of one lookup
+    async fn lookup_addresses(
+        &self,
+        lookup: &MessageAddressTableLookup,
+        slot: u64,
+    ) -> Result<(Vec<Pubkey>, Vec<Pubkey>), String> {
+        let writable = self
+            .streamed_entries(&lookup.account_key, &lookup.writable_indexes, slot)
+            .await?;
+        let readonly = self
+            .streamed_entries(&lookup.account_key, &lookup.readonly_indexes, slot)
+            .await?;
+        if let (Some(writable), Some(readonly)) = (writable, readonly) {
+            return Ok((writable, readonly));
+        }
+
+        let (table_slot, addresses) = self.table_addresses(lookup, slot).await?;
+        self.check_streamed(&lookup.account_key, slot, table_slot, &addresses)
+            .await?;
+        let pick = |indexes: &[u8]| -> Vec<Pubkey> {
+            indexes
+                .iter()
+                .map(|index| addresses[*index as usize])
+                .collect()
+        };
+        Ok((
+            pick(&lookup.writable_indexes),
+            pick(&lookup.readonly_indexes),
+        ))
+    }
+
+    // The addresses the stream last saw at `indexes` up to `slot`, None if one was never seen
+    async fn streamed_entries(
+        &self,
+        table: &Pubkey,
+        indexes: &[u8],
+        slot: u64,
+    ) -> Result<Option<Vec<Pubkey>>, String> {
+        let id = table.to_string();
+        let slot = slot as i64;
+        let mut addresses = vec![];
+        for index in indexes {
+            let address = sqlx::query_scalar!(
+                "SELECT address FROM lookup_table_entries
+                WHERE id = ?1 AND idx = ?2 AND first_slot <= ?3
+                ORDER BY first_slot DESC LIMIT 1",
+                id,
+                index,
+                slot,
+            )
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|err| err.to_string())?;
+            let Some(address) = address.and_then(|address| address.parse().ok()) else {
+                return Ok(None);
+            };
+            addresses.push(address);
+        }
+        Ok(Some(addresses))
+    }
+
+    // A table version taken at another slot than the transaction only resolves it when the
+    // stream saw the same addresses in between, otherwise the table was closed and recreated
+    async fn check_streamed(
+        &self,
+        table: &Pubkey,
+        slot: u64,
+        table_slot: u64,
+        addresses: &[Pubkey],
+    ) -> Result<(), String> {
+        let id = table.to_string();
+        let from = slot.min(table_slot) as i64;
+        let to = slot.max(table_slot) as i64;
+        let entries = sqlx::query!(
+            "SELECT idx, address FROM lookup_table_entries
+            WHERE id = ?1 AND last_slot >= ?2 AND first_slot <= ?3",
+            id,
+            from,
+            to,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|err| err.to_string())?;
+        for entry in entries {
+            let stored = addresses.get(entry.idx as usize).map(|a| a.to_string());
+            if stored.is_some_and(|stored| stored != entry.address) {
+                return Err(format!(
+                    "[!] Lookup table {} changed between slots {} and {}",
+                    table, from, to
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn table_addresses(
+        &self,
+        lookup: &MessageAddressTableLookup,
+        slot: u64,
+    ) -> Result<(u64, Vec<Pubkey>), String> {
+        let max_index = lookup
+            .writable_indexes
+            .iter()
+            .chain(lookup.readonly_indexes.iter())
+            .max()
+            .copied()
+            .unwrap_or_default() as usize;
+
+        if let Some(stored) = self
+            .stored_table(&lookup.account_key, slot, max_index)
+            .await?
+        {
+            return Ok(stored);
+        }
+        let (table_slot, addresses) = self.fetch_table(&lookup.account_key).await?;
+        if addresses.len() <= max_index {
+            return Err(format!(
+                "[!] Lookup table {} has no index {}",
+                lookup.account_key, max_index
+            ));
+        }
+        Ok((table_slot, addresses))
+    }
+
+    async fn stored_table(
+        &self,
+        table: &Pubkey,
+        slot: u64,
+        max_index: usize,
+    ) -> Result<Option<(u64, Vec<Pubkey>)>, String> {
+        let id = table.to_string();
+        let slot = slot as i64;
+        let min_len = ((max_index + 1) * 32) as i64;
+        let row = sqlx::query!(
+            "SELECT slot, addresses FROM address_lookup_tables
+            WHERE id = ?1 AND length(addresses) >= ?3
+            ORDER BY CASE WHEN slot <= ?2 THEN 0 ELSE 1 END, abs(slot - ?2)
+            LIMIT 1",
+            id,
+            slot,
+            min_len,
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|err| err.to_string())?;
+
+        Ok(row.map(|row| (row.slot as u64, decode_addresses(&row.addresses))))
+    }
+
+    // Goes through the rate limited client, ingestion shares it with the proxy
+    async fn fetch_table(&self, table: &Pubkey) -> Result<(u64, Vec<Pubkey>), String> {
+        let resp = self
+            .client
+            .proxy_request(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "getAccountInfo",
+                "params": [table.to_string(), {"encoding": "base64", "commitment": "confirmed"}],
+            }))
+            .await?;
+        if let Some(err) = resp.get("error") {
+            return Err(format!("[!] Lookup table error {:?}", err));
+        }
+        let slot = resp["result"]["context"]["slot"]
+            .as_u64()
+            .ok_or("Lookup table response has no slot")?;
+        let value = resp["result"]["value"].clone();
+        if value.is_null() {
+            return Err(format!("[!] Lookup table {} not found", table));
+        }
+        let account = serde_json::from_value::<UiAccount>(value)
+            .map_err(|err| err.to_string())?
+            .decode::<Account>()
+            .ok_or("Invalid lookup table account data")?;
+        let lookup_table =
+            AddressLookupTable::deserialize(&account.data).map_err(|err| err.to_string())?;
+
+        let id = table.to_string();
+        let db_slot = slot as i64;
+        let deactivation_slot = lookup_table.meta.deactivation_slot as i64;
+        let last_extended_slot = lookup_table.meta.last_extended_slot as i64;
+        let authority = lookup_table.meta.authority.map(|a| a.to_string());
+        let addresses: Vec<u8> = lookup_table
+            .addresses
+            .iter()
+            .flat_map(|address| address.to_bytes())
+            .collect();
+        sqlx::query!(
+            "INSERT OR REPLACE INTO address_lookup_tables (id, slot, deactivation_slot, last_extended_slot, authority, addresses) VALUES ($1, $2, $3, $4, $5, $6)",
+            id,
+            db_slot,
+            deactivation_slot,
+            last_extended_slot,
+            authority,
+            addresses,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|err| err.to_string())?;
+        tracing::info!("Indexed lookup table {} at slot {}", table, slot);
+
+        Ok((slot, lookup_table.addresses.to_vec()))
+    }
+}
+
+// Records the lookup table entries the transactions of a block loaded, as their meta says
+pub async fn record_lookup_entries(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    transactions: &[IndexedTransaction],
+) -> Result<(), sqlx::Error> {
+    let entries: BTreeSet<(String, u8, String)> = transactions
+        .iter()
+        .filter_map(|indexed| {
+            let loaded = loaded_addresses_from_meta(indexed.meta.as_ref()?)?;
+            Some(lookup_entries(&indexed.tx, &loaded))
+        })
+        .flatten()
+        .map(|(table, index, address)| (table.to_string(), index, address.to_string()))
+        .collect();
+    let slot = slot as i64;
+    for (id, index, address) in entries {
+        sqlx::query!(
+            "INSERT INTO lookup_table_entries (id, idx, address, first_slot, last_slot)
+            VALUES (?1, ?2, ?3, ?4, ?4)
+            ON CONFLICT (id, idx, address) DO UPDATE SET
+                first_slot = min(first_slot, excluded.first_slot),
+                last_slot = max(last_slot, excluded.last_slot)",
+            id,
+            index,
+            address,
+            slot,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// (table, index, address) of every address the lookups of `tx` loaded, in the order the
+// runtime loads them : the writable ones of each table, then the readonly ones
+fn lookup_entries(
+    tx: &VersionedTransaction,
+    loaded: &LoadedAddresses,
+) -> Vec<(Pubkey, u8, Pubkey)> {
+    let Some(lookups) = tx.message.address_table_lookups() else {
+        return vec![];
+    };
+    let writable = lookups.iter().flat_map(|lookup| {
+        lookup
+            .writable_indexes
+            .iter()
+            .map(|index| (lookup.account_key, *index))
+    });
+    let readonly = lookups.iter().flat_map(|lookup| {
+        lookup
+            .readonly_indexes
+            .iter()
+            .map(|index| (lookup.account_key, *index))
+    });
+    let indexes: Vec<(Pubkey, u8)> = writable.chain(readonly).collect();
+    if indexes.len() != loaded.writable.len() + loaded.readonly.len() {
+        return vec![];
+    }
+    indexes
+        .into_iter()
+        .zip(loaded.writable.iter().chain(loaded.readonly.iter()))
+        .map(|((table, index), address)| (table, index, *address))
+        .collect()
+}
+
+fn decode_addresses(data: &[u8]) -> Vec<Pubkey> {
+    data.chunks_exact(32)
+        .map(|chunk| Pubkey::try_from(chunk).expect("chunk is 32 bytes"))
+        .collect()
+}
+
+fn loaded_addresses_from_meta(meta: &UiTransactionStatusMeta) -> Option<LoadedAddresses> {
+    let OptionSerializer::Some(loaded) = &meta.loaded_addresses else {
+        return None;
+    };
+    let parse = |keys: &[String]| -> Option<Vec<Pubkey>> {
+        keys.iter().map(|key| key.parse().ok()).collect()
+    };
+    Some(LoadedAddresses {
+        writable: parse(&loaded.writable)?,
+        readonly: parse(&loaded.readonly)?,
+    })
+}
+
+// Every account of the transaction in message order, with its writability
+pub fn transaction_accounts(
+    tx: &VersionedTransaction,
+    loaded: &LoadedAddresses,
+) -> Vec<(Pubkey, bool)> {
+    let header = tx.message.header();
+    let static_keys = tx.message.static_account_keys();
+    let signed = header.num_required_signatures as usize;
+    let writable_signed = signed.saturating_sub(header.num_readonly_signed_accounts as usize);
+    let writable_unsigned = static_keys
+        .len()
+        .saturating_sub(header.num_readonly_unsigned_accounts as usize);
+
+    static_keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let writable = i < writable_signed || (i >= signed && i < writable_unsigned);
+            (*key, writable)
+        })
+        .chain(loaded.writable.iter().map(|key| (*key, true)))
+        .chain(loaded.readonly.iter().map(|key| (*key, false)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use solana_sdk::{
+        hash::Hash,
+        message::{v0, MessageHeader, VersionedMessage},
+        signature::Signature,
+    };
+
+    use super::*;
+
+    fn v0_transaction(table: Pubkey) -> VersionedTransaction {
+        VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(v0::Message {
+                header: MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 0,
+                },
+                account_keys: vec![Pubkey::new_unique()],
+                recent_blockhash: Hash::default(),
+                instructions: vec![],
+                address_table_lookups: vec![MessageAddressTableLookup {
+                    account_key: table,
+                    writable_indexes: vec![0],
+                    readonly_indexes: vec![1],
+                }],
+            }),
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn resolves_from_streamed_entries(db: SqlitePool) {
+        let table = Pubkey::new_unique();
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        for (index, address) in [(0, a), (1, b)] {
+            sqlx::query(
+                "INSERT INTO lookup_table_entries (id, idx, address, first_slot, last_slot)
+                VALUES (?1, ?2, ?3, 100, 120)",
+            )
+            .bind(table.to_string())
+            .bind(index)
+            .bind(address.to_string())
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        // The table was recreated with other addresses by the time it was fetched
+        let addresses: Vec<u8> = [Pubkey::new_unique(), Pubkey::new_unique()]
+            .iter()
+            .flat_map(|address| address.to_bytes())
+            .collect();
+        sqlx::query(
+            "INSERT INTO address_lookup_tables (id, slot, deactivation_slot, last_extended_slot, addresses)
+            VALUES (?1, 200, -1, 190, ?2)",
+        )
+        .bind(table.to_string())
+        .bind(addresses)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let client = LimitedRequestClient::new("http://127.0.0.1:0", 45, Duration::from_secs(1));
+        let resolver = LookupTableResolver::new(db, client);
+        let tx = v0_transaction(table);
+        let loaded = resolver.resolve(&tx, None, 150).await.unwrap();
+        assert_eq!(loaded.writable, vec![a]);
+        assert_eq!(loaded.readonly, vec![b]);
+
+        // Before the stream saw the table only the fetched version is left, which disagrees
+        assert!(resolver.resolve(&tx, None, 50).await.is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+
+use super::IndexedTransaction;
+
+// Mint column of lamport balances
+pub const SOL_MINT: &str = "SOL";
+const SOL_DECIMALS: u8 = 9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    pub account: Pubkey,
+    pub mint: String,
+    // Wallet owning a token account, the account itself for SOL
+    pub owner: Option<String>,
+    pub pre: u64,
+    pub post: u64,
+    pub decimals: u8,
+}
+
+impl BalanceChange {
+    pub fn delta(&self) -> i64 {
+        (self.post as i128 - self.pre as i128) as i64
+    }
+}
+
+// `keys` are the transaction accounts in meta order, static keys then loaded addresses.
+// Token accounts missing on one side were created or closed by the transaction.
+pub fn balance_changes(keys: &[Pubkey], meta: &UiTransactionStatusMeta) -> Vec<BalanceChange> {
+    let mut changes = vec![];
+    for (index, (pre, post)) in meta
+        .pre_balances
+        .iter()
+        .zip(&meta.post_balances)
+        .enumerate()
+    {
+        let Some(account) = keys.get(index) else {
+            continue;
+        };
+        if pre != post {
+            changes.push(BalanceChange {
+                account: *account,
+                mint: SOL_MINT.to_string(),
+                owner: Some(account.to_string()),
+                pre: *pre,
+                post: *post,
+                decimals: SOL_DECIMALS,
+            });
+        }
+    }
+
+    // [pre, post] of each (token account, mint)
+    let mut tokens: BTreeMap<(u8, String), [Option<&UiTransactionTokenBalance>; 2]> =
+        BTreeMap::new();
+    let sides = [&meta.pre_token_balances, &meta.post_token_balances];
+    for (side, balances) in sides.into_iter().enumerate() {
+        for balance in token_balances(balances) {
+            tokens
+                .entry((balance.account_index, balance.mint.clone()))
+                .or_default()[side] = Some(balance);
+        }
+    }
+    for ((index, mint), [pre, post]) in tokens {
+        let Some(account) = keys.get(index as usize) else {
+            continue;
+        };
+        let amount = |balance: Option<&UiTransactionTokenBalance>| {
+            balance
+                .and_then(|b| b.ui_token_amount.amount.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        let (pre_amount, post_amount) = (amount(pre), amount(post));
+        if pre_amount == post_amount {
+            continue;
+        }
+        let Some(balance) = post.or(pre) else {
+            continue;
+        };
+        let owner = match &balance.owner {
+            OptionSerializer::Some(owner) => Some(owner.clone()),
+            _ => None,
+        };
+        changes.push(BalanceChange {
+            account: *account,
+            mint,
+            owner,
+            pre: pre_amount,
+            post: post_amount,
+            decimals: balance.ui_token_amount.decimals,
+        });
+    }
+    changes
+}
+
+fn token_balances(
+    balances: &OptionSerializer<Vec<UiTransactionTokenBalance>>,
+) -> &[UiTransactionTokenBalance] {
+    match balances {
+        OptionSerializer::Some(balances) => balances,
+        _ => &[],
+    }
+}
+
+pub async fn index_balance_changes(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    txs: &[IndexedTransaction],
+) -> Result<(), sqlx::Error> {
+    let changes: Vec<(&String, BalanceChange)> = txs
+        .iter()
+        .filter_map(|indexed| Some((indexed, indexed.meta.as_ref()?)))
+        .flat_map(|(indexed, meta)| {
+            let keys: Vec<Pubkey> = indexed.accounts.iter().map(|(key, _)| *key).collect();
+            balance_changes(&keys, meta)
+                .into_iter()
+                .map(move |change| (&indexed.signature, change))
+        })
+        .collect();
+
+    for chunk in changes.chunks(500) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO balance_changes (signature, slot, account, mint, owner, pre, post, delta, decimals) ",
+        );
+        query_builder.push_values(chunk, |mut b, (signature, change)| {
+            b.push_bind(signature.to_string())
+                .push_bind(slot as i64)
+                .push_bind(change.account.to_string())
+                .push_bind(change.mint.clone())
+                .push_bind(change.owner.clone())
+                .push_bind(change.pre as i64)
+                .push_bind(change.post as i64)
+                .push_bind(change.delta())
+                .push_bind(change.decimals as i64);
+        });
+        query_builder.build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const ORE_MINT: &str = "oreoU2P8bN6jkk3jbaiVxYnG1dCXcYxwhwyK9jSybcp";
+
+    #[test]
+    fn sol_and_token_changes() {
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let wallet = keys[0].to_string();
+        let token_balance = |index: u8, amount: &str| {
+            json!({
+                "accountIndex": index,
+                "mint": ORE_MINT,
+                "owner": wallet,
+                "programId": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+                "uiTokenAmount": {
+                    "amount": amount,
+                    "decimals": 11,
+                    "uiAmount": null,
+                    "uiAmountString": "0",
+                },
+            })
+        };
+        let meta: UiTransactionStatusMeta = serde_json::from_value(json!({
+            "err": null,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": [1_000_000, 2_039_280, 1],
+            "postBalances": [995_000, 2_039_280, 1],
+            "innerInstructions": [],
+            "logMessages": [],
+            "preTokenBalances": [token_balance(1, "100")],
+            "postTokenBalances": [token_balance(1, "350"), token_balance(2, "7")],
+        }))
+        .unwrap();
+
+        let changes = balance_changes(&keys, &meta);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].mint, SOL_MINT);
+        assert_eq!(changes[0].delta(), -5000);
+        assert_eq!(changes[1].account, keys[1]);
+        assert_eq!(changes[1].owner.as_deref(), Some(wallet.as_str()));
+        assert_eq!((changes[1].pre, changes[1].post), (100, 350));
+        assert_eq!(changes[1].decimals, 11);
+        // created by the transaction
+        assert_eq!((changes[2].pre, changes[2].delta()), (0, 7));
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::{Duration, Instant};
+
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use tokio::sync::mpsc;
+
+use super::{
+    account_stream, finalize_slot, index_account_update, index_block, store_accounts,
+    AccountUpdate, IndexedBlock, IngestError, IngestState, PubsubHub, SlotStatus, SlotUpdate,
+    BLOCK_STREAM, METRICS, SLOT_STREAM,
+};
+
+// Past it the ingestion side waits for the writer
+pub const WRITE_CHANNEL_SIZE: usize = 256;
+const MAX_BATCH_ROWS: usize = 2_000;
+// How long a batch waits to fill up once it got its first write
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+pub enum WriteOp {
+    Snapshot {
+        program_id: Pubkey,
+        slot: u64,
+        accounts: Vec<(Pubkey, Account)>,
+    },
+    Account(AccountUpdate),
+    Block(IndexedBlock),
+    Slot(SlotUpdate),
+}
+
+impl WriteOp {
+    fn rows(&self) -> usize {
+        match self {
+            WriteOp::Snapshot { accounts, .. } => accounts.len().max(1),
+            WriteOp::Block(block) => block.transactions.len().max(1),
+            WriteOp::Account(_) | WriteOp::Slot(_) => 1,
+        }
+    }
+}
+
+// Writes every batch in one transaction on its own connection, which keeps the prepared
+// statements of the account and slot writes. A write that fails rolls the whole batch back
+// and ends it with the error, none of the cursors moved past it. Ends once every sender is
+// dropped and the writes already queued are committed.
+pub async fn batch_writer(
+    db: SqlitePool,
+    hub: PubsubHub,
+    state: IngestState,
+    mut rx: mpsc::Receiver<WriteOp>,
+) -> Result<(), IngestError> {
+    let mut conn = db.acquire().await?;
+    while let Some(batch) = next_batch(&mut rx).await {
+        let started = Instant::now();
+        let mut tx = conn.begin().await?;
+        for op in &batch {
+            write(&mut tx, op).await?;
+        }
+        tx.commit().await?;
+        METRICS.observe_db_write("batch", started);
+        METRICS.write_queue_depth.set(rx.len() as i64);
+        for op in batch {
+            committed(&hub, &state, op);
+        }
+    }
+    Ok(())
+}
+
+// Waits for a write, then for more until the batch holds `MAX_BATCH_ROWS` rows or
+// `BATCH_INTERVAL` passed
+async fn next_batch(rx: &mut mpsc::Receiver<WriteOp>) -> Option<Vec<WriteOp>> {
+    let first = rx.recv().await?;
+    let deadline = tokio::time::Instant::now() + BATCH_INTERVAL;
+    let mut rows = first.rows();
+    let mut batch = vec![first];
+    while rows < MAX_BATCH_ROWS {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(op)) => {
+                rows += op.rows();
+                batch.push(op);
+            }
+            _ => break,
+        }
+    }
+    Some(batch)
+}
+
+async fn write(conn: &mut SqliteConnection, op: &WriteOp) -> Result<(), sqlx::Error> {
+    match op {
+        WriteOp::Snapshot {
+            program_id,
+            slot,
+            accounts,
+        } => store_accounts(conn, program_id, *slot, accounts).await,
+        WriteOp::Account(update) => index_account_update(conn, update).await,
+        WriteOp::Block(block) => index_block(conn, block).await,
+        WriteOp::Slot(update) if update.status == SlotStatus::Finalized => {
+            finalize_slot(conn, update.slot).await
+        }
+        WriteOp::Slot(_) => Ok(()),
+    }
+}
+
+// Readers see the write from here, so subscribers and the cursors only move now
+fn committed(hub: &PubsubHub, state: &IngestState, op: WriteOp) {
+    match op {
+        WriteOp::Snapshot {
+            program_id, slot, ..
+        } => {
+            state.record_account(slot);
+            state.record_stream(&account_stream(&program_id), slot);
+        }
+        WriteOp::Account(update) => {
+            state.record_account(update.slot);
+            state.record_stream(&account_stream(&update.account.owner), update.slot);
+            hub.publish_account(update);
+        }
+        WriteOp::Block(block) => {
+            state.record_block(block.slot, block.block_height);
+            state.record_stream(BLOCK_STREAM, block.slot);
+        }
+        WriteOp::Slot(update) => state.record_stream(SLOT_STREAM, update.slot),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(slot: u64) -> WriteOp {
+        WriteOp::Slot(SlotUpdate {
+            slot,
+            parent: None,
+            status: SlotStatus::Confirmed,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batches_by_rows_and_time() {
+        let (tx, mut rx) = mpsc::channel(WRITE_CHANNEL_SIZE);
+        for i in 0..3 {
+            tx.send(slot(i)).await.unwrap();
+        }
+        let started = tokio::time::Instant::now();
+        assert_eq!(next_batch(&mut rx).await.unwrap().len(), 3);
+        assert_eq!(started.elapsed(), BATCH_INTERVAL);
+
+        let accounts = vec![(Pubkey::new_unique(), Account::default()); MAX_BATCH_ROWS];
+        tx.send(WriteOp::Snapshot {
+            program_id: Pubkey::new_unique(),
+            slot: 3,
+            accounts,
+        })
+        .await
+        .unwrap();
+        tx.send(slot(4)).await.unwrap();
+        let batch = next_batch(&mut rx).await.unwrap();
+        assert!(matches!(batch[..], [WriteOp::Snapshot { .. }]));
+
+        // The queued writes still come out once the senders are gone
+        drop(tx);
+        assert_eq!(next_batch(&mut rx).await.unwrap().len(), 1);
+        assert!(next_batch(&mut rx).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn failed_write_rolls_the_batch_back(db: SqlitePool) {
+        // Finalizing a slot fails, the account writes around it are not committed either
+        sqlx::query("DROP TABLE transactions")
+            .execute(&db)
+            .await
+            .unwrap();
+        let account = |slot| {
+            WriteOp::Account(AccountUpdate {
+                pubkey: Pubkey::new_unique(),
+                account: Account::default(),
+                slot,
+            })
+        };
+        let (tx, rx) = mpsc::channel(WRITE_CHANNEL_SIZE);
+        tx.send(account(5)).await.unwrap();
+        tx.send(WriteOp::Slot(SlotUpdate {
+            slot: 5,
+            parent: None,
+            status: SlotStatus::Finalized,
+        }))
+        .await
+        .unwrap();
+        tx.send(account(6)).await.unwrap();
+        drop(tx);
+        let state = IngestState::default();
+        let written = batch_writer(db.clone(), PubsubHub::new(vec![]), state.clone(), rx).await;
+        assert!(written.is_err());
+
+        let accounts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM accounts_archive")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(accounts, 0);
+        assert_eq!(state.account_slot(), None);
+        assert!(state.streams().is_empty());
+    }
+}
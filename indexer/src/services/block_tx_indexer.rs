@@ -1,80 +1,191 @@
-use futures::StreamExt;
-use solana_client::{
-    nonblocking::pubsub_client::PubsubClient,
-    rpc_client::SerializableTransaction,
-    rpc_config::{RpcBlockSubscribeConfig, RpcBlockSubscribeFilter},
+use std::time::Instant;
+
+use solana_client::rpc_client::SerializableTransaction;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, message::v0::LoadedAddresses, pubkey::Pubkey,
+    transaction::VersionedTransaction,
 };
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
-use solana_transaction_status::TransactionDetails;
-use sqlx::{Pool, QueryBuilder, Sqlite};
+use solana_transaction_status::UiTransactionStatusMeta;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
 
-pub async fn block_tx_indexer(db: Pool<Sqlite>, program_id: &Pubkey) {
-    let client = PubsubClient::new(crate::SOLANA_BLOCKS_RPC_WS)
-        .await
-        .unwrap();
-    let block_filter = RpcBlockSubscribeFilter::MentionsAccountOrProgram(program_id.to_string());
-    let comitment = CommitmentConfig::confirmed();
-    let block_config = RpcBlockSubscribeConfig {
-        commitment: Some(comitment),
-        encoding: Some(solana_transaction_status::UiTransactionEncoding::Base64),
-        transaction_details: Some(TransactionDetails::Full),
-        show_rewards: Some(false),
-        max_supported_transaction_version: Some(0),
-    };
+use crate::decoders;
 
-    let (mut block_stream, _resp) = match client
-        .block_subscribe(block_filter, Some(block_config))
-        .await
-    {
-        Ok(res) => res,
-        Err(err) => {
-            tracing::error!("[!] Failed to subscribe to program : {}", err);
-            return;
-        }
-    };
+use super::{
+    decompose_instructions, encode_data, extract_memo, index_balance_changes, index_instructions,
+    link_account_writes, record_lookup_entries, transaction_accounts, BlockUpdate,
+    IndexedInstruction, LookupTableResolver, METRICS,
+};
 
-    while let Some(block) = block_stream.next().await {
-        let slot = block.value.slot;
-        let block = match block.value.block {
-            Some(b) => b,
-            None => {
-                tracing::error!("[!] Missing block");
-                continue;
-            }
+// A block with the lookup tables of its transactions resolved, ready to be written
+pub struct IndexedBlock {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+    pub transactions: Vec<IndexedTransaction>,
+}
+
+// Resolving may ask upstream, so it runs before the block reaches the writer. Transactions
+// whose accounts can not all be resolved are left out instead of indexed with wrong keys.
+pub async fn prepare_block(resolver: &LookupTableResolver, block: BlockUpdate) -> IndexedBlock {
+    let slot = block.slot;
+    let mut transactions = vec![];
+    for tx in block.transactions {
+        let signature = *tx.tx.get_signature();
+        let indexed = match resolver.resolve(&tx.tx, tx.meta.as_ref(), slot).await {
+            Ok(loaded) => IndexedTransaction::new(tx.tx_index, tx.tx, tx.meta, loaded),
+            Err(err) => Err(format!("lookup tables not resolved : {}", err)),
         };
-        let block_time = block.block_time;
-        let transactions = match block.transactions {
-            Some(txs) => txs,
-            None => {
-                tracing::error!("[!] Missing transactions");
-                continue;
+        match indexed {
+            Ok(indexed) => transactions.push(indexed),
+            Err(err) => {
+                tracing::error!("[!] Skipped transaction {} : {}", signature, err);
+                METRICS.skipped_transactions.inc();
             }
-        };
-        let program_txs = transactions
-            .into_iter()
-            .filter_map(|tx| tx.transaction.decode().map(|t| (tx.meta, t)));
+        }
+    }
+    IndexedBlock {
+        slot,
+        block_time: block.block_time,
+        block_height: block.block_height,
+        transactions,
+    }
+}
+
+pub async fn index_block(
+    conn: &mut SqliteConnection,
+    block: &IndexedBlock,
+) -> Result<(), sqlx::Error> {
+    let slot = block.slot;
+    let block_time = block.block_time;
+    let comitment = CommitmentConfig::confirmed();
+    let program_txs = &block.transactions;
+
+    tracing::info!("Got block {} |  {:2} txs", slot, program_txs.len());
+    if program_txs.is_empty() {
+        return Ok(());
+    }
+    let started = Instant::now();
 
-        let txs_count = program_txs.clone().count();
-        tracing::info!("Got block {} |  {:2} txs", slot, txs_count);
+    let mut query_builder: QueryBuilder<Sqlite> =
+        QueryBuilder::new("INSERT OR REPLACE INTO transactions (signature, slot, tx_index, err, memo, block_time, confirmation_status, data, encoding, loaded_writable, loaded_readonly) ");
+    query_builder.push_values(program_txs, |mut b, indexed| {
+        let encoded = bincode::serialize(&indexed.tx)
+            .ok()
+            .map(|data| encode_data(&data, None));
+        let encoding = encoded.as_ref().map(|encoded| encoded.encoding);
+        let data = encoded.map(|encoded| encoded.data);
+        let err = indexed
+            .meta
+            .as_ref()
+            .map(|m| m.err.as_ref().map(|e| e.to_string()));
+        let memo = extract_memo(&indexed.instructions);
+        let block_time = block_time;
+        let confirmation_status = Some(comitment.commitment.to_string());
+        let loaded_writable = keys_to_json(&indexed.loaded.writable);
+        let loaded_readonly = keys_to_json(&indexed.loaded.readonly);
+        b.push_bind(indexed.signature.clone())
+            .push_bind(slot as i32)
+            .push_bind(indexed.tx_index.map(|tx_index| tx_index as i64))
+            .push_bind(err)
+            .push_bind(memo)
+            .push_bind(block_time)
+            .push_bind(confirmation_status)
+            .push_bind(data)
+            .push_bind(encoding)
+            .push_bind(loaded_writable)
+            .push_bind(loaded_readonly);
+    });
+    query_builder.build().execute(&mut *conn).await?;
 
-        let mut query_builder: QueryBuilder<Sqlite> =
-            QueryBuilder::new("INSERT OR REPLACE INTO transactions (signature, slot,  err, memo, block_time, confirmation_status, data) ");
-        query_builder.push_values(program_txs, |mut b, (meta, tx)| {
-            let signature = tx.get_signature().to_string();
-            let data = bincode::serialize(&tx).ok();
-            let err = meta.map(|m| m.err.map(|e| e.to_string()));
-            // todo: extract memo ?
-            let memo: Option<String> = None;
-            let block_time = block_time;
-            let confirmation_status = Some(comitment.commitment.to_string());
-            b.push_bind(signature)
+    let tx_accounts: Vec<(&String, usize, &Pubkey, bool)> = program_txs
+        .iter()
+        .flat_map(|indexed| {
+            indexed
+                .accounts
+                .iter()
+                .enumerate()
+                .map(move |(position, (account, writable))| {
+                    (&indexed.signature, position, account, *writable)
+                })
+        })
+        .collect();
+    for chunk in tx_accounts.chunks(1_000) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO transaction_accounts (signature, position, account, slot, writable) ",
+        );
+        query_builder.push_values(chunk, |mut b, (signature, position, account, writable)| {
+            b.push_bind(signature.to_string())
+                .push_bind(*position as i32)
+                .push_bind(account.to_string())
                 .push_bind(slot as i32)
-                .push_bind(err)
-                .push_bind(memo)
-                .push_bind(block_time)
-                .push_bind(confirmation_status)
-                .push_bind(data);
+                .push_bind(*writable);
         });
-        query_builder.build().execute(&db).await.unwrap();
+        query_builder.build().execute(&mut *conn).await?;
     }
+
+    record_lookup_entries(conn, slot, program_txs).await?;
+    link_account_writes(conn, slot, None).await?;
+    index_instructions(conn, slot, program_txs).await?;
+    decoders::index_decoded_instructions(conn, program_txs).await?;
+    decoders::index_ore_events(conn, slot, program_txs).await?;
+    index_balance_changes(conn, slot, program_txs).await?;
+    METRICS.observe_db_write("block", started);
+    METRICS
+        .ingested_transactions
+        .inc_by(program_txs.len() as u64);
+    Ok(())
+}
+
+// Transactions of a rooted slot can no longer be rolled back
+pub async fn finalize_slot(conn: &mut SqliteConnection, slot: u64) -> Result<(), sqlx::Error> {
+    let started = Instant::now();
+    let slot = slot as i64;
+    sqlx::query!(
+        "UPDATE transactions SET confirmation_status = 'finalized' WHERE slot = $1",
+        slot
+    )
+    .execute(&mut *conn)
+    .await?;
+    METRICS.observe_db_write("finalize", started);
+    Ok(())
+}
+
+// A decoded transaction with every account it references resolved
+pub struct IndexedTransaction {
+    // Position in the whole block, unknown ones are left out of the write attribution
+    pub tx_index: Option<usize>,
+    pub signature: String,
+    pub tx: VersionedTransaction,
+    pub meta: Option<UiTransactionStatusMeta>,
+    pub loaded: LoadedAddresses,
+    pub accounts: Vec<(Pubkey, bool)>,
+    pub instructions: Vec<IndexedInstruction>,
+}
+
+impl IndexedTransaction {
+    pub fn new(
+        tx_index: Option<usize>,
+        tx: VersionedTransaction,
+        meta: Option<UiTransactionStatusMeta>,
+        loaded: LoadedAddresses,
+    ) -> Result<Self, String> {
+        let signature = tx.get_signature().to_string();
+        let accounts = transaction_accounts(&tx, &loaded);
+        let keys: Vec<Pubkey> = accounts.iter().map(|(key, _)| *key).collect();
+        let instructions = decompose_instructions(&tx, meta.as_ref(), &keys)?;
+        Ok(Self {
+            tx_index,
+            signature,
+            tx,
+            meta,
+            loaded,
+            accounts,
+            instructions,
+        })
+    }
+}
+
+fn keys_to_json(keys: &[Pubkey]) -> String {
+    let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+    serde_json::to_string(&keys).unwrap_or_default()
 }
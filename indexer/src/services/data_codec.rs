@@ -0,0 +1,524 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use solana_sdk::{hash::hash, pubkey::Pubkey};
+use sqlx::{SqliteConnection, SqliteExecutor, SqlitePool};
+
+// Values of the `encoding` columns, NULL is raw too : older rows and the geyser plugin
+pub const RAW: i64 = 0;
+pub const ZSTD: i64 = 1;
+// zstd of the XOR against the history version at `base_slot`, whose data hashes to `base_hash`
+pub const ZSTD_DELTA: i64 = 2;
+
+const ZSTD_LEVEL: i32 = 3;
+const MAX_DELTA_CHAIN: usize = 16;
+const MAX_DICTIONARY_SIZE: usize = 16 * 1024;
+const DICTIONARY_SAMPLES: i64 = 10_000;
+
+#[derive(Default)]
+struct Dictionaries {
+    by_id: HashMap<u32, Arc<[u8]>>,
+    // Latest dictionary of each program, None once looked up without one
+    by_program: HashMap<Pubkey, Option<u32>>,
+}
+
+static DICTIONARIES: LazyLock<RwLock<Dictionaries>> = LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedData {
+    pub encoding: i64,
+    pub base_slot: Option<i64>,
+    pub base_hash: Option<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+fn decode_error(err: impl ToString) -> sqlx::Error {
+    sqlx::Error::Decode(err.to_string().into())
+}
+
+fn xor(base: &[u8], data: &[u8]) -> Vec<u8> {
+    base.iter().zip(data).map(|(a, b)| a ^ b).collect()
+}
+
+fn compress(data: &[u8], dictionary: Option<&[u8]>) -> Option<Vec<u8>> {
+    let compressed = match dictionary {
+        Some(dictionary) => zstd::bulk::Compressor::with_dictionary(ZSTD_LEVEL, dictionary)
+            .and_then(|mut compressor| compressor.compress(data)),
+        None => zstd::bulk::compress(data, ZSTD_LEVEL),
+    };
+    compressed.ok()
+}
+
+fn decompress(data: &[u8], dictionary: Option<&[u8]>) -> Result<Vec<u8>, sqlx::Error> {
+    let mut decompressed = vec![];
+    match dictionary {
+        Some(dictionary) => zstd::stream::read::Decoder::with_dictionary(data, dictionary)
+            .and_then(|mut decoder| decoder.read_to_end(&mut decompressed)),
+        None => zstd::stream::read::Decoder::new(data)
+            .and_then(|mut decoder| decoder.read_to_end(&mut decompressed)),
+    }
+    .map_err(decode_error)?;
+    Ok(decompressed)
+}
+
+// zstd when it is smaller, tiny accounts stay raw
+pub fn encode_data(data: &[u8], dictionary: Option<&[u8]>) -> EncodedData {
+    match compress(data, dictionary) {
+        Some(compressed) if compressed.len() < data.len() => EncodedData {
+            encoding: ZSTD,
+            base_slot: None,
+            base_hash: None,
+            data: compressed,
+        },
+        _ => EncodedData {
+            encoding: RAW,
+            base_slot: None,
+            base_hash: None,
+            data: data.to_vec(),
+        },
+    }
+}
+
+// The version at `slot` as a delta against the latest standalone version before it, when
+// that is smaller than `full`. Deltas never chain, the next one reuses the same base.
+pub async fn encode_delta(
+    conn: &mut SqliteConnection,
+    id: &str,
+    slot: u64,
+    data: &[u8],
+    full: &EncodedData,
+) -> Result<Option<EncodedData>, sqlx::Error> {
+    let slot = slot as i64;
+    let base = sqlx::query!(
+        "SELECT slot, data, encoding FROM accounts_history
+        WHERE id = ? AND slot < ? AND COALESCE(encoding, 0) != ? ORDER BY slot DESC LIMIT 1",
+        id,
+        slot,
+        ZSTD_DELTA,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(base) = base else {
+        return Ok(None);
+    };
+    let base_data = decode_data(&mut *conn, base.encoding, base.data).await?;
+    if base_data.len() != data.len() {
+        return Ok(None);
+    }
+    let delta = compress(&xor(&base_data, data), None);
+    Ok(delta
+        .filter(|delta| delta.len() < full.data.len())
+        .map(|delta| EncodedData {
+            encoding: ZSTD_DELTA,
+            base_slot: Some(base.slot),
+            base_hash: Some(hash(&base_data).to_bytes().to_vec()),
+            data: delta,
+        }))
+}
+
+// A raw or zstd column, the frame header names the dictionary it needs
+pub async fn decode_data(
+    db: impl SqliteExecutor<'_>,
+    encoding: Option<i64>,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, sqlx::Error> {
+    match encoding.unwrap_or(RAW) {
+        RAW => Ok(data),
+        ZSTD => match zstd::zstd_safe::get_dict_id_from_frame(&data) {
+            0 => decompress(&data, None),
+            dict_id => decompress(&data, Some(&dictionary(db, dict_id).await?)),
+        },
+        encoding => Err(decode_error(format!("Unexpected encoding {}", encoding))),
+    }
+}
+
+// Any stored version of `id`, following its delta down to the base version. A base replaced
+// since the delta was made fails rather than decoding to the wrong data.
+pub async fn decode_account_data(
+    conn: &mut SqliteConnection,
+    id: &str,
+    encoding: Option<i64>,
+    base_slot: Option<i64>,
+    base_hash: Option<Vec<u8>>,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, sqlx::Error> {
+    let (mut encoding, mut base_slot, mut base_hash, mut data) =
+        (encoding, base_slot, base_hash, data);
+    // Rows written before the deltas of a replaced base were rebased can chain
+    let mut deltas = vec![];
+    while encoding == Some(ZSTD_DELTA) {
+        if deltas.len() >= MAX_DELTA_CHAIN {
+            return Err(decode_error(format!("Delta chain of {} is too long", id)));
+        }
+        let slot = base_slot.ok_or_else(|| decode_error(format!("Delta of {} has no base", id)))?;
+        let base = sqlx::query!(
+            "SELECT data, encoding, base_slot, base_hash FROM accounts_history WHERE id = ? AND slot = ?",
+            id,
+            slot,
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| decode_error(format!("Base {} of {} is missing", slot, id)))?;
+        deltas.push((slot, base_hash, data));
+        (encoding, base_slot, base_hash, data) =
+            (base.encoding, base.base_slot, base.base_hash, base.data);
+    }
+    let mut decoded = decode_data(&mut *conn, encoding, data).await?;
+    for (slot, base_hash, delta) in deltas.into_iter().rev() {
+        decoded = apply_delta(id, slot, &decoded, base_hash, &delta)?;
+    }
+    Ok(decoded)
+}
+
+fn apply_delta(
+    id: &str,
+    base_slot: i64,
+    base: &[u8],
+    base_hash: Option<Vec<u8>>,
+    delta: &[u8],
+) -> Result<Vec<u8>, sqlx::Error> {
+    // Deltas written before the hash was stored have none to check
+    if base_hash.is_some_and(|base_hash| base_hash != hash(base).to_bytes()) {
+        return Err(decode_error(format!(
+            "Base {} of {} changed after its delta was made",
+            base_slot, id
+        )));
+    }
+    let delta = decompress(delta, None)?;
+    if delta.len() != base.len() {
+        return Err(decode_error(format!(
+            "Delta of {} does not fit its base",
+            id
+        )));
+    }
+    Ok(xor(base, &delta))
+}
+
+// The deltas made against the version of `id` at `slot` are stored standalone again, for
+// that version to be replaced
+pub async fn rebase_deltas(
+    conn: &mut SqliteConnection,
+    id: &str,
+    slot: u64,
+    dictionary: Option<&[u8]>,
+) -> Result<(), sqlx::Error> {
+    let slot = slot as i64;
+    let deltas = sqlx::query!(
+        "SELECT slot, data, base_hash FROM accounts_history WHERE id = ? AND base_slot = ? AND encoding = ?",
+        id,
+        slot,
+        ZSTD_DELTA,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    if deltas.is_empty() {
+        return Ok(());
+    }
+    let base = sqlx::query!(
+        "SELECT data, encoding, base_slot, base_hash FROM accounts_history WHERE id = ? AND slot = ?",
+        id,
+        slot,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let base = decode_account_data(
+        &mut *conn,
+        id,
+        base.encoding,
+        base.base_slot,
+        base.base_hash,
+        base.data,
+    )
+    .await?;
+    for delta in deltas {
+        let data = apply_delta(id, slot, &base, delta.base_hash, &delta.data)?;
+        let encoded = encode_data(&data, dictionary);
+        sqlx::query!(
+            "UPDATE accounts_history SET data = ?, encoding = ?, base_slot = NULL, base_hash = NULL
+            WHERE id = ? AND slot = ?",
+            encoded.data,
+            encoded.encoding,
+            id,
+            delta.slot,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn dictionary(db: impl SqliteExecutor<'_>, dict_id: u32) -> Result<Arc<[u8]>, sqlx::Error> {
+    let cached = DICTIONARIES.read().unwrap().by_id.get(&dict_id).cloned();
+    if let Some(dictionary) = cached {
+        return Ok(dictionary);
+    }
+    let id = dict_id as i64;
+    let dictionary: Arc<[u8]> = sqlx::query_scalar!(
+        "SELECT dictionary FROM zstd_dictionaries WHERE dict_id = ?",
+        id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| decode_error(format!("Unknown zstd dictionary {}", dict_id)))?
+    .into();
+    DICTIONARIES
+        .write()
+        .unwrap()
+        .by_id
+        .insert(dict_id, dictionary.clone());
+    Ok(dictionary)
+}
+
+// Latest trained dictionary of `program_id`, which new writes of its accounts use
+pub async fn program_dictionary(
+    conn: &mut SqliteConnection,
+    program_id: &Pubkey,
+) -> Result<Option<Arc<[u8]>>, sqlx::Error> {
+    let cached = DICTIONARIES
+        .read()
+        .unwrap()
+        .by_program
+        .get(program_id)
+        .copied();
+    let dict_id = match cached {
+        Some(dict_id) => dict_id,
+        None => {
+            let program = program_id.to_string();
+            let dict_id = sqlx::query_scalar!(
+                "SELECT dict_id FROM zstd_dictionaries WHERE program_id = ?
+                ORDER BY created_at DESC LIMIT 1",
+                program
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .map(|dict_id| dict_id as u32);
+            DICTIONARIES
+                .write()
+                .unwrap()
+                .by_program
+                .insert(*program_id, dict_id);
+            dict_id
+        }
+    };
+    match dict_id {
+        Some(dict_id) => Ok(Some(dictionary(conn, dict_id).await?)),
+        None => Ok(None),
+    }
+}
+
+// Trains on the latest versions of the program accounts. Rows compressed before keep the
+// dictionary their frame names, so a retrained one only changes the next writes.
+pub async fn train_dictionary(db: &SqlitePool, program_id: &Pubkey) -> Result<u32, String> {
+    let program = program_id.to_string();
+    let rows = sqlx::query!(
+        "SELECT data, encoding FROM accounts_archive WHERE owner = ? LIMIT ?",
+        program,
+        DICTIONARY_SAMPLES,
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|err| err.to_string())?;
+    let mut samples = Vec::with_capacity(rows.len());
+    for row in rows {
+        let data = decode_data(db, row.encoding, row.data)
+            .await
+            .map_err(|err| err.to_string())?;
+        samples.push(data);
+    }
+    let dictionary = zstd::dict::from_samples(&samples, MAX_DICTIONARY_SIZE)
+        .map_err(|err| format!("{} samples : {}", samples.len(), err))?;
+    let dict_id = zstd::zstd_safe::get_dict_id_from_dict(&dictionary);
+    if dict_id == 0 {
+        return Err("Trained dictionary has no id".to_string());
+    }
+
+    let id = dict_id as i64;
+    sqlx::query!(
+        "INSERT OR REPLACE INTO zstd_dictionaries (dict_id, program_id, dictionary, created_at) VALUES (?, ?, ?, unixepoch())",
+        id,
+        program,
+        dictionary,
+    )
+    .execute(db)
+    .await
+    .map_err(|err| err.to_string())?;
+    let mut dictionaries = DICTIONARIES.write().unwrap();
+    dictionaries.by_id.insert(dict_id, dictionary.into());
+    dictionaries.by_program.insert(*program_id, Some(dict_id));
+    Ok(dict_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::account::Account;
+
+    use super::*;
+    use crate::services::{index_account_update, store_accounts, AccountUpdate};
+
+    // data, encoding, base_slot and base_hash
+    type HistoryRow = (Vec<u8>, Option<i64>, Option<i64>, Option<Vec<u8>>);
+
+    // Shaped like an Ore proof : a fixed header, a counter and mostly unchanged fields
+    fn proof_data(i: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 200];
+        data[..8].copy_from_slice(b"PROOF\0\0\0");
+        data[8..12].copy_from_slice(&i.to_le_bytes());
+        data[40..44].copy_from_slice(&(i * 7919).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn tiny_data_stays_raw() {
+        let encoded = encode_data(&[1, 2, 3, 4], None);
+        assert_eq!(encoded.encoding, RAW);
+        assert_eq!(encoded.data, vec![1, 2, 3, 4]);
+        assert_eq!(encode_data(&proof_data(1), None).encoding, ZSTD);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn versions_decode_through_deltas(db: SqlitePool) {
+        let owner = Pubkey::new_unique();
+        let pubkey = Pubkey::new_unique();
+        let mut conn = db.acquire().await.unwrap();
+        for (slot, i) in [(10, 1), (11, 2), (12, 3)] {
+            let update = AccountUpdate {
+                pubkey,
+                account: Account {
+                    lamports: 1,
+                    data: proof_data(i),
+                    owner,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+                slot,
+            };
+            index_account_update(&mut conn, &update).await.unwrap();
+        }
+        drop(conn);
+
+        let id = pubkey.to_string();
+        let rows: Vec<HistoryRow> = sqlx::query_as(
+            "SELECT data, encoding, base_slot, base_hash FROM accounts_history WHERE id = ? ORDER BY slot",
+        )
+        .bind(&id)
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        let encodings: Vec<(Option<i64>, Option<i64>)> =
+            rows.iter().map(|row| (row.1, row.2)).collect();
+        assert_eq!(
+            encodings,
+            vec![
+                (Some(ZSTD), None),
+                (Some(ZSTD_DELTA), Some(10)),
+                (Some(ZSTD_DELTA), Some(10))
+            ]
+        );
+        let mut conn = db.acquire().await.unwrap();
+        for ((data, encoding, base_slot, base_hash), i) in rows.into_iter().zip(1..) {
+            let decoded = decode_account_data(&mut conn, &id, encoding, base_slot, base_hash, data)
+                .await
+                .unwrap();
+            assert_eq!(decoded, proof_data(i));
+        }
+
+        let (data, encoding): (Vec<u8>, Option<i64>) =
+            sqlx::query_as("SELECT data, encoding FROM accounts_archive WHERE id = ?")
+                .bind(&id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(encoding, Some(ZSTD));
+        assert_eq!(
+            decode_data(&db, encoding, data).await.unwrap(),
+            proof_data(3)
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn rewritten_base_rebases_its_deltas(db: SqlitePool) {
+        let pubkey = Pubkey::new_unique();
+        let update = |slot, i| AccountUpdate {
+            pubkey,
+            account: Account {
+                lamports: 1,
+                data: proof_data(i),
+                owner: Pubkey::default(),
+                executable: false,
+                rent_epoch: 0,
+            },
+            slot,
+        };
+        let mut conn = db.acquire().await.unwrap();
+        for (slot, i) in [(10, 1), (11, 2), (10, 5)] {
+            index_account_update(&mut conn, &update(slot, i))
+                .await
+                .unwrap();
+        }
+
+        let id = pubkey.to_string();
+        let rows: Vec<HistoryRow> = sqlx::query_as(
+            "SELECT data, encoding, base_slot, base_hash FROM accounts_history WHERE id = ? ORDER BY slot",
+        )
+        .bind(&id)
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        let mut versions = vec![];
+        for (data, encoding, base_slot, base_hash) in rows {
+            assert_eq!(encoding, Some(ZSTD));
+            let decoded = decode_account_data(&mut conn, &id, encoding, base_slot, base_hash, data)
+                .await
+                .unwrap();
+            versions.push(decoded);
+        }
+        assert_eq!(versions, vec![proof_data(5), proof_data(2)]);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn trained_dictionary(db: SqlitePool) {
+        let program_id = Pubkey::new_unique();
+        let accounts: Vec<(Pubkey, Account)> = (0..500)
+            .map(|i| {
+                let account = Account {
+                    lamports: 1,
+                    data: proof_data(i),
+                    owner: program_id,
+                    executable: false,
+                    rent_epoch: 0,
+                };
+                (Pubkey::new_unique(), account)
+            })
+            .collect();
+        let mut conn = db.acquire().await.unwrap();
+        store_accounts(&mut conn, &program_id, 1, &accounts)
+            .await
+            .unwrap();
+        assert!(program_dictionary(&mut conn, &program_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        let dict_id = train_dictionary(&db, &program_id).await.unwrap();
+        let dictionary = program_dictionary(&mut conn, &program_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let with_dictionary = encode_data(&proof_data(1_000), Some(&dictionary));
+        assert!(with_dictionary.data.len() < encode_data(&proof_data(1_000), None).data.len());
+        assert_eq!(
+            zstd::zstd_safe::get_dict_id_from_frame(&with_dictionary.data),
+            dict_id
+        );
+
+        // Decoding only has the frame to go by
+        DICTIONARIES.write().unwrap().by_id.clear();
+        let decoded = decode_data(&db, Some(ZSTD), with_dictionary.data)
+            .await
+            .unwrap();
+        assert_eq!(decoded, proof_data(1_000));
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    batch_writer, prepare_block, IngestError, IngestEvent, IngestSource, IngestState,
+    LookupTableResolver, PubsubHub, WriteOp, METRICS, WRITE_CHANNEL_SIZE,
+};
+
+// Writes whatever `source` yields until it ends, which after `shutdown` is once the
+// events already received are written. A failed write ends it early, for the supervisor to
+// start over with a new source.
+pub async fn indexer(
+    db: SqlitePool,
+    source: Box<dyn IngestSource>,
+    resolver: LookupTableResolver,
+    hub: PubsubHub,
+    state: IngestState,
+    shutdown: CancellationToken,
+) -> Result<(), IngestError> {
+    tracing::info!("Ingesting from {}", source.name());
+    let mut events = source.start(shutdown);
+    let (tx, rx) = mpsc::channel(WRITE_CHANNEL_SIZE);
+    let read = async {
+        while let Some(event) = events.next().await {
+            let op = match event {
+                IngestEvent::Snapshot {
+                    program_id,
+                    slot,
+                    accounts,
+                } => WriteOp::Snapshot {
+                    program_id,
+                    slot,
+                    accounts,
+                },
+                IngestEvent::Account(update) => WriteOp::Account(update),
+                IngestEvent::Block(block) => WriteOp::Block(prepare_block(&resolver, block).await),
+                IngestEvent::Slot(update) => WriteOp::Slot(update),
+                IngestEvent::Connection { stream, connected } => {
+                    state.set_connected(&stream, connected);
+                    continue;
+                }
+            };
+            // Waits while the writer is behind, which holds the source back
+            if tx.send(op).await.is_err() {
+                break;
+            }
+            METRICS
+                .write_queue_depth
+                .set((WRITE_CHANNEL_SIZE - tx.capacity()) as i64);
+        }
+        // Lets the writer finish the queue
+        drop(tx);
+        Ok::<_, IngestError>(())
+    };
+    let ingested =
+        tokio::try_join!(read, batch_writer(db.clone(), hub, state.clone(), rx)).map(|_| ());
+    if ingested.is_ok() {
+        tracing::info!("Ingestion stopped");
+    }
+    // The cursors are kept on failure too, they only move past committed writes
+    if let Err(err) = save_cursors(&db, &state).await {
+        tracing::error!("[!] Failed to save the ingestion cursors : {}", err);
+    }
+    ingested
+}
+
+// Last slot processed by each stream
+pub async fn save_cursors(db: &SqlitePool, state: &IngestState) -> Result<(), sqlx::Error> {
+    let updated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    for (stream, stream_state) in state.streams() {
+        let Some(slot) = stream_state.last_slot else {
+            continue;
+        };
+        let slot = slot as i64;
+        sqlx::query!(
+            "INSERT OR REPLACE INTO ingest_cursors (stream, slot, updated_at) VALUES ($1, $2, $3)",
+            stream,
+            slot,
+            updated_at,
+        )
+        .execute(db)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn load_cursors(db: &SqlitePool) -> Result<Vec<(String, u64)>, sqlx::Error> {
+    let rows = sqlx::query!("SELECT stream, slot FROM ingest_cursors ORDER BY stream")
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.stream, row.slot as u64))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::services::{FileReplaySource, LimitedRequestClient, SOL_MINT};
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/ingest/stream.ndjson");
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn replay_into_database(db: SqlitePool) {
+        let source = Box::new(FileReplaySource::new(FIXTURE));
+        indexer(
+            db.clone(),
+            source,
+            LookupTableResolver::new(
+                db.clone(),
+                LimitedRequestClient::new("http://127.0.0.1:0", 45, Duration::from_secs(1)),
+            ),
+            PubsubHub::new(vec![]),
+            IngestState::default(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let (slot, data): (i64, Vec<u8>) =
+            sqlx::query_as("SELECT slot, data FROM accounts_archive WHERE id = ?1")
+                .bind("E3XYtECRdKD9zVx5fEPJHQdQQZZxDE8RDEfNwYRvArsQ")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!((slot, data), (300_000_001, vec![1, 2, 3, 4]));
+        let versions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM accounts_history")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(versions, 2);
+
+        let (slot, tx_index, block_time, status): (i64, i64, i64, String) = sqlx::query_as(
+            "SELECT slot, tx_index, block_time, confirmation_status FROM transactions",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(slot, 300_000_002);
+        assert_eq!(tx_index, 1);
+        assert_eq!(block_time, 1_729_300_000);
+        assert_eq!(status, "finalized");
+        let accounts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transaction_accounts")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(accounts, 2);
+
+        let fee: i64 =
+            sqlx::query_scalar("SELECT delta FROM balance_changes WHERE mint = ?1 AND delta != 0")
+                .bind(SOL_MINT)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(fee, -5000);
+        let kind: String = sqlx::query_scalar("SELECT kind FROM ore_events")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(kind, "close");
+
+        let cursors = load_cursors(&db).await.unwrap();
+        assert_eq!(
+            cursors,
+            vec![
+                (
+                    "accounts/oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ".to_string(),
+                    300_000_001
+                ),
+                ("blocks".to_string(), 300_000_002),
+                ("slots".to_string(), 300_000_002),
+            ]
+        );
+    }
+}
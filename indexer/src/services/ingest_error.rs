@@ -0,0 +1,25 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum IngestError {
+    Database(sqlx::Error),
+    // The source could not be set up, e.g. an invalid replay speed
+    Source(String),
+}
+
+impl From<sqlx::Error> for IngestError {
+    fn from(err: sqlx::Error) -> Self {
+        IngestError::Database(err)
+    }
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::Database(err) => write!(f, "database error : {}", err),
+            IngestError::Source(err) => write!(f, "source error : {}", err),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
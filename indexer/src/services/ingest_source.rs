@@ -0,0 +1,207 @@
+mod file_replay;
+#[cfg(feature = "geyser")]
+mod geyser;
+mod recorder;
+#[cfg(unix)]
+mod socket;
+mod websocket;
+
+pub use file_replay::*;
+#[cfg(feature = "geyser")]
+pub use geyser::*;
+pub use recorder::*;
+#[cfg(unix)]
+pub use socket::*;
+pub use websocket::*;
+
+use std::{collections::HashMap, time::Duration};
+
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccount;
+use solana_client::rpc_response::RpcKeyedAccount;
+use solana_sdk::{account::Account, pubkey::Pubkey, transaction::VersionedTransaction};
+use solana_transaction_status::{UiConfirmedBlock, UiTransactionStatusMeta};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::AccountUpdate;
+
+const SOURCE_CHANNEL_SIZE: usize = 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub enum IngestEvent {
+    // Every account of a program at one slot, replaces the bootstrap
+    Snapshot {
+        program_id: Pubkey,
+        slot: u64,
+        accounts: Vec<(Pubkey, Account)>,
+    },
+    Account(AccountUpdate),
+    Block(BlockUpdate),
+    Slot(SlotUpdate),
+    // A subscription went up or down, named like the streams of `IngestState`
+    Connection {
+        stream: String,
+        connected: bool,
+    },
+}
+
+// Transactions of a block that mention the indexed programs
+#[derive(Debug, Clone)]
+pub struct BlockUpdate {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+    pub transactions: Vec<TransactionUpdate>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionUpdate {
+    // Position in the whole block, None when the source could not tell it
+    pub tx_index: Option<usize>,
+    pub tx: VersionedTransaction,
+    pub meta: Option<UiTransactionStatusMeta>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SlotStatus {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotUpdate {
+    pub slot: u64,
+    pub parent: Option<u64>,
+    pub status: SlotStatus,
+}
+
+pub type IngestStream = BoxStream<'static, IngestEvent>;
+
+// Where account updates, blocks and slot statuses come from. The stream ends once the
+// source is exhausted or `shutdown` is cancelled, reconnecting is up to the source.
+pub trait IngestSource: Send {
+    fn name(&self) -> &'static str;
+
+    fn start(self: Box<Self>, shutdown: CancellationToken) -> IngestStream;
+}
+
+// One line of a recorded stream, in the shape the RPC notifications come in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum IngestRecord {
+    #[serde(rename_all = "camelCase")]
+    Snapshot {
+        program_id: String,
+        slot: u64,
+        accounts: Vec<RpcKeyedAccount>,
+    },
+    Account {
+        slot: u64,
+        pubkey: String,
+        account: UiAccount,
+    },
+    #[serde(rename_all = "camelCase")]
+    Block {
+        slot: u64,
+        block: UiConfirmedBlock,
+        // Positions of `block.transactions` in the whole block, from sources that know them
+        // without the signatures of the block
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tx_indexes: Option<Vec<Option<usize>>>,
+    },
+    Slot(SlotUpdate),
+}
+
+impl IngestRecord {
+    pub fn into_event(self) -> Option<IngestEvent> {
+        let event = match self {
+            IngestRecord::Snapshot {
+                program_id,
+                slot,
+                accounts,
+            } => IngestEvent::Snapshot {
+                program_id: program_id.parse().ok()?,
+                slot,
+                accounts: accounts
+                    .into_iter()
+                    .filter_map(|keyed| Some((keyed.pubkey.parse().ok()?, keyed.account.decode()?)))
+                    .collect(),
+            },
+            IngestRecord::Account {
+                slot,
+                pubkey,
+                account,
+            } => IngestEvent::Account(AccountUpdate {
+                pubkey: pubkey.parse().ok()?,
+                account: account.decode()?,
+                slot,
+            }),
+            IngestRecord::Block {
+                slot,
+                block,
+                tx_indexes,
+            } => {
+                // A filtered notification only holds some transactions, their position
+                // comes from the signatures of the whole block
+                let positions: HashMap<String, usize> = block
+                    .signatures
+                    .unwrap_or_default()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, signature)| (signature, position))
+                    .collect();
+                let mut tx_indexes = tx_indexes.map(|indexes| indexes.into_iter());
+                IngestEvent::Block(BlockUpdate {
+                    slot,
+                    block_time: block.block_time,
+                    block_height: block.block_height,
+                    transactions: block
+                        .transactions
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|tx| {
+                            // Taken first so it stays aligned with the transactions
+                            let known = tx_indexes.as_mut().map(|indexes| indexes.next().flatten());
+                            let decoded: VersionedTransaction = tx.transaction.decode()?;
+                            let signature = decoded.signatures.first()?.to_string();
+                            let tx_index = match known {
+                                Some(known) => known,
+                                None => positions.get(&signature).copied(),
+                            };
+                            Some(TransactionUpdate {
+                                tx_index,
+                                tx: decoded,
+                                meta: tx.meta,
+                            })
+                        })
+                        .collect(),
+                })
+            }
+            IngestRecord::Slot(update) => IngestEvent::Slot(update),
+        };
+        Some(event)
+    }
+}
+
+// false once the indexer stopped listening
+async fn send_connection(tx: &mpsc::Sender<IngestEvent>, stream: &str, connected: bool) -> bool {
+    let event = IngestEvent::Connection {
+        stream: stream.to_string(),
+        connected,
+    };
+    tx.send(event).await.is_ok()
+}
+
+async fn reconnect_delay(delay: &mut Duration, shutdown: &CancellationToken) {
+    tokio::select! {
+        _ = shutdown.cancelled() => {}
+        _ = tokio::time::sleep(*delay) => {}
+    }
+    *delay = (*delay * 2).min(MAX_RECONNECT_DELAY);
+}
@@ -0,0 +1,197 @@
+use std::{path::PathBuf, time::Duration};
+
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+    time::Instant,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+use super::{IngestEvent, IngestSource, IngestStream, RecordedLine, SOURCE_CHANNEL_SIZE};
+
+// A recorded stream, one `RecordedLine` per line
+#[derive(Debug, Clone)]
+pub struct FileReplaySource {
+    pub path: PathBuf,
+    // Multiplier of the recorded pace, as fast as possible when unset
+    pub speed: Option<f64>,
+}
+
+impl FileReplaySource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            speed: None,
+        }
+    }
+}
+
+impl IngestSource for FileReplaySource {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn start(self: Box<Self>, shutdown: CancellationToken) -> IngestStream {
+        let (tx, rx) = mpsc::channel(SOURCE_CHANNEL_SIZE);
+        tokio::task::spawn(replay(*self, tx, shutdown));
+        Box::pin(ReceiverStream::new(rx))
+    }
+}
+
+async fn replay(
+    source: FileReplaySource,
+    tx: mpsc::Sender<IngestEvent>,
+    shutdown: CancellationToken,
+) {
+    let path = source.path;
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            tracing::error!("[!] Failed to open {} : {}", path.display(), err);
+            return;
+        }
+    };
+    let mut lines = BufReader::new(file).lines();
+    let mut line_number = 0;
+    // When the first timed line was replayed and its recording time
+    let mut clock: Option<(Instant, u64)> = None;
+    loop {
+        let line = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            line = lines.next_line() => line,
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                tracing::error!("[!] Failed to read {} : {}", path.display(), err);
+                break;
+            }
+        };
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(line) = serde_json::from_str::<RecordedLine>(&line) else {
+            tracing::error!("[!] Invalid record at {}:{}", path.display(), line_number);
+            continue;
+        };
+        if let (Some(speed), Some(recorded_at)) = (source.speed, line.recorded_at) {
+            let (started, first) = *clock.get_or_insert((Instant::now(), recorded_at));
+            let offset = recorded_at.saturating_sub(first) as f64 / 1000.0 / speed;
+            let due = started + Duration::from_secs_f64(offset);
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = tokio::time::sleep_until(due) => {}
+            }
+        }
+        let Some(event) = line.record.into_event() else {
+            tracing::error!(
+                "[!] Undecodable record at {}:{}",
+                path.display(),
+                line_number
+            );
+            continue;
+        };
+        if tx.send(event).await.is_err() {
+            return;
+        }
+    }
+    tracing::info!("Replayed {} lines of {}", line_number, path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use solana_sdk::{pubkey, pubkey::Pubkey};
+
+    use super::*;
+    use crate::services::{IngestRecord, RecordedLine, SlotStatus, SlotUpdate};
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/ingest/stream.ndjson");
+    const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+    #[tokio::test]
+    async fn replay_recorded_stream() {
+        let source = Box::new(FileReplaySource::new(FIXTURE));
+        let events: Vec<IngestEvent> = source.start(CancellationToken::new()).collect().await;
+        assert_eq!(events.len(), 4);
+
+        let IngestEvent::Snapshot {
+            program_id,
+            slot,
+            accounts,
+        } = &events[0]
+        else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!((*program_id, *slot), (PROGRAM_ID, 300_000_000));
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].1.owner, PROGRAM_ID);
+
+        let IngestEvent::Account(update) = &events[1] else {
+            panic!("expected an account update");
+        };
+        assert_eq!(update.slot, 300_000_001);
+        assert_eq!(update.account.data, vec![1, 2, 3, 4]);
+
+        let IngestEvent::Block(block) = &events[2] else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.slot, 300_000_002);
+        assert_eq!(block.block_time, Some(1_729_300_000));
+        assert_eq!(block.transactions.len(), 1);
+        let tx = &block.transactions[0];
+        assert_eq!(tx.tx_index, Some(1));
+        assert_eq!(tx.tx.message.static_account_keys()[1], PROGRAM_ID);
+        assert!(tx.meta.is_some());
+
+        let IngestEvent::Slot(update) = &events[3] else {
+            panic!("expected a slot update");
+        };
+        assert_eq!(update.status, SlotStatus::Finalized);
+        assert_eq!(update.slot, 300_000_002);
+    }
+
+    // The clock only moves when the replay sleeps
+    #[tokio::test(start_paused = true)]
+    async fn replay_at_recorded_pace() {
+        let path =
+            std::env::temp_dir().join(format!("argos-recording-{}.ndjson", std::process::id()));
+        let line = |recorded_at, slot| {
+            let line = RecordedLine {
+                recorded_at: Some(recorded_at),
+                record: IngestRecord::Slot(SlotUpdate {
+                    slot,
+                    parent: None,
+                    status: SlotStatus::Confirmed,
+                }),
+            };
+            serde_json::to_string(&line).unwrap()
+        };
+        std::fs::write(&path, format!("{}\n{}\n", line(0, 1), line(200, 2))).unwrap();
+
+        let mut source = FileReplaySource::new(&path);
+        source.speed = Some(2.0);
+        let started = Instant::now();
+        let events: Vec<IngestEvent> = Box::new(source)
+            .start(CancellationToken::new())
+            .collect()
+            .await;
+        let elapsed = started.elapsed();
+        std::fs::remove_file(&path).unwrap();
+
+        let slots: Vec<u64> = events
+            .iter()
+            .map(|event| match event {
+                IngestEvent::Slot(update) => update.slot,
+                _ => panic!("expected a slot update"),
+            })
+            .collect();
+        assert_eq!(slots, vec![1, 2]);
+        // 200ms recorded, played twice as fast
+        assert_eq!(elapsed, Duration::from_millis(100));
+    }
+}
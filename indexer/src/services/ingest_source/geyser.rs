@@ -0,0 +1,534 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use futures::{SinkExt, StreamExt};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use solana_transaction_status::{TransactionWithStatusMeta, UiTransactionStatusMeta};
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use yellowstone_grpc_client::GeyserGrpcClient;
+use yellowstone_grpc_proto::{
+    convert_from,
+    prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
+        SubscribeRequestFilterAccounts, SubscribeRequestFilterBlocksMeta,
+        SubscribeRequestFilterSlots, SubscribeRequestFilterTransactions, SubscribeUpdate,
+        SubscribeUpdateTransactionInfo,
+    },
+};
+
+use super::{
+    reconnect_delay, send_connection, BlockUpdate, IngestEvent, IngestSource, IngestStream,
+    SlotStatus, SlotUpdate, TransactionUpdate, RECONNECT_DELAY, SOURCE_CHANNEL_SIZE,
+};
+use crate::services::{account_stream, AccountUpdate, BLOCK_STREAM, SLOT_STREAM};
+
+const FILTER: &str = "indexed";
+const TRACKED_FILTER: &str = "tracked";
+
+// Yellowstone gRPC stream. There is no bootstrap, accounts only arrive once written,
+// so the initial state comes from a snapshot import.
+#[derive(Debug, Clone)]
+pub struct GeyserSource {
+    pub endpoint: String,
+    pub x_token: Option<String>,
+    pub programs: Vec<Pubkey>,
+    // Accounts of the programs already indexed. Once closed or reassigned they no longer
+    // match the owner filter, so they are subscribed by pubkey too.
+    pub tracked: HashSet<Pubkey>,
+}
+
+impl GeyserSource {
+    pub fn new(endpoint: impl Into<String>, programs: Vec<Pubkey>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            x_token: None,
+            programs,
+            tracked: HashSet::new(),
+        }
+    }
+
+    fn subscribe_request(&self) -> SubscribeRequest {
+        let programs: Vec<String> = self.programs.iter().map(|p| p.to_string()).collect();
+        let mut accounts = HashMap::from([(
+            FILTER.to_string(),
+            SubscribeRequestFilterAccounts {
+                owner: programs.clone(),
+                ..Default::default()
+            },
+        )]);
+        // Filters match when any of them does, the fields of one filter all have to
+        if !self.tracked.is_empty() {
+            accounts.insert(
+                TRACKED_FILTER.to_string(),
+                SubscribeRequestFilterAccounts {
+                    account: self.tracked.iter().map(|p| p.to_string()).collect(),
+                    ..Default::default()
+                },
+            );
+        }
+        SubscribeRequest {
+            accounts,
+            transactions: HashMap::from([(
+                FILTER.to_string(),
+                SubscribeRequestFilterTransactions {
+                    vote: Some(false),
+                    account_include: programs,
+                    ..Default::default()
+                },
+            )]),
+            slots: HashMap::from([(FILTER.to_string(), SubscribeRequestFilterSlots::default())]),
+            blocks_meta: HashMap::from([(
+                FILTER.to_string(),
+                SubscribeRequestFilterBlocksMeta::default(),
+            )]),
+            commitment: Some(CommitmentLevel::Confirmed as i32),
+            ..Default::default()
+        }
+    }
+
+    // Accounts of the programs in the archive, read again on every restart
+    pub async fn load_tracked(&mut self, db: &SqlitePool) -> Result<(), sqlx::Error> {
+        self.tracked.clear();
+        for program_id in &self.programs {
+            let program_id = program_id.to_string();
+            let ids = sqlx::query_scalar!(
+                "SELECT id FROM accounts_archive WHERE owner = ?",
+                program_id
+            )
+            .fetch_all(db)
+            .await?;
+            self.tracked
+                .extend(ids.iter().filter_map(|id| id.parse::<Pubkey>().ok()));
+        }
+        Ok(())
+    }
+
+    // Whether the tracked set changed with this version of the account
+    fn track(&mut self, update: &AccountUpdate) -> bool {
+        if update.account.lamports > 0 && self.programs.contains(&update.account.owner) {
+            self.tracked.insert(update.pubkey)
+        } else {
+            self.tracked.remove(&update.pubkey)
+        }
+    }
+
+    // All of them share the one subscription
+    fn streams(&self) -> Vec<String> {
+        let mut streams: Vec<String> = self.programs.iter().map(account_stream).collect();
+        streams.extend([BLOCK_STREAM.to_string(), SLOT_STREAM.to_string()]);
+        streams
+    }
+}
+
+impl IngestSource for GeyserSource {
+    fn name(&self) -> &'static str {
+        "geyser"
+    }
+
+    fn start(self: Box<Self>, shutdown: CancellationToken) -> IngestStream {
+        let (tx, rx) = mpsc::channel(SOURCE_CHANNEL_SIZE);
+        tokio::task::spawn(geyser_stream(*self, tx, shutdown));
+        Box::pin(ReceiverStream::new(rx))
+    }
+}
+
+async fn geyser_stream(
+    mut source: GeyserSource,
+    tx: mpsc::Sender<IngestEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut delay = RECONNECT_DELAY;
+    while !shutdown.is_cancelled() {
+        let client = match GeyserGrpcClient::build_from_shared(source.endpoint.clone())
+            .and_then(|builder| builder.x_token(source.x_token.clone()))
+        {
+            Ok(builder) => builder.connect().await,
+            Err(err) => {
+                tracing::error!("[!] Invalid geyser endpoint {} : {}", source.endpoint, err);
+                return;
+            }
+        };
+        let mut client = match client {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!("[!] Failed to connect to geyser : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        let (mut sink, mut stream) = match client
+            .subscribe_with_request(Some(source.subscribe_request()))
+            .await
+        {
+            Ok(res) => res,
+            Err(err) => {
+                tracing::error!("[!] Failed to subscribe to geyser : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        delay = RECONNECT_DELAY;
+        if !set_connected(&source, &tx, true).await {
+            return;
+        }
+
+        let mut blocks = BlockAssembler::default();
+        loop {
+            let update = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                update = stream.next() => update,
+            };
+            let update = match update {
+                Some(Ok(update)) => update,
+                Some(Err(err)) => {
+                    tracing::error!("[!] Geyser stream failed : {}", err);
+                    break;
+                }
+                None => {
+                    tracing::error!("[!] Geyser stream dropped");
+                    break;
+                }
+            };
+            let mut resubscribe = false;
+            for event in blocks.push(update) {
+                if let IngestEvent::Account(update) = &event {
+                    resubscribe |= source.track(update);
+                }
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            // A new request replaces the filters of the subscription
+            if resubscribe {
+                if let Err(err) = sink.send(source.subscribe_request()).await {
+                    tracing::error!("[!] Failed to update the geyser subscription : {}", err);
+                    break;
+                }
+            }
+        }
+        if !set_connected(&source, &tx, false).await {
+            return;
+        }
+    }
+}
+
+// false once the indexer stopped listening
+async fn set_connected(
+    source: &GeyserSource,
+    tx: &mpsc::Sender<IngestEvent>,
+    connected: bool,
+) -> bool {
+    for stream in source.streams() {
+        if !send_connection(tx, &stream, connected).await {
+            return false;
+        }
+    }
+    true
+}
+
+// Transactions arrive one by one, the block meta of their slot closes the block
+#[derive(Debug, Default)]
+struct BlockAssembler {
+    pending: BTreeMap<u64, Vec<TransactionUpdate>>,
+}
+
+impl BlockAssembler {
+    fn push(&mut self, update: SubscribeUpdate) -> Vec<IngestEvent> {
+        let Some(update) = update.update_oneof else {
+            return vec![];
+        };
+        match update {
+            UpdateOneof::Account(update) => {
+                let Some(info) = update.account else {
+                    return vec![];
+                };
+                let (Ok(pubkey), Ok(owner)) = (
+                    Pubkey::try_from(info.pubkey.as_slice()),
+                    Pubkey::try_from(info.owner.as_slice()),
+                ) else {
+                    return vec![];
+                };
+                vec![IngestEvent::Account(AccountUpdate {
+                    pubkey,
+                    account: Account {
+                        lamports: info.lamports,
+                        data: info.data,
+                        owner,
+                        executable: info.executable,
+                        rent_epoch: info.rent_epoch,
+                    },
+                    slot: update.slot,
+                })]
+            }
+            UpdateOneof::Transaction(update) => {
+                let Some(info) = update.transaction else {
+                    return vec![];
+                };
+                match transaction_update(info) {
+                    Some(tx) => self.pending.entry(update.slot).or_default().push(tx),
+                    None => tracing::error!("[!] Failed to decode geyser transaction"),
+                }
+                vec![]
+            }
+            UpdateOneof::BlockMeta(meta) => {
+                let mut transactions = self.pending.remove(&meta.slot).unwrap_or_default();
+                transactions.sort_by_key(|tx| tx.tx_index);
+                vec![IngestEvent::Block(BlockUpdate {
+                    slot: meta.slot,
+                    block_time: meta.block_time.map(|t| t.timestamp),
+                    block_height: meta.block_height.map(|h| h.block_height),
+                    transactions,
+                })]
+            }
+            UpdateOneof::Slot(update) => {
+                let status = match CommitmentLevel::try_from(update.status) {
+                    Ok(CommitmentLevel::Processed) => SlotStatus::Processed,
+                    Ok(CommitmentLevel::Confirmed) => SlotStatus::Confirmed,
+                    Ok(CommitmentLevel::Finalized) => SlotStatus::Finalized,
+                    Err(_) => return vec![],
+                };
+                if status == SlotStatus::Finalized {
+                    // Forked off slots never get their block meta
+                    self.pending.retain(|slot, _| *slot > update.slot);
+                }
+                vec![IngestEvent::Slot(SlotUpdate {
+                    slot: update.slot,
+                    parent: update.parent,
+                    status,
+                })]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn transaction_update(info: SubscribeUpdateTransactionInfo) -> Option<TransactionUpdate> {
+    let tx_index = Some(info.index as usize);
+    let TransactionWithStatusMeta::Complete(tx) = convert_from::create_tx_with_meta(info).ok()?
+    else {
+        return None;
+    };
+    Some(TransactionUpdate {
+        tx_index,
+        tx: tx.transaction,
+        meta: Some(UiTransactionStatusMeta::from(tx.meta)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, pin::Pin};
+
+    use futures::Stream;
+    use solana_sdk::pubkey;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{transport::Server, Request, Response, Status, Streaming};
+    use yellowstone_grpc_proto::prelude::{
+        geyser_server::{Geyser, GeyserServer},
+        BlockHeight, GetBlockHeightRequest, GetBlockHeightResponse, GetLatestBlockhashRequest,
+        GetLatestBlockhashResponse, GetSlotRequest, GetSlotResponse, GetVersionRequest,
+        GetVersionResponse, IsBlockhashValidRequest, IsBlockhashValidResponse, PingRequest,
+        PongResponse, SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateBlockMeta,
+        SubscribeUpdateSlot, UnixTimestamp,
+    };
+
+    use super::*;
+
+    const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+    // Replies to every subscription with the same scripted updates
+    struct MockGeyser {
+        updates: Vec<SubscribeUpdate>,
+    }
+
+    type UpdateStream = Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, Status>> + Send>>;
+
+    #[tonic::async_trait]
+    impl Geyser for MockGeyser {
+        type SubscribeStream = UpdateStream;
+
+        async fn subscribe(
+            &self,
+            _request: Request<Streaming<SubscribeRequest>>,
+        ) -> Result<Response<Self::SubscribeStream>, Status> {
+            let updates = self.updates.clone().into_iter().map(Ok);
+            // Pending forever afterwards, like a live stream
+            let stream = futures::stream::iter(updates).chain(futures::stream::pending());
+            Ok(Response::new(Box::pin(stream)))
+        }
+
+        async fn ping(&self, _: Request<PingRequest>) -> Result<Response<PongResponse>, Status> {
+            Err(Status::unimplemented("ping"))
+        }
+
+        async fn get_latest_blockhash(
+            &self,
+            _: Request<GetLatestBlockhashRequest>,
+        ) -> Result<Response<GetLatestBlockhashResponse>, Status> {
+            Err(Status::unimplemented("get_latest_blockhash"))
+        }
+
+        async fn get_block_height(
+            &self,
+            _: Request<GetBlockHeightRequest>,
+        ) -> Result<Response<GetBlockHeightResponse>, Status> {
+            Err(Status::unimplemented("get_block_height"))
+        }
+
+        async fn get_slot(
+            &self,
+            _: Request<GetSlotRequest>,
+        ) -> Result<Response<GetSlotResponse>, Status> {
+            Err(Status::unimplemented("get_slot"))
+        }
+
+        async fn is_blockhash_valid(
+            &self,
+            _: Request<IsBlockhashValidRequest>,
+        ) -> Result<Response<IsBlockhashValidResponse>, Status> {
+            Err(Status::unimplemented("is_blockhash_valid"))
+        }
+
+        async fn get_version(
+            &self,
+            _: Request<GetVersionRequest>,
+        ) -> Result<Response<GetVersionResponse>, Status> {
+            Err(Status::unimplemented("get_version"))
+        }
+    }
+
+    async fn serve(updates: Vec<SubscribeUpdate>) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn(
+            Server::builder()
+                .add_service(GeyserServer::new(MockGeyser { updates }))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        addr
+    }
+
+    fn update(update: UpdateOneof) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![FILTER.to_string()],
+            update_oneof: Some(update),
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_from_mock_server() {
+        let account = Pubkey::new_unique();
+        let addr = serve(vec![
+            update(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: account.to_bytes().to_vec(),
+                    lamports: 1_000,
+                    owner: PROGRAM_ID.to_bytes().to_vec(),
+                    data: vec![1, 2, 3],
+                    write_version: 7,
+                    ..Default::default()
+                }),
+                slot: 42,
+                ..Default::default()
+            })),
+            update(UpdateOneof::BlockMeta(SubscribeUpdateBlockMeta {
+                slot: 42,
+                block_time: Some(UnixTimestamp {
+                    timestamp: 1_729_300_000,
+                }),
+                block_height: Some(BlockHeight { block_height: 40 }),
+                ..Default::default()
+            })),
+            update(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot: 42,
+                parent: Some(41),
+                status: CommitmentLevel::Finalized as i32,
+            })),
+        ])
+        .await;
+
+        let shutdown = CancellationToken::new();
+        let source = Box::new(GeyserSource::new(
+            format!("http://{}", addr),
+            vec![PROGRAM_ID],
+        ));
+        let events: Vec<IngestEvent> = source
+            .start(shutdown.clone())
+            .filter(|event| {
+                futures::future::ready(!matches!(event, IngestEvent::Connection { .. }))
+            })
+            .take(3)
+            .collect()
+            .await;
+        shutdown.cancel();
+
+        let IngestEvent::Account(update) = &events[0] else {
+            panic!("expected an account update");
+        };
+        assert_eq!((update.pubkey, update.slot), (account, 42));
+        assert_eq!(update.account.owner, PROGRAM_ID);
+        assert_eq!(update.account.data, vec![1, 2, 3]);
+
+        let IngestEvent::Block(block) = &events[1] else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.block_time, Some(1_729_300_000));
+        assert_eq!(block.block_height, Some(40));
+        assert!(block.transactions.is_empty());
+
+        let IngestEvent::Slot(slot) = &events[2] else {
+            panic!("expected a slot update");
+        };
+        assert_eq!(slot.status, SlotStatus::Finalized);
+        assert_eq!(slot.parent, Some(41));
+    }
+
+    #[test]
+    fn tracked_accounts_filter() {
+        let mut source = GeyserSource::new("http://127.0.0.1:0", vec![PROGRAM_ID]);
+        assert!(!source
+            .subscribe_request()
+            .accounts
+            .contains_key(TRACKED_FILTER));
+
+        let pubkey = Pubkey::new_unique();
+        let mut update = AccountUpdate {
+            pubkey,
+            account: Account {
+                lamports: 1_000,
+                owner: PROGRAM_ID,
+                ..Default::default()
+            },
+            slot: 42,
+        };
+        assert!(source.track(&update));
+        assert!(!source.track(&update));
+        assert_eq!(
+            source.subscribe_request().accounts[TRACKED_FILTER].account,
+            vec![pubkey.to_string()]
+        );
+
+        // Closed, the update still matched by pubkey
+        update.account = Account::default();
+        assert!(source.track(&update));
+        assert!(!source
+            .subscribe_request()
+            .accounts
+            .contains_key(TRACKED_FILTER));
+    }
+
+    #[test]
+    fn finalized_slot_drops_forked_transactions() {
+        let mut blocks = BlockAssembler::default();
+        blocks.pending.insert(10, vec![]);
+        blocks.pending.insert(12, vec![]);
+        blocks.push(update(UpdateOneof::Slot(SubscribeUpdateSlot {
+            slot: 11,
+            parent: Some(10),
+            status: CommitmentLevel::Finalized as i32,
+        })));
+        assert_eq!(blocks.pending.keys().copied().collect::<Vec<_>>(), vec![12]);
+    }
+}
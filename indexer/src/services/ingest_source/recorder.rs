@@ -0,0 +1,62 @@
+use std::{path::Path, sync::Arc, time::Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::Mutex,
+};
+
+use super::IngestRecord;
+
+// A line of a recording, `recordedAt` is in milliseconds since the recording started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedLine<R = IngestRecord> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<u64>,
+    #[serde(flatten)]
+    pub record: R,
+}
+
+// Appends the notifications a source receives to an NDJSON file `FileReplaySource` plays back
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    started: Instant,
+    file: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub async fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::create(path).await?;
+        Ok(Self {
+            started: Instant::now(),
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    pub async fn record(&self, record: &IngestRecord) {
+        let line = RecordedLine {
+            recorded_at: Some(self.started.elapsed().as_millis() as u64),
+            record,
+        };
+        let mut line = match serde_json::to_vec(&line) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!("[!] Failed to serialize record : {}", err);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        // Flushed every line so a killed process keeps what it received
+        if let Err(err) = async {
+            file.write_all(&line).await?;
+            file.flush().await
+        }
+        .await
+        {
+            tracing::error!("[!] Failed to write record : {}", err);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use solana_sdk::pubkey::Pubkey;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::UnixStream,
+    sync::mpsc,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    reconnect_delay, send_connection, IngestEvent, IngestRecord, IngestSource, IngestStream,
+    RECONNECT_DELAY, SOURCE_CHANNEL_SIZE,
+};
+use crate::services::{account_stream, BLOCK_STREAM, SLOT_STREAM};
+
+// Records the geyser plugin of a local validator pushes over its Unix socket, one
+// `IngestRecord` per line. The plugin only sends the slots once confirmed.
+#[derive(Debug, Clone)]
+pub struct SocketSource {
+    pub path: PathBuf,
+    pub programs: Vec<Pubkey>,
+}
+
+impl SocketSource {
+    pub fn new(path: impl Into<PathBuf>, programs: Vec<Pubkey>) -> Self {
+        Self {
+            path: path.into(),
+            programs,
+        }
+    }
+
+    // All of them share the one connection
+    fn streams(&self) -> Vec<String> {
+        let mut streams: Vec<String> = self.programs.iter().map(account_stream).collect();
+        streams.extend([BLOCK_STREAM.to_string(), SLOT_STREAM.to_string()]);
+        streams
+    }
+}
+
+impl IngestSource for SocketSource {
+    fn name(&self) -> &'static str {
+        "socket"
+    }
+
+    fn start(self: Box<Self>, shutdown: CancellationToken) -> IngestStream {
+        let (tx, rx) = mpsc::channel(SOURCE_CHANNEL_SIZE);
+        tokio::task::spawn(socket_stream(*self, tx, shutdown));
+        Box::pin(ReceiverStream::new(rx))
+    }
+}
+
+async fn socket_stream(
+    source: SocketSource,
+    tx: mpsc::Sender<IngestEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut delay = RECONNECT_DELAY;
+    while !shutdown.is_cancelled() {
+        let stream = match UnixStream::connect(&source.path).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::error!(
+                    "[!] Failed to connect to {} : {}",
+                    source.path.display(),
+                    err
+                );
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        delay = RECONNECT_DELAY;
+        if !set_connected(&source, &tx, true).await {
+            return;
+        }
+
+        let mut lines = BufReader::new(stream).lines();
+        loop {
+            let line = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                line = lines.next_line() => line,
+            };
+            let line = match line {
+                Ok(Some(line)) => line,
+                Ok(None) => {
+                    tracing::error!("[!] Plugin socket closed");
+                    break;
+                }
+                Err(err) => {
+                    tracing::error!("[!] Failed to read the plugin socket : {}", err);
+                    break;
+                }
+            };
+            let event = serde_json::from_str::<IngestRecord>(&line)
+                .ok()
+                .and_then(IngestRecord::into_event);
+            let Some(event) = event else {
+                tracing::error!("[!] Invalid record from the plugin socket");
+                continue;
+            };
+            if tx.send(event).await.is_err() {
+                return;
+            }
+        }
+        if !set_connected(&source, &tx, false).await {
+            return;
+        }
+    }
+}
+
+// false once the indexer stopped listening
+async fn set_connected(
+    source: &SocketSource,
+    tx: &mpsc::Sender<IngestEvent>,
+    connected: bool,
+) -> bool {
+    for stream in source.streams() {
+        if !send_connection(tx, &stream, connected).await {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use solana_sdk::pubkey;
+    use tokio::{io::AsyncWriteExt, net::UnixListener};
+
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/ingest/stream.ndjson");
+    const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+    #[tokio::test]
+    async fn records_from_the_plugin_socket() {
+        let path = std::env::temp_dir().join(format!("argos-socket-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let lines = std::fs::read_to_string(FIXTURE).unwrap();
+            stream.write_all(lines.as_bytes()).await.unwrap();
+            // The plugin gives the positions instead of the signatures of the block
+            let mut block: serde_json::Value =
+                serde_json::from_str(lines.lines().nth(2).unwrap()).unwrap();
+            block["block"]["signatures"].take();
+            block["txIndexes"] = serde_json::json!([5]);
+            stream
+                .write_all(format!("{}\n", block).as_bytes())
+                .await
+                .unwrap();
+            // Open afterwards, like a plugin waiting for the next slot
+            std::future::pending::<()>().await;
+        });
+
+        let shutdown = CancellationToken::new();
+        let source = Box::new(SocketSource::new(&path, vec![PROGRAM_ID]));
+        let events: Vec<IngestEvent> = source
+            .start(shutdown.clone())
+            .filter(|event| {
+                futures::future::ready(!matches!(event, IngestEvent::Connection { .. }))
+            })
+            .take(5)
+            .collect()
+            .await;
+        shutdown.cancel();
+
+        assert!(matches!(
+            events[0],
+            IngestEvent::Snapshot {
+                slot: 300_000_000,
+                ..
+            }
+        ));
+        assert!(matches!(events[1], IngestEvent::Account(_)));
+        let IngestEvent::Block(block) = &events[2] else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.transactions[0].tx_index, Some(1));
+        assert!(matches!(events[3], IngestEvent::Slot(_)));
+        let IngestEvent::Block(block) = &events[4] else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.transactions[0].tx_index, Some(5));
+    }
+}
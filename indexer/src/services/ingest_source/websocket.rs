@@ -0,0 +1,727 @@
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::{
+        RpcAccountInfoConfig, RpcBlockSubscribeConfig, RpcBlockSubscribeFilter,
+        RpcProgramAccountsConfig,
+    },
+    rpc_filter::RpcFilterType,
+    rpc_request::RpcRequest,
+    rpc_response::{OptionalContext, RpcKeyedAccount},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedTransactionWithStatusMeta, TransactionDetails,
+    UiConfirmedBlock, UiTransactionEncoding,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    reconnect_delay, send_connection, IngestEvent, IngestRecord, IngestSource, IngestStream,
+    SlotStatus, SlotUpdate, RECONNECT_DELAY, SOURCE_CHANNEL_SIZE,
+};
+use crate::services::{account_stream, LimitedRequestClient, BLOCK_STREAM, SLOT_STREAM};
+
+// What the program subscription and the bootstrap ask upstream for
+#[derive(Debug, Clone)]
+pub struct AccountSubscription {
+    pub url: String,
+    pub filters: Option<Vec<RpcFilterType>>,
+    // Binary encodings only, the data is stored raw
+    pub encoding: UiAccountEncoding,
+    pub commitment: Option<CommitmentConfig>,
+}
+
+impl Default for AccountSubscription {
+    fn default() -> Self {
+        Self {
+            url: crate::SOLANA_ACCOUNT_RPC_WS.to_string(),
+            filters: None,
+            encoding: UiAccountEncoding::Base64,
+            commitment: None,
+        }
+    }
+}
+
+impl AccountSubscription {
+    fn program_accounts_config(&self, encoding: UiAccountEncoding) -> RpcProgramAccountsConfig {
+        RpcProgramAccountsConfig {
+            sort_results: None,
+            filters: self.filters.clone(),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(encoding),
+                data_slice: None,
+                commitment: self.commitment,
+                min_context_slot: None,
+            },
+            with_context: Some(true),
+        }
+    }
+}
+
+// programSubscribe bootstrapped with getProgramAccounts, blockSubscribe and rootSubscribe
+#[derive(Debug, Clone)]
+pub struct WebsocketSource {
+    pub program_id: Pubkey,
+    pub rpc_url: String,
+    // Shares the rate limit of the proxy, for the block signatures
+    pub client: LimitedRequestClient,
+    pub accounts: AccountSubscription,
+    pub blocks_url: String,
+    // Last block written by a previous run, the blocks confirmed since are fetched first
+    pub block_cursor: Option<u64>,
+    pub recorder: Option<Recorder>,
+}
+
+impl WebsocketSource {
+    pub fn new(program_id: Pubkey, client: LimitedRequestClient) -> Self {
+        Self {
+            program_id,
+            rpc_url: crate::SOLANA_RPC.to_string(),
+            client,
+            accounts: AccountSubscription::default(),
+            blocks_url: crate::SOLANA_BLOCKS_RPC_WS.to_string(),
+            block_cursor: None,
+            recorder: None,
+        }
+    }
+}
+
+impl IngestSource for WebsocketSource {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    fn start(self: Box<Self>, shutdown: CancellationToken) -> IngestStream {
+        let source = *self;
+        let (tx, rx) = mpsc::channel(SOURCE_CHANNEL_SIZE);
+        tokio::task::spawn(program_stream(source.clone(), tx.clone(), shutdown.clone()));
+        tokio::task::spawn(block_stream(source.clone(), tx.clone(), shutdown.clone()));
+        tokio::task::spawn(root_stream(source, tx, shutdown));
+        Box::pin(ReceiverStream::new(rx))
+    }
+}
+
+async fn program_stream(
+    source: WebsocketSource,
+    tx: mpsc::Sender<IngestEvent>,
+    shutdown: CancellationToken,
+) {
+    let program_id = source.program_id;
+    let stream_name = account_stream(&program_id);
+    let mut delay = RECONNECT_DELAY;
+    while !shutdown.is_cancelled() {
+        let client = match PubsubClient::new(&source.accounts.url).await {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!("[!] Failed to connect account subscription : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        // Subscribed before the bootstrap, notifications queue up meanwhile
+        let config = source
+            .accounts
+            .program_accounts_config(source.accounts.encoding);
+        let (mut stream, unsubscribe) =
+            match client.program_subscribe(&program_id, Some(config)).await {
+                Ok(res) => res,
+                Err(err) => {
+                    tracing::error!("[!] Failed to subscribe to program : {}", err);
+                    reconnect_delay(&mut delay, &shutdown).await;
+                    continue;
+                }
+            };
+
+        // Updates missed while disconnected are only recovered by a new bootstrap. So are
+        // those after a snapshot import, only the accounts that changed are written again.
+        let rpc_client = RpcClient::new(source.rpc_url.clone());
+        tracing::info!("Fetching accounts");
+        let config = source
+            .accounts
+            .program_accounts_config(UiAccountEncoding::Base64Zstd);
+        let (bootstrap_slot, accounts) =
+            match fetch_program_accounts(&rpc_client, &program_id, config).await {
+                Ok(res) => res,
+                Err(err) => {
+                    tracing::error!("[!] Failed to fetch accounts : {}", err);
+                    reconnect_delay(&mut delay, &shutdown).await;
+                    continue;
+                }
+            };
+        tracing::info!("Got {} accounts at slot {}", accounts.len(), bootstrap_slot);
+        let snapshot = IngestRecord::Snapshot {
+            program_id: program_id.to_string(),
+            slot: bootstrap_slot,
+            accounts,
+        };
+        if !send(&tx, &source.recorder, snapshot).await {
+            return;
+        }
+        delay = RECONNECT_DELAY;
+        if !send_connection(&tx, &stream_name, true).await {
+            return;
+        }
+
+        loop {
+            let msg = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    unsubscribe().await;
+                    tracing::info!("Account subscription of {} closed", program_id);
+                    return;
+                }
+                msg = stream.next() => msg,
+            };
+            let Some(msg) = msg else {
+                tracing::error!("[!] Account subscription of {} dropped", program_id);
+                if !send_connection(&tx, &stream_name, false).await {
+                    return;
+                }
+                break;
+            };
+            // Already part of the bootstrapped state
+            if msg.context.slot <= bootstrap_slot {
+                tracing::debug!(
+                    "Skipped account {} at slot {}",
+                    msg.value.pubkey,
+                    msg.context.slot
+                );
+                continue;
+            }
+            let update = IngestRecord::Account {
+                slot: msg.context.slot,
+                pubkey: msg.value.pubkey,
+                account: msg.value.account,
+            };
+            if !send(&tx, &source.recorder, update).await {
+                return;
+            }
+        }
+    }
+}
+
+async fn block_stream(
+    source: WebsocketSource,
+    tx: mpsc::Sender<IngestEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut delay = RECONNECT_DELAY;
+    let mut last_slot = source.block_cursor;
+    while !shutdown.is_cancelled() {
+        let client = match PubsubClient::new(&source.blocks_url).await {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!("[!] Failed to connect block subscription : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        let block_filter =
+            RpcBlockSubscribeFilter::MentionsAccountOrProgram(source.program_id.to_string());
+        let block_config = RpcBlockSubscribeConfig {
+            commitment: Some(CommitmentConfig::confirmed()),
+            encoding: Some(UiTransactionEncoding::Base64),
+            transaction_details: Some(TransactionDetails::Full),
+            show_rewards: Some(false),
+            max_supported_transaction_version: Some(0),
+        };
+        let (mut block_stream, unsubscribe) = match client
+            .block_subscribe(block_filter, Some(block_config))
+            .await
+        {
+            Ok(res) => res,
+            Err(err) => {
+                tracing::error!("[!] Failed to subscribe to program : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        if !send_connection(&tx, BLOCK_STREAM, true).await {
+            return;
+        }
+        // Subscribed first, so the blocks of the gap and the notifications overlap
+        if let Some(after) = last_slot {
+            match backfill_blocks(&source, &tx, after).await {
+                Backfill::Complete(slot) => last_slot = Some(slot),
+                // The notifications would leave the rest of the gap behind
+                Backfill::Partial(slot) => {
+                    last_slot = Some(slot);
+                    unsubscribe().await;
+                    if !send_connection(&tx, BLOCK_STREAM, false).await {
+                        return;
+                    }
+                    reconnect_delay(&mut delay, &shutdown).await;
+                    continue;
+                }
+                Backfill::Stopped => return,
+            }
+        }
+        delay = RECONNECT_DELAY;
+
+        loop {
+            let block = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    unsubscribe().await;
+                    return;
+                }
+                block = block_stream.next() => block,
+            };
+            let Some(block) = block else {
+                tracing::error!("[!] Block subscription dropped");
+                if !send_connection(&tx, BLOCK_STREAM, false).await {
+                    return;
+                }
+                break;
+            };
+            let slot = block.value.slot;
+            if last_slot.is_some_and(|last_slot| slot <= last_slot) {
+                tracing::debug!("Skipped backfilled block {}", slot);
+                continue;
+            }
+            let Some(mut block) = block.value.block else {
+                tracing::error!("[!] Missing block");
+                continue;
+            };
+            // The notification only holds the transactions mentioning the program, their
+            // position comes from the signatures of the whole block
+            match source.client.get_block_signatures(slot).await {
+                Ok(signatures) => block.signatures = Some(signatures),
+                Err(err) => {
+                    tracing::error!(
+                        "[!] Failed to fetch the signatures of block {} : {}",
+                        slot,
+                        err
+                    )
+                }
+            }
+            let record = IngestRecord::Block {
+                slot,
+                block,
+                tx_indexes: None,
+            };
+            if !send(&tx, &source.recorder, record).await {
+                return;
+            }
+            last_slot = Some(slot);
+        }
+    }
+}
+
+// Most slots getBlocks spans in one request
+const MAX_BLOCKS_RANGE: u64 = 500_000;
+
+enum Backfill {
+    // Every confirmed block up to the subscription was sent, through this slot
+    Complete(u64),
+    // Failed after this slot, the rest of the gap is left for the next connection
+    Partial(u64),
+    // The indexer stopped listening
+    Stopped,
+}
+
+// The confirmed blocks after `after`, reduced to the transactions mentioning the program like
+// the notifications. Those already finalized are sent as such, their roots were missed too.
+async fn backfill_blocks(
+    source: &WebsocketSource,
+    tx: &mpsc::Sender<IngestEvent>,
+    after: u64,
+) -> Backfill {
+    let (finalized, confirmed) = match tokio::try_join!(
+        source.client.get_slot("finalized"),
+        source.client.get_slot("confirmed")
+    ) {
+        Ok(slots) => slots,
+        Err(err) => {
+            tracing::error!("[!] Failed to fetch the slots to backfill to : {}", err);
+            return Backfill::Partial(after);
+        }
+    };
+    tracing::info!("Backfilling slots {} to {}", after + 1, confirmed);
+
+    let mut last_slot = after;
+    while last_slot < confirmed {
+        let end = confirmed.min(last_slot + MAX_BLOCKS_RANGE);
+        let params = json!([last_slot + 1, end, {"commitment": "confirmed"}]);
+        let slots: Vec<u64> = match rpc_result(source, "getBlocks", params).await {
+            Ok(slots) => slots,
+            Err(err) => {
+                tracing::error!(
+                    "[!] Failed to list the blocks after {} : {}",
+                    last_slot,
+                    err
+                );
+                return Backfill::Partial(last_slot);
+            }
+        };
+        for slot in slots {
+            let config = json!({
+                "encoding": "base64",
+                "maxSupportedTransactionVersion": 0,
+                "transactionDetails": "full",
+                "rewards": false,
+                "commitment": "confirmed",
+            });
+            let mut block: UiConfirmedBlock =
+                match rpc_result(source, "getBlock", json!([slot, config])).await {
+                    Ok(block) => block,
+                    Err(err) => {
+                        tracing::error!("[!] Failed to backfill block {} : {}", slot, err);
+                        return Backfill::Partial(last_slot);
+                    }
+                };
+            // Positions in the whole block, before the other transactions are left out
+            let (tx_indexes, transactions): (Vec<Option<usize>>, Vec<_>) = block
+                .transactions
+                .take()
+                .unwrap_or_default()
+                .into_iter()
+                .enumerate()
+                .filter(|(_, tx)| mentions(tx, &source.program_id))
+                .map(|(tx_index, tx)| (Some(tx_index), tx))
+                .unzip();
+            block.transactions = Some(transactions);
+            let record = IngestRecord::Block {
+                slot,
+                block,
+                tx_indexes: Some(tx_indexes),
+            };
+            if !send(tx, &source.recorder, record).await {
+                return Backfill::Stopped;
+            }
+            if slot <= finalized {
+                let update = SlotUpdate {
+                    slot,
+                    parent: None,
+                    status: SlotStatus::Finalized,
+                };
+                if !send(tx, &source.recorder, IngestRecord::Slot(update)).await {
+                    return Backfill::Stopped;
+                }
+            }
+            last_slot = slot;
+        }
+        // The slots of the range without a block were skipped
+        last_slot = end;
+    }
+    Backfill::Complete(last_slot)
+}
+
+async fn rpc_result<T: DeserializeOwned>(
+    source: &WebsocketSource,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<T, String> {
+    let resp = source
+        .client
+        .proxy_request(json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+        .await?;
+    if let Some(err) = resp.get("error") {
+        return Err(err.to_string());
+    }
+    let result = resp.get("result").ok_or("result not found".to_string())?;
+    serde_json::from_value(result.clone()).map_err(|err| err.to_string())
+}
+
+// What the mentionsAccountOrProgram filter of blockSubscribe keeps
+fn mentions(tx: &EncodedTransactionWithStatusMeta, program_id: &Pubkey) -> bool {
+    let Some(decoded) = tx.transaction.decode() else {
+        return false;
+    };
+    if decoded.message.static_account_keys().contains(program_id) {
+        return true;
+    }
+    let program_id = program_id.to_string();
+    match tx.meta.as_ref().map(|meta| &meta.loaded_addresses) {
+        Some(OptionSerializer::Some(loaded)) => loaded
+            .writable
+            .iter()
+            .chain(loaded.readonly.iter())
+            .any(|address| *address == program_id),
+        _ => false,
+    }
+}
+
+// Rooted slots, which is when the transactions of a slot are finalized
+async fn root_stream(
+    source: WebsocketSource,
+    tx: mpsc::Sender<IngestEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut delay = RECONNECT_DELAY;
+    while !shutdown.is_cancelled() {
+        let client = match PubsubClient::new(&source.blocks_url).await {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!("[!] Failed to connect root subscription : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        let (mut root_stream, unsubscribe) = match client.root_subscribe().await {
+            Ok(res) => res,
+            Err(err) => {
+                tracing::error!("[!] Failed to subscribe to roots : {}", err);
+                reconnect_delay(&mut delay, &shutdown).await;
+                continue;
+            }
+        };
+        delay = RECONNECT_DELAY;
+        if !send_connection(&tx, SLOT_STREAM, true).await {
+            return;
+        }
+
+        loop {
+            let root = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    unsubscribe().await;
+                    return;
+                }
+                root = root_stream.next() => root,
+            };
+            let Some(slot) = root else {
+                tracing::error!("[!] Root subscription dropped");
+                if !send_connection(&tx, SLOT_STREAM, false).await {
+                    return;
+                }
+                break;
+            };
+            let update = SlotUpdate {
+                slot,
+                parent: None,
+                status: SlotStatus::Finalized,
+            };
+            if !send(&tx, &source.recorder, IngestRecord::Slot(update)).await {
+                return;
+            }
+        }
+    }
+}
+
+// false once the indexer stopped listening
+async fn send(
+    tx: &mpsc::Sender<IngestEvent>,
+    recorder: &Option<Recorder>,
+    record: IngestRecord,
+) -> bool {
+    if let Some(recorder) = recorder {
+        recorder.record(&record).await;
+    }
+    match record.into_event() {
+        Some(event) => tx.send(event).await.is_ok(),
+        None => {
+            tracing::error!("[!] Failed to decode notification");
+            true
+        }
+    }
+}
+
+// The program accounts with the slot the node served them at
+async fn fetch_program_accounts(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    config: RpcProgramAccountsConfig,
+) -> Result<(u64, Vec<RpcKeyedAccount>), ClientError> {
+    let response: OptionalContext<Vec<RpcKeyedAccount>> = rpc_client
+        .send(
+            RpcRequest::GetProgramAccounts,
+            json!([program_id.to_string(), config]),
+        )
+        .await?;
+    match response {
+        OptionalContext::Context(response) => Ok((response.context.slot, response.value)),
+        OptionalContext::NoContext(_) => Err(ClientError::from(ClientErrorKind::Custom(
+            "getProgramAccounts response without context".to_string(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use argos_mock_rpc::{Fixtures, MockServer};
+    use solana_sdk::pubkey;
+
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/rpc/ore.json");
+    const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+    #[tokio::test]
+    async fn stream_from_mock_server() {
+        let server = MockServer::start(Fixtures::load(FIXTURES).unwrap()).await;
+        let client = LimitedRequestClient::new(&server.rpc_url(), 45, Duration::from_secs(1));
+        let mut source = WebsocketSource::new(PROGRAM_ID, client);
+        source.rpc_url = server.rpc_url();
+        source.accounts.url = server.ws_url();
+        source.blocks_url = server.ws_url();
+
+        let shutdown = CancellationToken::new();
+        let mut stream = Box::new(source).start(shutdown.clone());
+        let mut events = vec![];
+        let mut connected = vec![];
+        while events.len() < 4 {
+            let event = tokio::time::timeout(Duration::from_secs(10), stream.next())
+                .await
+                .expect("timed out waiting for events")
+                .expect("stream ended");
+            match event {
+                IngestEvent::Connection {
+                    stream,
+                    connected: true,
+                } => connected.push(stream),
+                IngestEvent::Connection { stream, .. } => panic!("lost the {} stream", stream),
+                event => events.push(event),
+            }
+        }
+        // Each stream is up before its first update
+        connected.sort();
+        assert_eq!(
+            connected,
+            vec![
+                account_stream(&PROGRAM_ID),
+                BLOCK_STREAM.to_string(),
+                SLOT_STREAM.to_string()
+            ]
+        );
+
+        let mut snapshots = 0;
+        for event in &events {
+            match event {
+                IngestEvent::Snapshot { slot, accounts, .. } => {
+                    snapshots += 1;
+                    assert_eq!(*slot, 300_000_000);
+                    assert_eq!(accounts.len(), 1);
+                }
+                // The notification of the bootstrap slot is skipped
+                IngestEvent::Account(update) => {
+                    assert_eq!(update.slot, 300_000_001);
+                    assert_eq!(update.account.data, vec![1, 2, 3, 4]);
+                }
+                // Second in the block, the other transaction does not mention the program
+                IngestEvent::Block(block) => {
+                    assert_eq!(block.slot, 300_000_002);
+                    assert_eq!(block.transactions.len(), 1);
+                    assert_eq!(block.transactions[0].tx_index, Some(1));
+                }
+                IngestEvent::Slot(update) => {
+                    assert_eq!(update.slot, 300_000_002);
+                    assert_eq!(update.status, SlotStatus::Finalized);
+                }
+                IngestEvent::Connection { .. } => unreachable!(),
+            }
+        }
+        assert_eq!(snapshots, 1);
+
+        shutdown.cancel();
+        let end = tokio::time::timeout(Duration::from_secs(10), stream.next()).await;
+        assert!(matches!(end, Ok(None)));
+        assert!(server.methods().contains(&"getProgramAccounts".to_string()));
+    }
+
+    #[tokio::test]
+    async fn backfills_from_the_block_cursor() {
+        let mut fixtures = Fixtures::load(FIXTURES).unwrap();
+        // Confirmed while the indexer was down, and finalized by now
+        let missed = fixtures.blocks[&300_000_002].clone();
+        fixtures.blocks.insert(299_999_999, missed);
+        let server = MockServer::start(fixtures).await;
+        let client = LimitedRequestClient::new(&server.rpc_url(), 45, Duration::from_secs(1));
+        let mut source = WebsocketSource::new(PROGRAM_ID, client);
+        source.rpc_url = server.rpc_url();
+        source.accounts.url = server.ws_url();
+        source.blocks_url = server.ws_url();
+        source.block_cursor = Some(299_999_990);
+
+        let shutdown = CancellationToken::new();
+        let mut stream = Box::new(source).start(shutdown.clone());
+        let (mut blocks, mut finalized) = (vec![], vec![]);
+        while !finalized.contains(&300_000_002) || !blocks.contains(&300_000_002) {
+            let event = tokio::time::timeout(Duration::from_secs(10), stream.next())
+                .await
+                .expect("timed out waiting for events")
+                .expect("stream ended");
+            match event {
+                IngestEvent::Block(block) => {
+                    assert_eq!(block.transactions.len(), 1);
+                    blocks.push(block.slot);
+                }
+                IngestEvent::Slot(update) => finalized.push(update.slot),
+                _ => {}
+            }
+        }
+        shutdown.cancel();
+
+        // The notification of the backfilled block is skipped
+        assert_eq!(blocks, vec![299_999_999, 300_000_002]);
+        assert!(finalized.contains(&299_999_999));
+        let requests = server.requests();
+        let get_blocks = requests
+            .iter()
+            .find(|request| request["method"] == "getBlocks")
+            .unwrap();
+        assert_eq!(get_blocks["params"][0], 299_999_991);
+        assert_eq!(get_blocks["params"][1], 300_000_000);
+    }
+
+    #[tokio::test]
+    async fn failed_backfill_resumes_after_the_last_block_sent() {
+        let mut fixtures = Fixtures::load(FIXTURES).unwrap();
+        let missed = fixtures.blocks[&300_000_002].clone();
+        fixtures.blocks.insert(299_999_993, missed);
+        // Listed by getBlocks but not served by getBlock
+        fixtures.blocks.insert(299_999_995, serde_json::Value::Null);
+        let server = MockServer::start(fixtures).await;
+        let client = LimitedRequestClient::new(&server.rpc_url(), 45, Duration::from_secs(1));
+        let mut source = WebsocketSource::new(PROGRAM_ID, client);
+        source.rpc_url = server.rpc_url();
+        source.accounts.url = server.ws_url();
+        source.blocks_url = server.ws_url();
+        source.block_cursor = Some(299_000_000);
+
+        let shutdown = CancellationToken::new();
+        let mut stream = Box::new(source).start(shutdown.clone());
+        let (mut blocks, mut disconnects) = (vec![], 0);
+        while disconnects < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(10), stream.next())
+                .await
+                .expect("timed out waiting for events")
+                .expect("stream ended");
+            match event {
+                IngestEvent::Block(block) => blocks.push(block.slot),
+                IngestEvent::Connection {
+                    stream,
+                    connected: false,
+                } if stream == BLOCK_STREAM => disconnects += 1,
+                _ => {}
+            }
+        }
+        shutdown.cancel();
+
+        // The notifications past the failed block are not let through
+        assert_eq!(blocks, vec![299_999_993]);
+        let ranges: Vec<(u64, u64)> = server
+            .requests()
+            .iter()
+            .filter(|request| request["method"] == "getBlocks")
+            .map(|request| {
+                let params = &request["params"];
+                (params[0].as_u64().unwrap(), params[1].as_u64().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            ranges[..3],
+            [
+                (299_000_001, 299_500_000),
+                (299_500_001, 300_000_000),
+                (299_999_994, 300_000_000)
+            ]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use solana_sdk::pubkey::Pubkey;
+
+use super::LimitedRequestClient;
+
+const UPSTREAM_SLOT_POLL: Duration = Duration::from_secs(5);
+// Above this many slots behind upstream the node reports itself unhealthy
+pub const MAX_SLOTS_BEHIND: u64 = 128;
+
+pub const BLOCK_STREAM: &str = "blocks";
+pub const SLOT_STREAM: &str = "slots";
+
+pub fn account_stream(program_id: &Pubkey) -> String {
+    format!("accounts/{}", program_id)
+}
+
+// Progress of the ingestion streams, shared between the indexers and the proxy.
+// A value of 0 means nothing was seen yet.
+#[derive(Debug, Clone)]
+pub struct IngestState {
+    block_slot: Arc<AtomicU64>,
+    block_height: Arc<AtomicU64>,
+    account_slot: Arc<AtomicU64>,
+    upstream_slot: Arc<AtomicU64>,
+    upstream_reachable: Arc<AtomicBool>,
+    streams: Arc<Mutex<BTreeMap<String, StreamState>>>,
+    started: Instant,
+}
+
+// A subscription of the ingestion source, named by `account_stream`, `BLOCK_STREAM`
+// or `SLOT_STREAM`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamState {
+    pub connected: bool,
+    pub last_slot: Option<u64>,
+    pub last_update: Option<Instant>,
+}
+
+impl Default for IngestState {
+    fn default() -> Self {
+        Self {
+            block_slot: Default::default(),
+            block_height: Default::default(),
+            account_slot: Default::default(),
+            upstream_slot: Default::default(),
+            upstream_reachable: Default::default(),
+            streams: Default::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl IngestState {
+    pub fn record_block(&self, slot: u64, block_height: Option<u64>) {
+        self.block_slot.fetch_max(slot, Ordering::Relaxed);
+        if let Some(height) = block_height {
+            self.block_height.fetch_max(height, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_account(&self, slot: u64) {
+        self.account_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    // Latest confirmed slot seen by the block stream
+    pub fn block_slot(&self) -> Option<u64> {
+        non_zero(self.block_slot.load(Ordering::Relaxed))
+    }
+
+    pub fn block_height(&self) -> Option<u64> {
+        non_zero(self.block_height.load(Ordering::Relaxed))
+    }
+
+    pub fn account_slot(&self) -> Option<u64> {
+        non_zero(self.account_slot.load(Ordering::Relaxed))
+    }
+
+    pub fn upstream_slot(&self) -> Option<u64> {
+        non_zero(self.upstream_slot.load(Ordering::Relaxed))
+    }
+
+    // Whether the last upstream slot poll succeeded
+    pub fn upstream_reachable(&self) -> bool {
+        self.upstream_reachable.load(Ordering::Relaxed)
+    }
+
+    pub fn set_connected(&self, stream: &str, connected: bool) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.entry(stream.to_string()).or_default().connected = connected;
+    }
+
+    // An update came through, so the stream is connected
+    pub fn record_stream(&self, stream: &str, slot: u64) {
+        let mut streams = self.streams.lock().unwrap();
+        let state = streams.entry(stream.to_string()).or_default();
+        state.connected = true;
+        state.last_slot = Some(state.last_slot.map_or(slot, |last| last.max(slot)));
+        state.last_update = Some(Instant::now());
+    }
+
+    pub fn streams(&self) -> BTreeMap<String, StreamState> {
+        self.streams.lock().unwrap().clone()
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    // Lag of the slowest stream behind upstream, `None` until both sides are known
+    pub fn slots_behind(&self) -> Option<u64> {
+        let upstream = self.upstream_slot()?;
+        let ingested = self.block_slot()?.min(self.account_slot()?);
+        Some(upstream.saturating_sub(ingested))
+    }
+}
+
+fn non_zero(value: u64) -> Option<u64> {
+    (value != 0).then_some(value)
+}
+
+pub async fn upstream_slot_tracker(client: LimitedRequestClient, state: IngestState) {
+    let mut interval = tokio::time::interval(UPSTREAM_SLOT_POLL);
+    loop {
+        interval.tick().await;
+        match client.get_slot("confirmed").await {
+            Ok(slot) => {
+                state.upstream_slot.fetch_max(slot, Ordering::Relaxed);
+                state.upstream_reachable.store(true, Ordering::Relaxed);
+            }
+            Err(err) => {
+                state.upstream_reachable.store(false, Ordering::Relaxed);
+                tracing::error!("[!] Failed to get upstream slot : {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use solana_sdk::{pubkey::Pubkey, transaction::VersionedTransaction};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, UiInstruction, UiTransactionStatusMeta,
+};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+
+use super::IndexedTransaction;
+
+// Anchor size, programs with shorter discriminators (Ore uses 1 byte) match on a prefix
+pub const DISCRIMINATOR_LEN: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct IndexedInstruction {
+    pub ix_index: usize,
+    // `None` for top level instructions
+    pub inner_index: Option<usize>,
+    pub stack_height: u32,
+    pub program_id: Pubkey,
+    pub data: Vec<u8>,
+    pub accounts: Vec<Pubkey>,
+}
+
+impl IndexedInstruction {
+    pub fn discriminator(&self) -> &[u8] {
+        &self.data[..self.data.len().min(DISCRIMINATOR_LEN)]
+    }
+
+    fn inner_index_column(&self) -> i64 {
+        self.inner_index.map(|i| i as i64).unwrap_or(-1)
+    }
+}
+
+// Top level instructions followed by their inner instructions, in execution order. Fails
+// when an instruction references an account missing from `keys`, e.g. one loaded from a
+// lookup table that was not resolved.
+pub fn decompose_instructions(
+    tx: &VersionedTransaction,
+    meta: Option<&UiTransactionStatusMeta>,
+    keys: &[Pubkey],
+) -> Result<Vec<IndexedInstruction>, String> {
+    let key = |index: u8| {
+        keys.get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("Account index {} out of {} keys", index, keys.len()))
+    };
+    let inner_instructions = match meta.map(|m| &m.inner_instructions) {
+        Some(OptionSerializer::Some(inner)) => inner.as_slice(),
+        _ => &[],
+    };
+
+    let mut instructions = vec![];
+    for (ix_index, ix) in tx.message.instructions().iter().enumerate() {
+        instructions.push(IndexedInstruction {
+            ix_index,
+            inner_index: None,
+            stack_height: 1,
+            program_id: key(ix.program_id_index)?,
+            data: ix.data.clone(),
+            accounts: ix
+                .accounts
+                .iter()
+                .map(|a| key(*a))
+                .collect::<Result<_, _>>()?,
+        });
+
+        let inner = inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == ix_index)
+            .flat_map(|inner| inner.instructions.iter());
+        for (inner_index, inner_ix) in inner.enumerate() {
+            let UiInstruction::Compiled(inner_ix) = inner_ix else {
+                continue;
+            };
+            let Ok(data) = bs58::decode(&inner_ix.data).into_vec() else {
+                tracing::error!("[!] Invalid inner instruction data in {}", tx.signatures[0]);
+                continue;
+            };
+            instructions.push(IndexedInstruction {
+                ix_index,
+                inner_index: Some(inner_index),
+                stack_height: inner_ix.stack_height.unwrap_or(2),
+                program_id: key(inner_ix.program_id_index)?,
+                data,
+                accounts: inner_ix
+                    .accounts
+                    .iter()
+                    .map(|a| key(*a))
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+    }
+    Ok(instructions)
+}
+
+pub async fn index_instructions(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    txs: &[IndexedTransaction],
+) -> Result<(), sqlx::Error> {
+    let instructions: Vec<(&String, &IndexedInstruction)> = txs
+        .iter()
+        .flat_map(|indexed| {
+            indexed
+                .instructions
+                .iter()
+                .map(move |ix| (&indexed.signature, ix))
+        })
+        .collect();
+
+    for chunk in instructions.chunks(500) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO instructions (signature, slot, ix_index, inner_index, stack_height, program_id, discriminator, data, accounts) ",
+        );
+        query_builder.push_values(chunk, |mut b, (signature, ix)| {
+            let accounts: Vec<String> = ix.accounts.iter().map(|a| a.to_string()).collect();
+            b.push_bind(signature.to_string())
+                .push_bind(slot as i64)
+                .push_bind(ix.ix_index as i64)
+                .push_bind(ix.inner_index_column())
+                .push_bind(ix.stack_height as i64)
+                .push_bind(ix.program_id.to_string())
+                .push_bind(ix.discriminator().to_vec())
+                .push_bind(ix.data.clone())
+                .push_bind(serde_json::to_string(&accounts).unwrap_or_default());
+        });
+        query_builder.build().execute(&mut *conn).await?;
+    }
+
+    let ix_accounts: Vec<(&String, &IndexedInstruction, usize, &Pubkey)> = instructions
+        .iter()
+        .flat_map(|(signature, ix)| {
+            ix.accounts
+                .iter()
+                .enumerate()
+                .map(move |(position, account)| (*signature, *ix, position, account))
+        })
+        .collect();
+    for chunk in ix_accounts.chunks(1_000) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO instruction_accounts (signature, ix_index, inner_index, position, account, program_id, slot) ",
+        );
+        query_builder.push_values(chunk, |mut b, (signature, ix, position, account)| {
+            b.push_bind(signature.to_string())
+                .push_bind(ix.ix_index as i64)
+                .push_bind(ix.inner_index_column())
+                .push_bind(*position as i64)
+                .push_bind(account.to_string())
+                .push_bind(ix.program_id.to_string())
+                .push_bind(slot as i64);
+        });
+        query_builder.build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        hash::Hash,
+        instruction::CompiledInstruction,
+        message::{v0, MessageHeader, VersionedMessage},
+        signature::Signature,
+    };
+
+    use super::*;
+
+    #[test]
+    fn unresolved_accounts_fail() {
+        let static_keys = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(v0::Message {
+                header: MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                },
+                account_keys: static_keys.clone(),
+                recent_blockhash: Hash::default(),
+                // Account 2 is loaded from a lookup table
+                instructions: vec![CompiledInstruction {
+                    program_id_index: 1,
+                    accounts: vec![0, 2],
+                    data: vec![1],
+                }],
+                address_table_lookups: vec![],
+            }),
+        };
+        assert!(decompose_instructions(&tx, None, &static_keys).is_err());
+
+        let keys = [static_keys.clone(), vec![Pubkey::new_unique()]].concat();
+        let instructions = decompose_instructions(&tx, None, &keys).unwrap();
+        assert_eq!(instructions[0].program_id, static_keys[1]);
+        assert_eq!(instructions[0].accounts, vec![keys[0], keys[2]]);
+    }
+}
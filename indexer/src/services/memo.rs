@@ -0,0 +1,27 @@
+use solana_sdk::{pubkey, pubkey::Pubkey};
+
+use super::IndexedInstruction;
+
+pub const MEMO_V1_PROGRAM_ID: Pubkey = pubkey!("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo");
+pub const MEMO_V2_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+fn is_memo_program(program_id: &Pubkey) -> bool {
+    program_id == &MEMO_V1_PROGRAM_ID || program_id == &MEMO_V2_PROGRAM_ID
+}
+
+// Same format as the `memo` field of getSignaturesForAddress : "[len] memo; [len] memo".
+// Unlike the validator we also look at memos emitted through CPI, in execution order.
+pub fn extract_memo(instructions: &[IndexedInstruction]) -> Option<String> {
+    let memos: Vec<String> = instructions
+        .iter()
+        .filter(|ix| is_memo_program(&ix.program_id))
+        .map(|ix| format_memo(&ix.data))
+        .collect();
+
+    (!memos.is_empty()).then(|| memos.join("; "))
+}
+
+fn format_memo(data: &[u8]) -> String {
+    let memo = std::str::from_utf8(data).unwrap_or("(unparseable)");
+    format!("[{}] {}", data.len(), memo)
+}
@@ -0,0 +1,256 @@
+use std::{sync::LazyLock, time::Instant};
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use super::IngestState;
+
+// Seconds, from a fast sqlite insert to a slow upstream answer
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// JSON-RPC methods the metrics are labelled with, any other name a client sends counts as "other"
+// so it can not grow the label values
+const RPC_METHODS: &[&str] = &[
+    "getAccountDiff",
+    "getAccountInfo",
+    "getBalance",
+    "getBlock",
+    "getBlockHeight",
+    "getBlockTime",
+    "getBlocks",
+    "getEpochInfo",
+    "getHealth",
+    "getLatestBlockhash",
+    "getMinimumBalanceForRentExemption",
+    "getMultipleAccounts",
+    "getProgramAccounts",
+    "getSignatureStatuses",
+    "getSignaturesForAddress",
+    "getSlot",
+    "getTokenAccountBalance",
+    "getTokenAccountsByOwner",
+    "getTransaction",
+    "getVersion",
+    "isBlockhashValid",
+    "sendTransaction",
+    "simulateTransaction",
+];
+
+pub fn method_label(method: &str) -> &'static str {
+    RPC_METHODS
+        .iter()
+        .find(|known| **known == method)
+        .copied()
+        .unwrap_or("other")
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    ingested_slot: IntGaugeVec,
+    upstream_slot: IntGauge,
+    slots_behind: IntGauge,
+    pub ingested_transactions: IntCounter,
+    // Left out rather than indexed with accounts that could not be resolved
+    pub skipped_transactions: IntCounter,
+    pub account_updates: IntCounter,
+    pub db_write_seconds: HistogramVec,
+    // By method and whether it was answered locally or upstream, for the cache hit rate
+    pub proxy_requests: IntCounterVec,
+    pub upstream_requests: IntCounterVec,
+    pub upstream_errors: IntCounterVec,
+    pub upstream_request_seconds: HistogramVec,
+    // Requests waiting for the rate limiter of `LimitedRequestClient`
+    pub upstream_queue_depth: IntGauge,
+    pub task_restarts: IntCounterVec,
+    // Ingestion events waiting for the batch writer
+    pub write_queue_depth: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("argos".to_string()), None).unwrap();
+        let metrics = Self {
+            ingested_slot: IntGaugeVec::new(
+                Opts::new("ingested_slot", "Latest slot written by each stream"),
+                &["stream"],
+            )
+            .unwrap(),
+            upstream_slot: IntGauge::new("upstream_slot", "Latest confirmed slot of upstream")
+                .unwrap(),
+            slots_behind: IntGauge::new(
+                "slots_behind",
+                "Slots the slowest stream is behind upstream",
+            )
+            .unwrap(),
+            ingested_transactions: IntCounter::new(
+                "ingested_transactions_total",
+                "Transactions written by the block indexer",
+            )
+            .unwrap(),
+            skipped_transactions: IntCounter::new(
+                "skipped_transactions_total",
+                "Transactions whose accounts could not be resolved",
+            )
+            .unwrap(),
+            account_updates: IntCounter::new(
+                "account_updates_total",
+                "Account updates written by the account indexer",
+            )
+            .unwrap(),
+            db_write_seconds: HistogramVec::new(
+                HistogramOpts::new("db_write_seconds", "Duration of the ingestion writes")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["write"],
+            )
+            .unwrap(),
+            proxy_requests: IntCounterVec::new(
+                Opts::new(
+                    "proxy_requests_total",
+                    "JSON-RPC requests received by the proxy",
+                ),
+                &["method", "answered_by"],
+            )
+            .unwrap(),
+            upstream_requests: IntCounterVec::new(
+                Opts::new("upstream_requests_total", "Requests sent upstream"),
+                &["endpoint"],
+            )
+            .unwrap(),
+            upstream_errors: IntCounterVec::new(
+                Opts::new("upstream_errors_total", "Failed upstream requests"),
+                &["endpoint"],
+            )
+            .unwrap(),
+            upstream_request_seconds: HistogramVec::new(
+                HistogramOpts::new("upstream_request_seconds", "Duration of upstream requests")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["endpoint"],
+            )
+            .unwrap(),
+            upstream_queue_depth: IntGauge::new(
+                "upstream_queue_depth",
+                "Upstream requests waiting for the rate limiter",
+            )
+            .unwrap(),
+            task_restarts: IntCounterVec::new(
+                Opts::new("task_restarts_total", "Restarts of the supervised tasks"),
+                &["task"],
+            )
+            .unwrap(),
+            write_queue_depth: IntGauge::new(
+                "write_queue_depth",
+                "Ingestion events waiting for the batch writer",
+            )
+            .unwrap(),
+            registry,
+        };
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.ingested_slot.clone()),
+            Box::new(metrics.upstream_slot.clone()),
+            Box::new(metrics.slots_behind.clone()),
+            Box::new(metrics.ingested_transactions.clone()),
+            Box::new(metrics.skipped_transactions.clone()),
+            Box::new(metrics.account_updates.clone()),
+            Box::new(metrics.db_write_seconds.clone()),
+            Box::new(metrics.proxy_requests.clone()),
+            Box::new(metrics.upstream_requests.clone()),
+            Box::new(metrics.upstream_errors.clone()),
+            Box::new(metrics.upstream_request_seconds.clone()),
+            Box::new(metrics.upstream_queue_depth.clone()),
+            Box::new(metrics.task_restarts.clone()),
+            Box::new(metrics.write_queue_depth.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    pub fn observe_db_write(&self, write: &str, started: Instant) {
+        self.db_write_seconds
+            .with_label_values(&[write])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    pub fn proxy_request(&self, method: &str, answered_by: &str) {
+        self.proxy_requests
+            .with_label_values(&[method, answered_by])
+            .inc();
+    }
+
+    pub fn upstream_request(&self, endpoint: &str, started: Instant, ok: bool) {
+        self.upstream_requests.with_label_values(&[endpoint]).inc();
+        self.upstream_request_seconds
+            .with_label_values(&[endpoint])
+            .observe(started.elapsed().as_secs_f64());
+        if !ok {
+            self.upstream_errors.with_label_values(&[endpoint]).inc();
+        }
+    }
+
+    // Text exposition format, the slot gauges are read from `state` at scrape time
+    pub fn render(&self, state: &IngestState) -> String {
+        let streams = [
+            ("blocks", state.block_slot()),
+            ("accounts", state.account_slot()),
+        ];
+        for (stream, slot) in streams {
+            self.ingested_slot
+                .with_label_values(&[stream])
+                .set(slot.unwrap_or_default() as i64);
+        }
+        self.upstream_slot
+            .set(state.upstream_slot().unwrap_or_default() as i64);
+        self.slots_behind
+            .set(state.slots_behind().unwrap_or_default() as i64);
+
+        let mut buffer = vec![];
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!("[!] Failed to encode metrics : {}", err);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_registered_metrics() {
+        let metrics = Metrics::new();
+        metrics.proxy_request("getAccountInfo", "local");
+        metrics.proxy_request("getAccountInfo", "upstream");
+        metrics.upstream_request("getAccountInfo", Instant::now(), false);
+        metrics.upstream_queue_depth.inc();
+
+        let state = IngestState::default();
+        state.record_block(120, None);
+        state.record_account(110);
+        let text = metrics.render(&state);
+
+        assert!(text.contains("argos_ingested_slot{stream=\"blocks\"} 120"));
+        assert!(text.contains("argos_ingested_slot{stream=\"accounts\"} 110"));
+        assert!(text.contains(
+            "argos_proxy_requests_total{answered_by=\"local\",method=\"getAccountInfo\"} 1"
+        ));
+        assert!(text.contains("argos_upstream_errors_total{endpoint=\"getAccountInfo\"} 1"));
+        assert!(
+            text.contains("argos_upstream_request_seconds_count{endpoint=\"getAccountInfo\"} 1")
+        );
+        assert!(text.contains("argos_upstream_queue_depth 1"));
+    }
+
+    #[test]
+    fn unknown_methods_share_a_label() {
+        assert_eq!(method_label("getAccountInfo"), "getAccountInfo");
+        assert_eq!(method_label("getAccountInfo2"), "other");
+        assert_eq!(method_label(""), "other");
+    }
+}
@@ -0,0 +1,159 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use tokio::sync::{broadcast, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+
+const ACCOUNT_CHANNEL_SIZE: usize = 4096;
+const UPSTREAM_CHANNEL_SIZE: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct AccountUpdate {
+    pub pubkey: Pubkey,
+    pub account: Account,
+    pub slot: u64,
+}
+
+// The results of an upstream subscription, then why it failed if it did
+pub type UpstreamNotification = Result<Value, String>;
+type UpstreamSubscriptions = Arc<Mutex<HashMap<String, broadcast::Sender<UpstreamNotification>>>>;
+
+// Fan-out point between the ingestion streams and websocket clients.
+// Account writes of indexed programs are broadcast locally, every other
+// subscription is opened once upstream and shared by all the clients asking for it.
+#[derive(Debug, Clone)]
+pub struct PubsubHub {
+    indexed_programs: Arc<Vec<Pubkey>>,
+    accounts: broadcast::Sender<AccountUpdate>,
+    upstream: UpstreamSubscriptions,
+}
+
+impl PubsubHub {
+    pub fn new(indexed_programs: Vec<Pubkey>) -> Self {
+        let (accounts, _) = broadcast::channel(ACCOUNT_CHANNEL_SIZE);
+        Self {
+            indexed_programs: Arc::new(indexed_programs),
+            accounts,
+            upstream: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_indexed_program(&self, program_id: &Pubkey) -> bool {
+        self.indexed_programs.contains(program_id)
+    }
+
+    pub fn publish_account(&self, update: AccountUpdate) {
+        // no receiver is not an error, nobody is listening yet
+        let _ = self.accounts.send(update);
+    }
+
+    pub fn subscribe_accounts(&self) -> broadcast::Receiver<AccountUpdate> {
+        self.accounts.subscribe()
+    }
+
+    // `method` and `params` are the ones sent by the client, identical requests
+    // share the same upstream subscription.
+    pub async fn subscribe_upstream(
+        &self,
+        method: &str,
+        params: &Value,
+    ) -> broadcast::Receiver<UpstreamNotification> {
+        let key = format!("{}:{}", method, params);
+        let mut upstream = self.upstream.lock().await;
+        if let Some(tx) = upstream.get(&key) {
+            return tx.subscribe();
+        }
+
+        let (tx, rx) = broadcast::channel(UPSTREAM_CHANNEL_SIZE);
+        upstream.insert(key.clone(), tx.clone());
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        tokio::spawn(relay_upstream(key, request, tx, self.upstream.clone()));
+        rx
+    }
+}
+
+async fn relay_upstream(
+    key: String,
+    request: Value,
+    tx: broadcast::Sender<UpstreamNotification>,
+    subscriptions: UpstreamSubscriptions,
+) {
+    tracing::info!("Opening upstream subscription {}", key);
+    // Removed first, a client subscribing again opens a new one
+    let result = relay(&request, &tx).await;
+    subscriptions.lock().await.remove(&key);
+    if let Err(err) = result {
+        tracing::error!("[!] Upstream subscription {} failed : {}", key, err);
+        // the clients still listening are told before their receivers close
+        let _ = tx.send(Err(err));
+    }
+    tracing::info!("Closed upstream subscription {}", key);
+}
+
+// Ok once the subscription is done with : the last client is gone or the one signature
+// notification came
+async fn relay(
+    request: &Value,
+    tx: &broadcast::Sender<UpstreamNotification>,
+) -> Result<(), String> {
+    let (mut ws, _) = tokio_tungstenite::connect_async(crate::SOLANA_ACCOUNT_RPC_WS)
+        .await
+        .map_err(|err| err.to_string())?;
+    ws.send(Message::Text(request.to_string()))
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut subscription_id = None;
+    loop {
+        let Some(msg) = ws.next().await else {
+            return Err("Closed by upstream".to_string());
+        };
+        let text = match msg.map_err(|err| err.to_string())? {
+            Message::Text(text) => text,
+            Message::Close(_) => return Err("Closed by upstream".to_string()),
+            _ => continue,
+        };
+        let value: Value = serde_json::from_str(&text).map_err(|err| err.to_string())?;
+        if let Some(err) = value.get("error") {
+            return Err(err.to_string());
+        }
+        if subscription_id.is_none() {
+            subscription_id = value.get("result").and_then(|r| r.as_u64());
+            continue;
+        }
+        let Some(params) = value.get("params") else {
+            continue;
+        };
+        if tx.send(Ok(params["result"].clone())).is_err() {
+            // last client is gone
+            break;
+        }
+        // signature subscriptions are one shot
+        if value["method"] == "signatureNotification" {
+            return Ok(());
+        }
+    }
+
+    if let Some(id) = subscription_id {
+        let unsubscribe_method = request["method"]
+            .as_str()
+            .unwrap_or_default()
+            .replace("Subscribe", "Unsubscribe");
+        let unsubscribe = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": unsubscribe_method,
+            "params": [id],
+        });
+        let _ = ws.send(Message::Text(unsubscribe.to_string())).await;
+    }
+    let _ = ws.close(None).await;
+    Ok(())
+}
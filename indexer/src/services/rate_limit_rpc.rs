@@ -3,40 +3,66 @@ use rand::{thread_rng, Rng};
 use reqwest::{Client, Request, Response, Url};
 use serde_json::{json, Value};
 use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
+use tokio_util::task::TaskTracker;
 use tower::{Service, ServiceExt};
 
-use crate::SOLANA_RPC;
+use super::{method_label, METRICS};
 
 // A simple type alias so as to DRY.
 type Result<T> = std::result::Result<T, String>;
+// The endpoint is the JSON-RPC method, to label the metrics with
+type QueuedRequest = (String, Request, oneshot::Sender<Result<Response>>);
 
 #[derive(Debug, Clone)]
 pub struct LimitedRequestClient {
-    request_tx: mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Response>>)>,
+    rpc_url: Url,
+    request_tx: mpsc::UnboundedSender<QueuedRequest>,
+    // Requests in the queue or in flight
+    requests: TaskTracker,
 }
 
 impl LimitedRequestClient {
-    pub fn new(rate_limit_number: u64, rate_limit_duration: Duration) -> Self {
+    pub fn new(rpc_url: &str, rate_limit_number: u64, rate_limit_duration: Duration) -> Self {
+        let rpc_url = Url::parse(rpc_url).expect("Invalid RPC url");
         let reqwest_client = reqwest::Client::builder().build().unwrap();
-        let (tx, mut rx) =
-            mpsc::unbounded_channel::<(Request, oneshot::Sender<Result<Response>>)>();
+        let (tx, mut rx) = mpsc::unbounded_channel::<QueuedRequest>();
 
         tokio::spawn(async move {
             let mut service = tower::ServiceBuilder::new()
                 .rate_limit(rate_limit_number, rate_limit_duration)
                 .service(reqwest_client);
-            while let Some((req, resp_tx)) = rx.recv().await {
-                let srv = service.ready().await.unwrap();
+            while let Some((endpoint, req, resp_tx)) = rx.recv().await {
+                METRICS.upstream_queue_depth.dec();
+                let srv = match service.ready().await {
+                    Ok(srv) => srv,
+                    Err(err) => {
+                        METRICS.upstream_request(&endpoint, Instant::now(), false);
+                        let _ = resp_tx.send(Err(err.to_string()));
+                        continue;
+                    }
+                };
                 let resp = srv.call(req);
-                tokio::spawn(async {
-                    let resp = resp.await.map_err(|err| err.to_string());
+                tokio::spawn(async move {
+                    let started = Instant::now();
+                    let (resp, ok) = match resp.await {
+                        Ok(resp) => match read_response(resp).await {
+                            Ok((resp, ok)) => (Ok(resp), ok),
+                            Err(err) => (Err(err), false),
+                        },
+                        Err(err) => (Err(err.to_string()), false),
+                    };
+                    METRICS.upstream_request(&endpoint, started, ok);
                     resp_tx.send(resp)
                 });
             }
         });
-        Self { request_tx: tx }
+        Self {
+            rpc_url,
+            request_tx: tx,
+            requests: TaskTracker::new(),
+        }
     }
 
     pub async fn get_blocks(&self, start_slot: u64, end_slot: Option<u64>) -> Result<Response> {
@@ -48,13 +74,13 @@ impl LimitedRequestClient {
             "params": [start_slot, end_slot],
         });
 
-        let mut request = Request::new(Method::POST, Url::parse(SOLANA_RPC).unwrap());
+        let mut request = Request::new(Method::POST, self.rpc_url.clone());
         request
             .headers_mut()
             .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        let body = serde_json::to_vec(&body_value).unwrap();
+        let body = serde_json::to_vec(&body_value).map_err(|err| err.to_string())?;
         *request.body_mut() = Some(body.into());
-        self.request(request).await
+        self.request("getBlocks", request).await
     }
 
     pub async fn get_block(&self, slot: u64) -> Result<Response> {
@@ -74,13 +100,13 @@ impl LimitedRequestClient {
             ]
         });
 
-        let mut request = Request::new(Method::POST, Url::parse(SOLANA_RPC).unwrap());
+        let mut request = Request::new(Method::POST, self.rpc_url.clone());
         request
             .headers_mut()
             .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        let body = serde_json::to_vec(&body_value).unwrap();
+        let body = serde_json::to_vec(&body_value).map_err(|err| err.to_string())?;
         *request.body_mut() = Some(body.into());
-        self.request(request).await
+        self.request("getBlock", request).await
     }
 
     pub async fn get_block_accounts(&self, slot: u64) -> Result<Response> {
@@ -100,13 +126,40 @@ impl LimitedRequestClient {
             ]
         });
 
-        let mut request = Request::new(Method::POST, Url::parse(SOLANA_RPC).unwrap());
+        let mut request = Request::new(Method::POST, self.rpc_url.clone());
         request
             .headers_mut()
             .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        let body = serde_json::to_vec(&body_value).unwrap();
+        let body = serde_json::to_vec(&body_value).map_err(|err| err.to_string())?;
         *request.body_mut() = Some(body.into());
-        self.request(request).await
+        self.request("getBlock", request).await
+    }
+
+    // Every signature of a confirmed block, in execution order
+    pub async fn get_block_signatures(&self, slot: u64) -> Result<Vec<String>> {
+        let rand_id: usize = thread_rng().gen();
+        let rpc_resp = self
+            .proxy_request(json!({
+                "jsonrpc": "2.0",
+                "id":rand_id,
+                "method":"getBlock",
+                "params": [
+                    slot,
+                    {
+                    "encoding": "base64",
+                    "maxSupportedTransactionVersion":0,
+                    "transactionDetails":"signatures",
+                    "rewards":false,
+                    "commitment":"confirmed"
+                    }
+                ]
+            }))
+            .await?;
+        if let Some(err) = rpc_resp.get("error") {
+            return Err(format!("[!] Block error {:?}", err));
+        }
+        serde_json::from_value(rpc_resp["result"]["signatures"].clone())
+            .map_err(|err| err.to_string())
     }
 
     pub async fn get_transaction(
@@ -127,14 +180,17 @@ impl LimitedRequestClient {
             ]
         });
 
-        let mut request = Request::new(Method::POST, Url::parse(SOLANA_RPC).unwrap());
+        let mut request = Request::new(Method::POST, self.rpc_url.clone());
         request
             .headers_mut()
             .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         let body = serde_json::to_vec(&body_value).map_err(|err| err.to_string())?;
         *request.body_mut() = Some(body.into());
 
-        let resp = self.request(request).await.map_err(|err| err.to_string())?;
+        let resp = self
+            .request("getTransaction", request)
+            .await
+            .map_err(|err| err.to_string())?;
         let rpc_resp: Value = resp.json().await.map_err(|err| err.to_string())?;
 
         if let Some(err) = rpc_resp.get("error") {
@@ -149,24 +205,166 @@ impl LimitedRequestClient {
         Ok(tx)
     }
 
+    pub async fn get_slot(&self, commitment: &str) -> Result<u64> {
+        let rand_id: usize = thread_rng().gen();
+        let body_value = json!({
+            "jsonrpc": "2.0",
+            "id":rand_id,
+            "method":"getSlot",
+            "params": [{"commitment": commitment}],
+        });
+
+        let rpc_resp = self.proxy_request(body_value).await?;
+        if let Some(err) = rpc_resp.get("error") {
+            return Err(format!("[!] Slot error {:?}", err));
+        }
+        rpc_resp
+            .get("result")
+            .and_then(|slot| slot.as_u64())
+            .ok_or("result not found".to_string())
+    }
+
     pub async fn proxy_request(&self, body_value: Value) -> Result<Value> {
-        let mut request = Request::new(Method::POST, Url::parse(SOLANA_RPC).unwrap());
+        let endpoint = method_label(body_value["method"].as_str().unwrap_or_default());
+        let mut request = Request::new(Method::POST, self.rpc_url.clone());
         request
             .headers_mut()
             .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         let body = serde_json::to_vec(&body_value).map_err(|err| err.to_string())?;
         *request.body_mut() = Some(body.into());
 
-        let resp = self.request(request).await.map_err(|err| err.to_string())?;
+        let resp = self
+            .request(endpoint, request)
+            .await
+            .map_err(|err| err.to_string())?;
         let rpc_resp: Value = resp.json().await.map_err(|err| err.to_string())?;
         Ok(rpc_resp)
     }
 
-    async fn request(&self, req: Request) -> Result<Response> {
+    // Refuses new requests and waits for the queued ones to complete
+    pub async fn drain(&self) {
+        self.requests.close();
+        self.requests.wait().await;
+    }
+
+    async fn request(&self, endpoint: &str, req: Request) -> Result<Response> {
+        if self.requests.is_closed() {
+            return Err("Client is shutting down".to_string());
+        }
+        let _request = self.requests.token();
         let (tx, rx) = oneshot::channel::<Result<Response>>();
+        METRICS.upstream_queue_depth.inc();
         self.request_tx
-            .send((req, tx))
-            .map_err(|err| err.to_string())?;
+            .send((endpoint.to_string(), req, tx))
+            .map_err(|err| {
+                METRICS.upstream_queue_depth.dec();
+                err.to_string()
+            })?;
         rx.await.map_err(|err| err.to_string())?
     }
 }
+
+// Upstream answers JSON-RPC errors with a 200, so the body is read to tell whether it failed
+async fn read_response(resp: Response) -> Result<(Response, bool)> {
+    let (status, headers) = (resp.status(), resp.headers().clone());
+    let body = resp.bytes().await.map_err(|err| err.to_string())?;
+    let ok = status.is_success()
+        && match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Array(resps)) => resps.iter().all(|resp| resp.get("error").is_none()),
+            Ok(resp) => resp.get("error").is_none(),
+            Err(_) => false,
+        };
+    let mut resp = http::Response::new(body);
+    *resp.status_mut() = status;
+    *resp.headers_mut() = headers;
+    Ok((Response::from(resp), ok))
+}
+
+#[cfg(test)]
+mod tests {
+    use argos_mock_rpc::{Fixtures, MockServer};
+
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/rpc/ore.json");
+    const SIGNATURE: &str =
+        "1XTRN2RJN5MfCYf2UTVo8dcmei94CvVB2BBWvvograKBRzTW7RaDH79KZ4NSHQNUNLf3DwSbhdsgmDYptsM2d21";
+
+    #[tokio::test]
+    async fn requests_upstream() {
+        let server = MockServer::start(Fixtures::load(FIXTURES).unwrap()).await;
+        let client = LimitedRequestClient::new(&server.rpc_url(), 45, Duration::from_secs(1));
+
+        assert_eq!(client.get_slot("confirmed").await.unwrap(), 300_000_000);
+        let tx = client.get_transaction(SIGNATURE.to_string()).await.unwrap();
+        assert_eq!(tx.slot, 300_000_002);
+        assert_eq!(tx.block_time, Some(1_729_300_000));
+
+        let blocks: Value = client
+            .get_blocks(300_000_000, Some(300_000_010))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(blocks["result"], json!([300_000_002]));
+        let errors = || {
+            METRICS
+                .upstream_errors
+                .with_label_values(&["getBlock"])
+                .get()
+        };
+        let errors_before = errors();
+        let block: Value = client
+            .get_block(300_000_001)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(block["error"]["code"], -32009);
+        // Answered with a 200 but still a failed request
+        assert!(errors() > errors_before);
+
+        assert_eq!(
+            server.methods(),
+            vec!["getSlot", "getTransaction", "getBlocks", "getBlock"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let server = MockServer::start(Fixtures::default()).await;
+        let client = LimitedRequestClient::new(&server.rpc_url(), 2, Duration::from_millis(200));
+
+        let started = std::time::Instant::now();
+        for _ in 0..5 {
+            client.get_slot("confirmed").await.unwrap();
+        }
+        // 2 requests per window, the fifth one waits for the third window
+        assert!(started.elapsed() >= Duration::from_millis(400));
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn drains_queued_requests() {
+        let server = MockServer::start(Fixtures::default()).await;
+        let client = LimitedRequestClient::new(&server.rpc_url(), 1, Duration::from_millis(100));
+
+        let queued: Vec<_> = (0..3)
+            .map(|_| {
+                let client = client.clone();
+                tokio::task::spawn(async move { client.get_slot("confirmed").await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        client.drain().await;
+
+        // Everything queued before the drain went through, nothing after
+        assert_eq!(server.requests().len(), 3);
+        for request in queued {
+            assert!(request.await.unwrap().is_ok());
+        }
+        assert!(client.get_slot("confirmed").await.is_err());
+    }
+}
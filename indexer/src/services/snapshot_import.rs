@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use sqlx::SqlitePool;
+
+use super::store_accounts;
+
+// StoredMeta (write version, data len, pubkey) + AccountMeta (lamports, rent epoch, owner,
+// executable padded to 8) + account hash
+const STORED_ACCOUNT_HEADER: usize = 48 + 56 + 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotArchive {
+    pub path: PathBuf,
+    pub slot: u64,
+    // Slot of the full snapshot an incremental one applies to
+    pub base_slot: Option<u64>,
+}
+
+impl SnapshotArchive {
+    // snapshot-<slot>-<hash>.tar.zst or incremental-snapshot-<base slot>-<slot>-<hash>.tar.zst
+    pub fn from_path(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".tar.zst"))
+            .ok_or_else(|| format!("{} is not a .tar.zst archive", path.display()))?;
+        let parse = |slot: &str| {
+            slot.parse::<u64>()
+                .map_err(|_| format!("Invalid slot in archive name {}", name))
+        };
+        let parts: Vec<&str> = name.split('-').collect();
+        let (slot, base_slot) = match parts.as_slice() {
+            ["snapshot", slot, _hash] => (parse(slot)?, None),
+            ["incremental", "snapshot", base, slot, _hash] => (parse(slot)?, Some(parse(base)?)),
+            _ => return Err(format!("Unknown snapshot archive name {}", name)),
+        };
+        Ok(Self {
+            path,
+            slot,
+            base_slot,
+        })
+    }
+}
+
+// Storage slot, then the read order of the entry, the later one wins within a storage
+type Version = (u64, u64);
+
+// Newest version of the accounts owned by `programs` at some point. Storages come in
+// archive order, not slot order, so the archives are read twice : the owned versions
+// first, then the closed or foreign versions of those accounts that shadow them. Only
+// the accounts of `programs` are kept, not every account of the snapshot.
+#[derive(Debug, Default)]
+pub struct SnapshotAccounts {
+    pub slot: u64,
+    accounts: HashMap<Pubkey, (Version, Option<Account>)>,
+}
+
+impl SnapshotAccounts {
+    pub fn accounts(&self) -> Vec<(Pubkey, Account)> {
+        let mut accounts: Vec<(Pubkey, Account)> = self
+            .accounts
+            .iter()
+            .filter_map(|(pubkey, (_, account))| Some((*pubkey, account.clone()?)))
+            .collect();
+        accounts.sort_by_key(|(pubkey, _)| *pubkey);
+        accounts
+    }
+
+    fn insert_owned(&mut self, version: Version, pubkey: Pubkey, account: Account) {
+        match self.accounts.get(&pubkey) {
+            Some((newer, _)) if *newer > version => {}
+            _ => {
+                self.accounts.insert(pubkey, (version, Some(account)));
+            }
+        }
+    }
+
+    fn shadow(&mut self, version: Version, pubkey: &Pubkey) {
+        if let Some(entry) = self.accounts.get_mut(pubkey) {
+            if entry.0 < version {
+                *entry = (version, None);
+            }
+        }
+    }
+}
+
+// Reads the full snapshot and the incremental ones on top of it, in that order
+pub fn read_snapshot_accounts(
+    archives: &[SnapshotArchive],
+    programs: &[Pubkey],
+) -> Result<SnapshotAccounts, String> {
+    for (position, archive) in archives.iter().enumerate() {
+        match (position, archive.base_slot) {
+            (0, None) => {}
+            (0, Some(_)) => {
+                return Err(format!(
+                    "{} is incremental, a full snapshot must come first",
+                    archive.path.display()
+                ))
+            }
+            (_, Some(base_slot))
+                if base_slot == archives[0].slot && archive.slot > archives[position - 1].slot => {}
+            _ => {
+                return Err(format!(
+                    "{} does not apply on top of the snapshot of slot {}",
+                    archive.path.display(),
+                    archives[position - 1].slot
+                ))
+            }
+        }
+    }
+
+    let owned = |account: &Account| account.lamports > 0 && programs.contains(&account.owner);
+    let mut snapshot = SnapshotAccounts::default();
+    for shadowing in [false, true] {
+        // Entries come in the same order on both reads
+        let mut order = 0;
+        for archive in archives {
+            read_archive(&archive.path, |storage_slot, pubkey, account| {
+                order += 1;
+                let version = (storage_slot, order);
+                match (shadowing, owned(&account)) {
+                    (false, true) => snapshot.insert_owned(version, pubkey, account),
+                    (true, false) => snapshot.shadow(version, &pubkey),
+                    _ => {}
+                }
+            })?;
+            if shadowing {
+                tracing::info!(
+                    "Read snapshot {} | {} accounts",
+                    archive.path.display(),
+                    snapshot.accounts.len()
+                );
+            }
+        }
+    }
+    snapshot.slot = archives.last().map_or(0, |archive| archive.slot);
+    Ok(snapshot)
+}
+
+fn read_archive(path: &Path, mut visit: impl FnMut(u64, Pubkey, Account)) -> Result<(), String> {
+    let file = File::open(path).map_err(|err| format!("{} : {}", path.display(), err))?;
+    let decoder = zstd::Decoder::new(file).map_err(|err| err.to_string())?;
+    let mut archive = tar::Archive::new(decoder);
+    let entries = archive.entries().map_err(|err| err.to_string())?;
+    for entry in entries {
+        let mut entry = entry.map_err(|err| err.to_string())?;
+        let entry_path = entry.path().map_err(|err| err.to_string())?.into_owned();
+        // accounts/<slot>.<storage id>
+        let Some(storage_slot) = entry_path
+            .strip_prefix("accounts")
+            .ok()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('.'))
+            .and_then(|(slot, _id)| slot.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        let mut storage = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut storage)
+            .map_err(|err| format!("{} : {}", entry_path.display(), err))?;
+        for (pubkey, account) in stored_accounts(&storage) {
+            visit(storage_slot, pubkey, account);
+        }
+    }
+    Ok(())
+}
+
+// Entries of an append vec. Storages are preallocated, so a zeroed header or an entry running
+// past the end marks the end of the written part.
+pub fn stored_accounts(storage: &[u8]) -> Vec<(Pubkey, Account)> {
+    let u64_at =
+        |offset: usize| u64::from_le_bytes(storage[offset..offset + 8].try_into().unwrap());
+    let pubkey_at =
+        |offset: usize| Pubkey::new_from_array(storage[offset..offset + 32].try_into().unwrap());
+
+    let mut accounts = vec![];
+    let mut offset = 0;
+    while offset + STORED_ACCOUNT_HEADER <= storage.len() {
+        let data_len = u64_at(offset + 8) as usize;
+        let pubkey = pubkey_at(offset + 16);
+        let lamports = u64_at(offset + 48);
+        let rent_epoch = u64_at(offset + 56);
+        let owner = pubkey_at(offset + 64);
+        let executable = storage[offset + 96] != 0;
+        if pubkey == Pubkey::default() && owner == Pubkey::default() && lamports == 0 {
+            break;
+        }
+        let data_start = offset + STORED_ACCOUNT_HEADER;
+        let Some(data) = data_len
+            .checked_add(data_start)
+            .and_then(|data_end| storage.get(data_start..data_end))
+        else {
+            break;
+        };
+        accounts.push((
+            pubkey,
+            Account {
+                lamports,
+                data: data.to_vec(),
+                owner,
+                executable,
+                rent_epoch,
+            },
+        ));
+        offset = (data_start + data_len).next_multiple_of(8);
+    }
+    accounts
+}
+
+// Seeds accounts_archive with the state at the snapshot slot, returns that slot
+pub async fn import_snapshot(
+    db: &SqlitePool,
+    archives: Vec<SnapshotArchive>,
+    programs: Vec<Pubkey>,
+) -> Result<u64, String> {
+    let blocking_programs = programs.clone();
+    let snapshot =
+        tokio::task::spawn_blocking(move || read_snapshot_accounts(&archives, &blocking_programs))
+            .await
+            .map_err(|err| err.to_string())??;
+
+    let accounts = snapshot.accounts();
+    for program_id in &programs {
+        let owned: Vec<(Pubkey, Account)> = accounts
+            .iter()
+            .filter(|(_, account)| account.owner == *program_id)
+            .cloned()
+            .collect();
+        // Unchanged accounts keep their older version, the others missing from the snapshot
+        // were closed before it
+        let mut tx = db.begin().await.map_err(|err| err.to_string())?;
+        store_accounts(&mut tx, program_id, snapshot.slot, &owned)
+            .await
+            .map_err(|err| err.to_string())?;
+        tx.commit().await.map_err(|err| err.to_string())?;
+        tracing::info!(
+            "Imported {} accounts of {} at slot {}",
+            owned.len(),
+            program_id,
+            snapshot.slot
+        );
+    }
+    Ok(snapshot.slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey;
+
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/snapshot");
+    const PROGRAM_ID: Pubkey = pubkey!("oreV2ZymfyeXgNgBdqMkumTqqAprVqgBWQfoYkrtKWQ");
+
+    fn fixture(name: &str) -> SnapshotArchive {
+        SnapshotArchive::from_path(format!("{}/{}", FIXTURES, name)).unwrap()
+    }
+
+    #[test]
+    fn archive_names() {
+        let full = fixture("snapshot-100-GgBaCs3NCBuZN12kCJgAW63ydqohFkHEdfdEXBPzLHq.tar.zst");
+        assert_eq!((full.slot, full.base_slot), (100, None));
+        let incremental = fixture(
+            "incremental-snapshot-100-150-4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY.tar.zst",
+        );
+        assert_eq!((incremental.slot, incremental.base_slot), (150, Some(100)));
+        assert!(SnapshotArchive::from_path("snapshot-100.tar.bz2").is_err());
+    }
+
+    #[test]
+    fn full_and_incremental() {
+        let full = fixture("snapshot-100-GgBaCs3NCBuZN12kCJgAW63ydqohFkHEdfdEXBPzLHq.tar.zst");
+        let incremental = fixture(
+            "incremental-snapshot-100-150-4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY.tar.zst",
+        );
+        assert!(read_snapshot_accounts(std::slice::from_ref(&incremental), &[PROGRAM_ID]).is_err());
+
+        // Full: accounts 1, 2 and 3 owned by the program, 4 by the system program
+        let snapshot = read_snapshot_accounts(std::slice::from_ref(&full), &[PROGRAM_ID]).unwrap();
+        assert_eq!(snapshot.slot, 100);
+        let accounts = snapshot.accounts();
+        assert_eq!(accounts.len(), 3);
+        assert!(accounts.iter().all(|(_, a)| a.owner == PROGRAM_ID));
+        let first = &accounts
+            .iter()
+            .find(|(pubkey, _)| *pubkey == Pubkey::new_from_array([1; 32]))
+            .unwrap()
+            .1;
+        // rewritten in a later storage of the full snapshot
+        assert_eq!(first.data, vec![1, 1, 1]);
+        assert_eq!(first.lamports, 1_100);
+
+        // Incremental: 2 updated, 3 closed
+        let snapshot = read_snapshot_accounts(&[full, incremental], &[PROGRAM_ID]).unwrap();
+        assert_eq!(snapshot.slot, 150);
+        let accounts = snapshot.accounts();
+        let pubkeys: Vec<Pubkey> = accounts.iter().map(|(pubkey, _)| *pubkey).collect();
+        assert_eq!(
+            pubkeys,
+            vec![
+                Pubkey::new_from_array([1; 32]),
+                Pubkey::new_from_array([2; 32])
+            ]
+        );
+        assert_eq!(accounts[1].1.data, vec![2; 10]);
+        assert_eq!(accounts[1].1.lamports, 2_500);
+    }
+
+    #[test]
+    fn newer_storage_first() {
+        // The storage of slot 200 closes 5 and reassigns 6 to the system program, it comes
+        // before the one of slot 100 where 5, 6 and 7 are owned by the program
+        let full = fixture("snapshot-200-8mXaCc1tPqJkLdvZ3oYgSfEwR9uNHbT5xKzVGyAqWe4h.tar.zst");
+        let snapshot = read_snapshot_accounts(&[full], &[PROGRAM_ID]).unwrap();
+        let pubkeys: Vec<Pubkey> = snapshot
+            .accounts()
+            .into_iter()
+            .map(|(pubkey, _)| pubkey)
+            .collect();
+        assert_eq!(pubkeys, vec![Pubkey::new_from_array([7; 32])]);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use super::METRICS;
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+// A task that ran this long before failing restarts after `RESTART_DELAY` again
+const STABLE_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskState {
+    Running,
+    // Failed, waiting for the backoff before running again
+    Restarting,
+    // Returned Ok on its own, e.g. a replayed file ran out
+    Finished,
+    // Ended by the shutdown
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskStatus {
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+// Runs long lived tasks again when they fail or panic, with an exponential backoff
+#[derive(Debug, Clone, Default)]
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<String, TaskStatus>>>,
+}
+
+impl Supervisor {
+    // `task` builds a new run of the task each time, the handle completes once it finished
+    // or `shutdown` is cancelled
+    pub fn spawn<F, Fut, E>(
+        &self,
+        name: &str,
+        shutdown: CancellationToken,
+        mut task: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+        tokio::task::spawn(async move {
+            let mut delay = RESTART_DELAY;
+            loop {
+                supervisor.update(&name, TaskState::Running, None);
+                let started = Instant::now();
+                // Spawned so a panic ends the run instead of the supervisor
+                let err = match tokio::task::spawn(task()).await {
+                    Ok(Ok(())) if shutdown.is_cancelled() => {
+                        supervisor.update(&name, TaskState::Stopped, None);
+                        return;
+                    }
+                    Ok(Ok(())) => {
+                        tracing::info!("Task {} finished", name);
+                        supervisor.update(&name, TaskState::Finished, None);
+                        return;
+                    }
+                    Ok(Err(err)) => err.to_string(),
+                    Err(err) => panic_message(err),
+                };
+                tracing::error!("[!] Task {} failed : {}", name, err);
+                if shutdown.is_cancelled() {
+                    supervisor.update(&name, TaskState::Stopped, Some(err));
+                    return;
+                }
+                if started.elapsed() >= STABLE_RUN {
+                    delay = RESTART_DELAY;
+                }
+                supervisor.update(&name, TaskState::Restarting, Some(err));
+                METRICS.task_restarts.with_label_values(&[&name]).inc();
+                tokio::select! {
+                    _ = shutdown.cancelled() => {
+                        supervisor.update(&name, TaskState::Stopped, None);
+                        return;
+                    }
+                    _ = tokio::time::sleep(delay) => {}
+                }
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+            }
+        })
+    }
+
+    pub fn tasks(&self) -> BTreeMap<String, TaskStatus> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    fn update(&self, name: &str, state: TaskState, err: Option<String>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let status = tasks.entry(name.to_string()).or_insert(TaskStatus {
+            state,
+            restarts: 0,
+            last_error: None,
+        });
+        if state == TaskState::Restarting {
+            status.restarts += 1;
+        }
+        status.state = state;
+        if err.is_some() {
+            status.last_error = err;
+        }
+    }
+}
+
+fn panic_message(err: JoinError) -> String {
+    let Ok(panic) = err.try_into_panic() else {
+        return "task cancelled".to_string();
+    };
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    format!("panicked : {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_failed_tasks() {
+        let supervisor = Supervisor::default();
+        let runs = Arc::new(AtomicU32::new(0));
+        let handle = supervisor.spawn("flaky", CancellationToken::new(), {
+            let runs = runs.clone();
+            move || {
+                let run = runs.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    match run {
+                        0 => Err("connection reset".to_string()),
+                        1 => panic!("bad row"),
+                        _ => Ok(()),
+                    }
+                }
+            }
+        });
+        handle.await.unwrap();
+
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        let status = &supervisor.tasks()["flaky"];
+        assert_eq!(status.state, TaskState::Finished);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_error.as_deref(), Some("panicked : bad row"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_on_shutdown() {
+        let supervisor = Supervisor::default();
+        let shutdown = CancellationToken::new();
+        let handle = supervisor.spawn("failing", shutdown.clone(), || async {
+            Err::<(), _>("unreachable database")
+        });
+        tokio::time::sleep(RESTART_DELAY * 4).await;
+        let status = supervisor.tasks()["failing"].clone();
+        assert_eq!(status.state, TaskState::Restarting);
+        // Runs at 0s, 1s and 3s
+        assert_eq!(status.restarts, 3);
+
+        shutdown.cancel();
+        handle.await.unwrap();
+        assert_eq!(supervisor.tasks()["failing"].state, TaskState::Stopped);
+    }
+}
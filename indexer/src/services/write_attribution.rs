@@ -0,0 +1,117 @@
+use serde::Serialize;
+use sqlx::{SqliteConnection, SqlitePool};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountWrite {
+    pub tx_index: u64,
+    pub signature: String,
+}
+
+// programSubscribe only gives the slot of a version, its writer is the successful transaction
+// with the highest block position that had the account writable. Blocks and account updates
+// can land in any order, this runs after both : for every account of the slot, or only the
+// updated one. Transactions whose position is unknown are left out.
+pub async fn link_account_writes(
+    conn: &mut SqliteConnection,
+    slot: u64,
+    account: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let slot = slot as i64;
+    sqlx::query!(
+        "INSERT OR REPLACE INTO account_writes (account, slot, tx_index, signature)
+        SELECT a.account, t.slot, t.tx_index, t.signature
+        FROM transaction_accounts a
+        JOIN transactions t ON t.signature = a.signature
+        JOIN accounts_history h ON h.id = a.account AND h.slot = t.slot
+        WHERE t.slot = ?1 AND a.writable AND t.err IS NULL AND t.tx_index IS NOT NULL
+        AND (?2 IS NULL OR a.account = ?2)
+        AND t.tx_index = (
+            SELECT max(lt.tx_index) FROM transaction_accounts la
+            JOIN transactions lt ON lt.signature = la.signature
+            WHERE la.account = a.account AND la.slot = ?1 AND la.writable AND lt.err IS NULL
+        )",
+        slot,
+        account,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Transaction that produced the account version at `slot`
+pub async fn account_writer(
+    db: &SqlitePool,
+    account: &str,
+    slot: u64,
+) -> Result<Option<AccountWrite>, sqlx::Error> {
+    let slot = slot as i64;
+    let row = sqlx::query!(
+        "SELECT tx_index, signature FROM account_writes WHERE account = ? AND slot = ?",
+        account,
+        slot,
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(|row| AccountWrite {
+        tx_index: row.tx_index as u64,
+        signature: row.signature,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn last_writer_of_the_slot(db: SqlitePool) {
+        // 'c' comes last but failed, 'd' has no known position
+        for (signature, tx_index, err) in [
+            ("a", Some(0), None),
+            ("b", Some(3), None),
+            ("c", Some(7), Some("failed")),
+            ("d", None, None),
+        ] {
+            sqlx::query(
+                "INSERT INTO transactions (signature, slot, tx_index, err) VALUES (?1, 5, ?2, ?3)",
+            )
+            .bind(signature)
+            .bind(tx_index)
+            .bind(err)
+            .execute(&db)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO transaction_accounts (signature, position, account, slot, writable)
+                VALUES (?1, 0, 'account', 5, true)",
+            )
+            .bind(signature)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        sqlx::query(
+            "INSERT INTO accounts_history (id, slot, data, executable, lamports, owner, rent_epoch)
+            VALUES ('account', 5, x'01', false, 1, 'owner', 0)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let mut conn = db.acquire().await.unwrap();
+        link_account_writes(&mut conn, 5, None).await.unwrap();
+        link_account_writes(&mut conn, 5, Some("account"))
+            .await
+            .unwrap();
+        drop(conn);
+
+        let writer = account_writer(&db, "account", 5).await.unwrap();
+        assert_eq!(
+            writer,
+            Some(AccountWrite {
+                tx_index: 3,
+                signature: "b".into()
+            })
+        );
+    }
+}
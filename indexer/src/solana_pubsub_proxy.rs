@@ -0,0 +1,351 @@
+use std::{collections::HashMap, str::FromStr};
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::Response,
+    Extension,
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_account_decoder::UiAccountEncoding;
+use solana_rpc_client_api::config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_sdk::{account::AccountSharedData, pubkey::Pubkey};
+use sqlx::SqlitePool;
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    decoders::{self, AnchorIdl},
+    services::{AccountUpdate, PubsubHub, UpstreamNotification},
+};
+
+#[derive(Deserialize, Debug)]
+struct PubsubRequest {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+pub async fn pubsub_ws(
+    ws: WebSocketUpgrade,
+    Extension(hub): Extension<PubsubHub>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(shutdown): Extension<CancellationToken>,
+) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, hub, pool, shutdown))
+}
+
+// Closed on shutdown, the server waits for every connection to end
+async fn handle_socket(
+    socket: WebSocket,
+    hub: PubsubHub,
+    pool: SqlitePool,
+    shutdown: CancellationToken,
+) {
+    let (mut sender, mut receiver) = socket.split();
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = out_rx.recv().await {
+            if sender.send(Message::Text(msg.to_string())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subscriptions: HashMap<u64, JoinHandle<()>> = HashMap::new();
+    let mut next_id = 0u64;
+
+    loop {
+        let msg = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            msg = receiver.next() => msg,
+        };
+        let Some(Ok(msg)) = msg else {
+            break;
+        };
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let request: PubsubRequest = match serde_json::from_str(&text) {
+            Ok(request) => request,
+            Err(err) => {
+                let _ = out_tx.send(error_response(Value::Null, -32700, &err.to_string()));
+                continue;
+            }
+        };
+        tracing::debug!("Got pubsub request {:?}", request);
+
+        let resp = if request.method.ends_with("Unsubscribe") {
+            // One that already ended, e.g. after its upstream failed, is no longer there
+            let removed = request.params[0]
+                .as_u64()
+                .and_then(|id| subscriptions.remove(&id))
+                .is_some_and(|handle| {
+                    handle.abort();
+                    !handle.is_finished()
+                });
+            json!({"jsonrpc":"2.0","result":removed,"id":request.id})
+        } else if let Some(method) = notification_method(&request.method) {
+            let id = next_id;
+            match subscribe(&hub, &pool, &request, method, id, out_tx.clone()).await {
+                Ok(handle) => {
+                    next_id += 1;
+                    subscriptions.insert(id, handle);
+                    json!({"jsonrpc":"2.0","result":id,"id":request.id})
+                }
+                Err(err) => error_response(request.id, -32602, &err),
+            }
+        } else {
+            let message = format!("Method not found {}", request.method);
+            error_response(request.id, -32601, &message)
+        };
+        if out_tx.send(resp).is_err() {
+            break;
+        }
+    }
+
+    for (_, handle) in subscriptions {
+        handle.abort();
+    }
+    writer.abort();
+}
+
+async fn subscribe(
+    hub: &PubsubHub,
+    pool: &SqlitePool,
+    request: &PubsubRequest,
+    notification_method: &'static str,
+    id: u64,
+    out: mpsc::UnboundedSender<Value>,
+) -> Result<JoinHandle<()>, String> {
+    let params = &request.params;
+    match request.method.as_str() {
+        "accountSubscribe" => {
+            let pubkey = parse_pubkey(&params[0])?;
+            let config: RpcAccountInfoConfig =
+                serde_json::from_value(params.get(1).cloned().unwrap_or(Value::Null))
+                    .unwrap_or_default();
+            if let Some(owner) = indexed_account_owner(pool, &pubkey).await {
+                let idl = subscription_idl(pool, &owner, &config).await;
+                let rx = hub.subscribe_accounts();
+                return Ok(tokio::spawn(forward_local(
+                    rx,
+                    out,
+                    id,
+                    notification_method,
+                    move |update| {
+                        (update.pubkey == pubkey).then(|| {
+                            json!({
+                                "context": {"slot": update.slot},
+                                "value": decoders::encode_account(
+                                    &update.pubkey,
+                                    &update.account,
+                                    &config,
+                                    idl.as_ref(),
+                                ),
+                            })
+                        })
+                    },
+                )));
+            }
+        }
+        "programSubscribe" => {
+            let program_id = parse_pubkey(&params[0])?;
+            let config: RpcProgramAccountsConfig =
+                serde_json::from_value(params.get(1).cloned().unwrap_or(Value::Null))
+                    .unwrap_or_default();
+            if hub.is_indexed_program(&program_id) {
+                let idl = subscription_idl(pool, &program_id, &config.account_config).await;
+                let rx = hub.subscribe_accounts();
+                return Ok(tokio::spawn(forward_local(
+                    rx,
+                    out,
+                    id,
+                    notification_method,
+                    move |update| {
+                        if update.account.owner != program_id {
+                            return None;
+                        }
+                        let shared = AccountSharedData::from(update.account.clone());
+                        let filters = config.filters.as_deref().unwrap_or_default();
+                        if !filters.iter().all(|filter| filter.allows(&shared)) {
+                            return None;
+                        }
+                        Some(json!({
+                            "context": {"slot": update.slot},
+                            "value": {
+                                "pubkey": update.pubkey.to_string(),
+                                "account": decoders::encode_account(
+                                    &update.pubkey,
+                                    &update.account,
+                                    &config.account_config,
+                                    idl.as_ref(),
+                                ),
+                            },
+                        }))
+                    },
+                )));
+            }
+        }
+        _ => {}
+    }
+
+    let rx = hub.subscribe_upstream(&request.method, params).await;
+    Ok(tokio::spawn(forward_upstream(
+        rx,
+        out,
+        id,
+        notification_method,
+    )))
+}
+
+async fn forward_local<F>(
+    mut rx: broadcast::Receiver<AccountUpdate>,
+    out: mpsc::UnboundedSender<Value>,
+    id: u64,
+    method: &'static str,
+    to_result: F,
+) where
+    F: Fn(&AccountUpdate) -> Option<Value>,
+{
+    loop {
+        let update = match rx.recv().await {
+            Ok(update) => update,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::error!("[!] Subscription {} lagged {} updates", id, skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let Some(result) = to_result(&update) else {
+            continue;
+        };
+        if out.send(notification(method, result, id)).is_err() {
+            return;
+        }
+    }
+}
+
+// A failed upstream subscription ends with an error naming the subscription, the client
+// has to subscribe again
+async fn forward_upstream(
+    mut rx: broadcast::Receiver<UpstreamNotification>,
+    out: mpsc::UnboundedSender<Value>,
+    id: u64,
+    method: &'static str,
+) {
+    loop {
+        let result = match rx.recv().await {
+            Ok(Ok(result)) => result,
+            Ok(Err(err)) => {
+                let mut error = error_response(
+                    Value::Null,
+                    -32000,
+                    &format!("Subscription {} failed upstream : {}", id, err),
+                );
+                error["error"]["data"] = json!({"subscription": id});
+                let _ = out.send(error);
+                return;
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::error!("[!] Subscription {} lagged {} notifications", id, skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if out.send(notification(method, result, id)).is_err() {
+            return;
+        }
+    }
+}
+
+// Owner of the account when it is indexed
+async fn indexed_account_owner(pool: &SqlitePool, pubkey: &Pubkey) -> Option<Pubkey> {
+    let id = pubkey.to_string();
+    sqlx::query!("SELECT owner FROM accounts_archive WHERE id = ?", id)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|row| row.owner)
+        .and_then(|owner| Pubkey::from_str(&owner).ok())
+}
+
+// Only jsonParsed subscriptions decode account data
+async fn subscription_idl(
+    pool: &SqlitePool,
+    program_id: &Pubkey,
+    config: &RpcAccountInfoConfig,
+) -> Option<AnchorIdl> {
+    if config.encoding != Some(UiAccountEncoding::JsonParsed) {
+        return None;
+    }
+    decoders::load_decoder_idl(pool, program_id)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("[!] Failed to load IDL of {} : {}", program_id, err);
+            None
+        })
+}
+
+fn notification_method(method: &str) -> Option<&'static str> {
+    match method {
+        "accountSubscribe" => Some("accountNotification"),
+        "programSubscribe" => Some("programNotification"),
+        "signatureSubscribe" => Some("signatureNotification"),
+        "slotSubscribe" => Some("slotNotification"),
+        _ => None,
+    }
+}
+
+fn parse_pubkey(value: &Value) -> Result<Pubkey, String> {
+    value
+        .as_str()
+        .and_then(|s| Pubkey::from_str(s).ok())
+        .ok_or("Invalid pubkey".to_string())
+}
+
+fn notification(method: &str, result: Value, subscription: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": {
+            "result": result,
+            "subscription": subscription,
+        },
+    })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc":"2.0","error":{"code":code,"message":message},"id":id})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failed_upstream_subscription_is_reported() {
+        let (tx, rx) = broadcast::channel(4);
+        let (out, mut out_rx) = mpsc::unbounded_channel();
+        let forward = tokio::spawn(forward_upstream(rx, out, 7, "slotNotification"));
+        tx.send(Ok(json!({"slot": 1}))).unwrap();
+        tx.send(Err("Closed by upstream".to_string())).unwrap();
+        forward.await.unwrap();
+
+        let notification = out_rx.recv().await.unwrap();
+        assert_eq!(notification["params"]["subscription"], 7);
+        let error = out_rx.recv().await.unwrap();
+        assert_eq!(error["error"]["code"], -32000);
+        assert_eq!(error["error"]["data"]["subscription"], 7);
+        assert!(out_rx.recv().await.is_none());
+    }
+}
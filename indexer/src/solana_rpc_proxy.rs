@@ -1,10 +1,35 @@
+use std::str::FromStr;
+
 use axum::{Extension, Json};
 use http::StatusCode;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use sqlx::SqlitePool;
+use solana_account_decoder::UiAccountEncoding;
+use solana_rpc_client_api::config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    pubkey::Pubkey,
+};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use tokio::sync::OnceCell;
+
+use crate::{
+    decoders,
+    services::{
+        self, DiffError, DiffRequest, IngestState, LimitedRequestClient, PubsubHub, METRICS,
+    },
+};
+
+// Commitment of the block stream, local slot answers are only valid for it
+const INGESTED_COMMITMENT: &str = "confirmed";
+
+static UPSTREAM_VERSION: OnceCell<Value> = OnceCell::const_new();
 
-use crate::services::LimitedRequestClient;
+const MAX_SIGNATURES_LIMIT: u32 = 1000;
+
+// Label of the proxy request metrics, the cache hit rate is the share answered locally
+const LOCAL: &str = "local";
+const UPSTREAM: &str = "upstream";
 
 #[derive(Deserialize, Debug)]
 pub struct RpcRequest {
@@ -18,17 +43,53 @@ pub struct RpcRequest {
 #[serde(rename_all = "camelCase", tag = "method", content = "params")]
 enum RpcMethod {
     GetVersion,
+    GetHealth,
+    GetSlot(Option<Value>),
+    GetBlockHeight(Option<Value>),
     GetAccountInfo(Value),
     GetProgramAccounts(Value),
     GetSignaturesForAddress(Value),
+    // Extension : [{"address", "fromSlot", "toSlot"}] or [{"signature"}]
+    GetAccountDiff((DiffRequest,)),
     #[serde(untagged)]
     Unproxied(Value),
 }
 
+impl RpcMethod {
+    fn name(&self) -> String {
+        let name = match self {
+            RpcMethod::GetVersion => "getVersion",
+            RpcMethod::GetHealth => "getHealth",
+            RpcMethod::GetSlot(_) => "getSlot",
+            RpcMethod::GetBlockHeight(_) => "getBlockHeight",
+            RpcMethod::GetAccountInfo(_) => "getAccountInfo",
+            RpcMethod::GetProgramAccounts(_) => "getProgramAccounts",
+            RpcMethod::GetSignaturesForAddress(_) => "getSignaturesForAddress",
+            RpcMethod::GetAccountDiff(_) => "getAccountDiff",
+            RpcMethod::Unproxied(v) => {
+                services::method_label(v["method"].as_str().unwrap_or_default())
+            }
+        };
+        name.to_string()
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct SignaturesForAddressConfig {
+    limit: Option<u32>,
+    before: Option<String>,
+    until: Option<String>,
+    // Extension : only keep transactions whose memo contains this text
+    memo: Option<String>,
+}
+
 #[derive(Debug)]
 enum ProxyError {
     Database(sqlx::Error),
     Client(reqwest::Error),
+    // Upstream could not be reached or did not answer
+    Upstream(String),
     BadRequest(String),
     InternalServer,
 }
@@ -39,63 +100,331 @@ impl From<ProxyError> for StatusCode {
             ProxyError::Database(_) | ProxyError::InternalServer => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
-            ProxyError::Client(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::Client(_) | ProxyError::Upstream(_) => StatusCode::BAD_GATEWAY,
             ProxyError::BadRequest(_) => StatusCode::BAD_REQUEST,
         }
     }
 }
 
+// Rows stored before the account metadata was indexed are left to upstream
 async fn get_account_from_db(
     pool: &SqlitePool,
     account_id: &str,
-) -> Result<Option<Value>, ProxyError> {
-    sqlx::query!(
-        "SELECT data, slot, executable, lamports, owner, rent_epoch FROM accounts_archive WHERE id = ?",
+) -> Result<Option<(i64, Account)>, ProxyError> {
+    let row = sqlx::query!(
+        "SELECT data, encoding, slot, executable, lamports, owner, rent_epoch FROM accounts_archive WHERE id = ?",
         account_id
     )
     .fetch_optional(pool)
     .await
-    .map(|row| if let Some(row) = row {
-        Some(
-            json!({
-                "data":row.data,
-                "slot":row.slot,
-                "executable":row.executable,
-                "lamports":row.lamports,
-                "owner":row.owner,
-                "rentEpoch":row.rent_epoch
-            })
-        )
-    } else {
-            None
-        }
+    .map_err(|err| ProxyError::Database(err))?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let owner = row.owner.and_then(|owner| Pubkey::from_str(&owner).ok());
+    let (Some(lamports), Some(owner), Some(executable), Some(rent_epoch)) =
+        (row.lamports, owner, row.executable, row.rent_epoch)
+    else {
+        return Ok(None);
+    };
+    let data = services::decode_data(pool, row.encoding, row.data)
+        .await
+        .map_err(ProxyError::Database)?;
+    let account = Account {
+        lamports: lamports as u64,
+        data,
+        owner,
+        executable,
+        rent_epoch: rent_epoch as u64,
+    };
+    Ok(Some((row.slot, account)))
+}
+
+// Every archived account of the program with the latest slot among them, left to upstream
+// before the bootstrap or while some rows miss the account metadata
+async fn get_program_accounts_from_db(
+    pool: &SqlitePool,
+    program_id: &Pubkey,
+) -> Result<Option<(i64, Vec<(Pubkey, Account)>)>, ProxyError> {
+    let owner = program_id.to_string();
+    let rows = sqlx::query!(
+        "SELECT id, data, encoding, slot, executable, lamports, rent_epoch FROM accounts_archive WHERE owner = ?",
+        owner
     )
-    .map_err(|err| ProxyError::Database(err))
+    .fetch_all(pool)
+    .await
+    .map_err(ProxyError::Database)?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    let mut slot = 0;
+    let mut accounts = Vec::with_capacity(rows.len());
+    for row in rows {
+        let pubkey = Pubkey::from_str(&row.id).ok();
+        let (Some(pubkey), Some(lamports), Some(executable), Some(rent_epoch)) =
+            (pubkey, row.lamports, row.executable, row.rent_epoch)
+        else {
+            return Ok(None);
+        };
+        let data = services::decode_data(pool, row.encoding, row.data)
+            .await
+            .map_err(ProxyError::Database)?;
+        slot = slot.max(row.slot);
+        let account = Account {
+            lamports: lamports as u64,
+            data,
+            owner: *program_id,
+            executable,
+            rent_epoch: rent_epoch as u64,
+        };
+        accounts.push((pubkey, account));
+    }
+    Ok(Some((slot, accounts)))
+}
+
+async fn is_indexed_address(
+    pool: &SqlitePool,
+    hub: &PubsubHub,
+    address: &str,
+) -> Result<bool, ProxyError> {
+    if address
+        .parse()
+        .is_ok_and(|program_id| hub.is_indexed_program(&program_id))
+    {
+        return Ok(true);
+    }
+    sqlx::query!("SELECT id FROM accounts_archive WHERE id = ?", address)
+        .fetch_optional(pool)
+        .await
+        .map(|row| row.is_some())
+        .map_err(|err| ProxyError::Database(err))
+}
+
+// Where a transaction sits in the newest first order, rows indexed before `tx_index` come
+// last in their slot
+const SIGNATURE_ORDER: &str = "(t.slot, COALESCE(t.tx_index, -1), t.signature)";
+
+// None when the `before` or `until` signature is not indexed, upstream may know it
+async fn get_signatures_from_db(
+    pool: &SqlitePool,
+    address: &str,
+    config: &SignaturesForAddressConfig,
+) -> Result<Option<Vec<Value>>, ProxyError> {
+    let limit = config
+        .limit
+        .unwrap_or(MAX_SIGNATURES_LIMIT)
+        .min(MAX_SIGNATURES_LIMIT);
+
+    let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "SELECT t.signature, t.slot, t.err, t.memo, t.block_time, t.confirmation_status
+        FROM transaction_accounts a JOIN transactions t ON t.signature = a.signature
+        WHERE a.account = ",
+    );
+    query_builder.push_bind(address.to_string());
+    for (signature, comparison) in [(&config.before, " < "), (&config.until, " > ")] {
+        let Some(signature) = signature else {
+            continue;
+        };
+        let Some((slot, tx_index)) = signature_position(pool, signature).await? else {
+            return Ok(None);
+        };
+        query_builder
+            .push(format!(" AND {}{}(", SIGNATURE_ORDER, comparison))
+            .push_bind(slot)
+            .push(", ")
+            .push_bind(tx_index)
+            .push(", ")
+            .push_bind(signature.clone())
+            .push(")");
+    }
+    if let Some(memo) = &config.memo {
+        query_builder
+            .push(" AND instr(t.memo, ")
+            .push_bind(memo.clone())
+            .push(") > 0");
+    }
+    query_builder
+        .push(" ORDER BY t.slot DESC, COALESCE(t.tx_index, -1) DESC, t.signature DESC LIMIT ")
+        .push_bind(limit as i64);
+
+    let rows = query_builder
+        .build_query_as::<(
+            String,
+            i64,
+            Option<String>,
+            Option<String>,
+            Option<i64>,
+            Option<String>,
+        )>()
+        .fetch_all(pool)
+        .await
+        .map_err(|err| ProxyError::Database(err))?;
+
+    Ok(Some(
+        rows.into_iter()
+            .map(
+                |(signature, slot, err, memo, block_time, confirmation_status)| {
+                    json!({
+                        "signature": signature,
+                        "slot": slot,
+                        "err": err,
+                        "memo": memo,
+                        "blockTime": block_time,
+                        "confirmationStatus": confirmation_status,
+                    })
+                },
+            )
+            .collect(),
+    ))
+}
+
+async fn signature_position(
+    pool: &SqlitePool,
+    signature: &str,
+) -> Result<Option<(i64, i64)>, ProxyError> {
+    sqlx::query_as("SELECT slot, COALESCE(tx_index, -1) FROM transactions WHERE signature = ?")
+        .bind(signature)
+        .fetch_optional(pool)
+        .await
+        .map_err(ProxyError::Database)
+}
+
+fn memo_matches(signature: &Value, memo: &str) -> bool {
+    signature["memo"]
+        .as_str()
+        .is_some_and(|signature_memo| signature_memo.contains(memo))
+}
+
+async fn get_upstream_version(client: &LimitedRequestClient) -> Result<&'static Value, ProxyError> {
+    UPSTREAM_VERSION
+        .get_or_try_init(|| async {
+            let resp = client
+                .proxy_request(json!({"jsonrpc":"2.0","id":1,"method":"getVersion"}))
+                .await
+                .map_err(ProxyError::Upstream)?;
+            resp.get("result")
+                .cloned()
+                .ok_or(ProxyError::Upstream("getVersion without result".into()))
+        })
+        .await
+}
+
+// Returns the locally ingested value when the request can be answered from it
+fn local_slot_answer(params: &Option<Value>, local: Option<u64>) -> Option<u64> {
+    let config = params.as_ref().and_then(|p| p.get(0));
+    let commitment = config
+        .and_then(|c| c.get("commitment"))
+        .and_then(|c| c.as_str());
+    if commitment != Some(INGESTED_COMMITMENT) {
+        return None;
+    }
+    let local = local?;
+    let min_context_slot = config
+        .and_then(|c| c.get("minContextSlot"))
+        .and_then(|s| s.as_u64());
+    match min_context_slot {
+        Some(min) if min > local => None,
+        _ => Some(local),
+    }
 }
 
 #[axum::debug_handler]
 pub async fn rpx_proxy(
     Extension(client): Extension<LimitedRequestClient>,
     Extension(pool): Extension<SqlitePool>,
+    Extension(state): Extension<IngestState>,
+    Extension(hub): Extension<PubsubHub>,
     Json(request): Json<RpcRequest>,
 ) -> Result<Json<Value>, StatusCode> {
     tracing::debug!("Got request {:?}", request);
 
+    let method = request.method.name();
+    let mut answered_by = LOCAL;
     let resp = match request.method {
         RpcMethod::GetVersion => {
-            json!({"jsonrpc":"2.0","result":{"feature-set":2891131721u64,"solana-core":"1.16.7"},"id":request.id})
+            let version = get_upstream_version(&client).await?;
+            json!({"jsonrpc":"2.0","result":version,"id":request.id})
+        }
+        RpcMethod::GetHealth => match state.slots_behind() {
+            Some(behind) if behind <= services::MAX_SLOTS_BEHIND => {
+                json!({"jsonrpc":"2.0","result":"ok","id":request.id})
+            }
+            Some(behind) => json!({
+                "jsonrpc":"2.0",
+                "error":{
+                    "code":-32005,
+                    "message":format!("Node is behind by {} slots", behind),
+                    "data":{"numSlotsBehind":behind}
+                },
+                "id":request.id
+            }),
+            None => json!({
+                "jsonrpc":"2.0",
+                "error":{"code":-32005,"message":"Node is unhealthy","data":{}},
+                "id":request.id
+            }),
+        },
+        RpcMethod::GetSlot(params) => match local_slot_answer(&params, state.block_slot()) {
+            Some(slot) => json!({"jsonrpc":"2.0","result":slot,"id":request.id}),
+            None => {
+                answered_by = UPSTREAM;
+                client
+                    .proxy_request(json!({
+                        "jsonrpc": "2.0",
+                        "id": request.id,
+                        "method": "getSlot",
+                        "params": params.unwrap_or(json!([]))
+                    }))
+                    .await
+                    .map_err(ProxyError::Upstream)?
+            }
+        },
+        RpcMethod::GetBlockHeight(params) => {
+            match local_slot_answer(&params, state.block_height()) {
+                Some(height) => json!({"jsonrpc":"2.0","result":height,"id":request.id}),
+                None => {
+                    answered_by = UPSTREAM;
+                    client
+                        .proxy_request(json!({
+                            "jsonrpc": "2.0",
+                            "id": request.id,
+                            "method": "getBlockHeight",
+                            "params": params.unwrap_or(json!([]))
+                        }))
+                        .await
+                        .map_err(ProxyError::Upstream)?
+                }
+            }
         }
         RpcMethod::GetAccountInfo(params) => {
             let account_id = params[0]
                 .as_str()
                 .ok_or(ProxyError::BadRequest("Invalid account ID".into()))?;
-            let config = params.get(1).and_then(|v| v.as_object());
+            let pubkey = Pubkey::from_str(account_id)
+                .map_err(|_| ProxyError::BadRequest("Invalid account ID".into()))?;
+            let config: RpcAccountInfoConfig = params
+                .get(1)
+                .and_then(|c| serde_json::from_value(c.clone()).ok())
+                .unwrap_or_default();
 
-            if let Some(account) = get_account_from_db(&pool, account_id).await? {
+            if let Some((slot, account)) = get_account_from_db(&pool, account_id).await? {
+                let idl = if config.encoding == Some(UiAccountEncoding::JsonParsed) {
+                    decoders::load_decoder_idl(&pool, &account.owner)
+                        .await
+                        .map_err(|err| ProxyError::Database(err))?
+                } else {
+                    None
+                };
+                let value = decoders::encode_account(&pubkey, &account, &config, idl.as_ref());
                 json!({
-                    "cached":account,
+                    "jsonrpc":"2.0",
+                    "result":{"context":{"slot":slot},"value":value},
+                    "id":request.id
                 })
             } else {
+                answered_by = UPSTREAM;
                 client
                     .proxy_request(json!({
                         "jsonrpc": "2.0",
@@ -104,19 +433,319 @@ pub async fn rpx_proxy(
                         "params": params
                     }))
                     .await
-                    .map_err(|err| ProxyError::BadRequest(err))?
+                    .map_err(ProxyError::Upstream)?
+            }
+        }
+        RpcMethod::GetProgramAccounts(params) => {
+            let program_id = params[0]
+                .as_str()
+                .and_then(|id| Pubkey::from_str(id).ok())
+                .ok_or(ProxyError::BadRequest("Invalid program ID".into()))?;
+            let config: RpcProgramAccountsConfig = params
+                .get(1)
+                .and_then(|c| serde_json::from_value(c.clone()).ok())
+                .unwrap_or_default();
+
+            let local = if hub.is_indexed_program(&program_id) {
+                get_program_accounts_from_db(&pool, &program_id).await?
+            } else {
+                None
+            };
+            if let Some((slot, accounts)) = local {
+                let account_config = &config.account_config;
+                let idl = if account_config.encoding == Some(UiAccountEncoding::JsonParsed) {
+                    decoders::load_decoder_idl(&pool, &program_id)
+                        .await
+                        .map_err(ProxyError::Database)?
+                } else {
+                    None
+                };
+                let filters = config.filters.as_deref().unwrap_or_default();
+                let accounts: Vec<Value> = accounts
+                    .into_iter()
+                    .filter(|(_, account)| {
+                        let shared = AccountSharedData::from(account.clone());
+                        filters.iter().all(|filter| filter.allows(&shared))
+                    })
+                    .map(|(pubkey, account)| {
+                        json!({
+                            "pubkey": pubkey.to_string(),
+                            "account": decoders::encode_account(
+                                &pubkey,
+                                &account,
+                                account_config,
+                                idl.as_ref(),
+                            ),
+                        })
+                    })
+                    .collect();
+                // The ingested slot is newer than the last write when the accounts are idle
+                let slot = state
+                    .account_slot()
+                    .map_or(slot, |ingested| slot.max(ingested as i64));
+                let result = if config.with_context == Some(true) {
+                    json!({"context":{"slot":slot},"value":accounts})
+                } else {
+                    json!(accounts)
+                };
+                json!({"jsonrpc":"2.0","result":result,"id":request.id})
+            } else {
+                answered_by = UPSTREAM;
+                client
+                    .proxy_request(json!({
+                        "jsonrpc": "2.0",
+                        "id": request.id,
+                        "method": "getProgramAccounts",
+                        "params": params
+                    }))
+                    .await
+                    .map_err(ProxyError::Upstream)?
+            }
+        }
+        RpcMethod::GetSignaturesForAddress(mut params) => {
+            let address = params[0]
+                .as_str()
+                .ok_or(ProxyError::BadRequest("Invalid address".into()))?
+                .to_string();
+            let config: SignaturesForAddressConfig = params
+                .get(1)
+                .and_then(|c| serde_json::from_value(c.clone()).ok())
+                .unwrap_or_default();
+
+            let local = if is_indexed_address(&pool, &hub, &address).await? {
+                get_signatures_from_db(&pool, &address, &config).await?
+            } else {
+                None
+            };
+            if let Some(signatures) = local {
+                json!({"jsonrpc":"2.0","result":signatures,"id":request.id})
+            } else {
+                answered_by = UPSTREAM;
+                // upstream does not know the memo extension, filter its answer instead
+                if let Some(c) = params.get_mut(1).and_then(|c| c.as_object_mut()) {
+                    c.remove("memo");
+                }
+                let mut resp = client
+                    .proxy_request(json!({
+                        "jsonrpc": "2.0",
+                        "id": request.id,
+                        "method": "getSignaturesForAddress",
+                        "params": params
+                    }))
+                    .await
+                    .map_err(ProxyError::Upstream)?;
+                if let (Some(memo), Some(signatures)) = (
+                    &config.memo,
+                    resp.get_mut("result").and_then(|r| r.as_array_mut()),
+                ) {
+                    signatures.retain(|signature| memo_matches(signature, memo));
+                }
+                resp
+            }
+        }
+        RpcMethod::GetAccountDiff((diff_request,)) => {
+            match services::state_diff(&pool, &diff_request).await {
+                Ok(diff) => json!({"jsonrpc":"2.0","result":diff,"id":request.id}),
+                Err(DiffError::BadRequest(message) | DiffError::NotFound(message)) => json!({
+                    "jsonrpc":"2.0",
+                    "error":{"code":-32602,"message":message},
+                    "id":request.id
+                }),
+                Err(DiffError::Database(err)) => return Err(ProxyError::Database(err).into()),
             }
         }
-        RpcMethod::GetProgramAccounts(_) => todo!(),
-        RpcMethod::GetSignaturesForAddress(_) => todo!(),
         RpcMethod::Unproxied(v) => {
             tracing::info!("Unproxied request {:?}", v);
+            answered_by = UPSTREAM;
             client
                 .proxy_request(v)
                 .await
-                .map_err(|err| ProxyError::BadRequest(err))?
+                .map_err(ProxyError::Upstream)?
         }
     };
 
+    METRICS.proxy_request(&method, answered_by);
     Ok(Json(resp))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use argos_mock_rpc::{Fixtures, MockServer};
+
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/rpc/ore.json");
+    const PAYER: &str = "AWxggjuZRmWULwxwPeM6ZZxRtdDdekVq22mFRx2QbW7U";
+
+    async fn call(server: &MockServer, pool: &SqlitePool, request: Value) -> Value {
+        let client = LimitedRequestClient::new(&server.rpc_url(), 45, Duration::from_secs(1));
+        let Json(resp) = rpx_proxy(
+            Extension(client),
+            Extension(pool.clone()),
+            Extension(IngestState::default()),
+            Extension(PubsubHub::new(vec![crate::PROGRAM_ID])),
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await
+        .unwrap();
+        resp
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn unreachable_upstream_is_a_bad_gateway(pool: SqlitePool) {
+        let client = LimitedRequestClient::new("http://127.0.0.1:1", 45, Duration::from_secs(1));
+        let status = rpx_proxy(
+            Extension(client),
+            Extension(pool),
+            Extension(IngestState::default()),
+            Extension(PubsubHub::new(vec![crate::PROGRAM_ID])),
+            Json(
+                serde_json::from_value(json!({"jsonrpc":"2.0","id":1,"method":"getVersion"}))
+                    .unwrap(),
+            ),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn unindexed_signatures_filtered_by_memo(pool: SqlitePool) {
+        let server = MockServer::start(Fixtures::load(FIXTURES).unwrap()).await;
+        let resp = call(
+            &server,
+            &pool,
+            json!({
+                "jsonrpc":"2.0",
+                "id":1,
+                "method":"getSignaturesForAddress",
+                "params":[PAYER, {"memo":"argos"}]
+            }),
+        )
+        .await;
+        let signatures = resp["result"].as_array().unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0]["memo"], "[9] argos fixture");
+        // upstream never sees the extension
+        assert_eq!(server.requests()[0]["params"][1], json!({}));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn signature_pages_split_slots(pool: SqlitePool) {
+        let server = MockServer::start(Fixtures::load(FIXTURES).unwrap()).await;
+        let program = crate::PROGRAM_ID.to_string();
+        for (signature, tx_index) in [("a", 2), ("b", 0), ("c", 1)] {
+            sqlx::query(
+                "INSERT INTO transactions (signature, slot, tx_index) VALUES (?1, 100, ?2)",
+            )
+            .bind(signature)
+            .bind(tx_index)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO transaction_accounts (signature, position, account, slot, writable)
+                VALUES (?1, 0, ?2, 100, false)",
+            )
+            .bind(signature)
+            .bind(&program)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let page = |before: Option<&str>| {
+            json!({
+                "jsonrpc":"2.0",
+                "id":1,
+                "method":"getSignaturesForAddress",
+                "params":[program, {"limit":2, "before":before}]
+            })
+        };
+        let signatures = |resp: Value| -> Vec<String> {
+            resp["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s["signature"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let first = call(&server, &pool, page(None)).await;
+        assert_eq!(signatures(first), vec!["a", "c"]);
+        let second = call(&server, &pool, page(Some("c"))).await;
+        assert_eq!(signatures(second), vec!["b"]);
+        assert!(server.methods().is_empty());
+
+        // A cursor that was never indexed is left to upstream
+        call(&server, &pool, page(Some("unknown"))).await;
+        assert_eq!(server.methods(), vec!["getSignaturesForAddress"]);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn slot_without_ingestion_goes_upstream(pool: SqlitePool) {
+        let server = MockServer::start(Fixtures::load(FIXTURES).unwrap()).await;
+        let resp = call(
+            &server,
+            &pool,
+            json!({
+                "jsonrpc":"2.0",
+                "id":7,
+                "method":"getSlot",
+                "params":[{"commitment":"confirmed"}]
+            }),
+        )
+        .await;
+        assert_eq!(resp["result"], 300_000_000);
+        assert_eq!(resp["id"], 7);
+        assert_eq!(server.methods(), vec!["getSlot"]);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn program_accounts_from_the_archive(pool: SqlitePool) {
+        let server = MockServer::start(Fixtures::load(FIXTURES).unwrap()).await;
+        let program = crate::PROGRAM_ID.to_string();
+        for (id, slot, data) in [("a", 10, vec![1u8, 2]), ("b", 12, vec![1, 2, 3])] {
+            let id = Pubkey::new_from_array([id.as_bytes()[0]; 32]).to_string();
+            sqlx::query(
+                "INSERT INTO accounts_archive (id, slot, data, executable, lamports, owner, rent_epoch)
+                VALUES (?1, ?2, ?3, false, 1, ?4, 0)",
+            )
+            .bind(id)
+            .bind(slot)
+            .bind(data)
+            .bind(&program)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let resp = call(
+            &server,
+            &pool,
+            json!({
+                "jsonrpc":"2.0",
+                "id":1,
+                "method":"getProgramAccounts",
+                "params":[program, {"withContext":true, "filters":[{"dataSize":3}]}]
+            }),
+        )
+        .await;
+        assert_eq!(resp["result"]["context"]["slot"], 12);
+        let accounts = resp["result"]["value"].as_array().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0]["account"]["data"], json!(["AQID", "base64"]));
+        assert!(server.methods().is_empty());
+
+        // Any other program is left to upstream
+        let other = Pubkey::new_unique().to_string();
+        call(
+            &server,
+            &pool,
+            json!({"jsonrpc":"2.0","id":2,"method":"getProgramAccounts","params":[other]}),
+        )
+        .await;
+        assert_eq!(server.methods(), vec!["getProgramAccounts"]);
+    }
+}
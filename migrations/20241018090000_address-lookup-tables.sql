@@ -0,0 +1,22 @@
+CREATE TABLE address_lookup_tables(
+    id TEXT NOT NULL,
+    slot INT NOT NULL,
+    deactivation_slot INT NOT NULL,
+    last_extended_slot INT NOT NULL,
+    authority TEXT,
+    addresses BLOB NOT NULL,
+    PRIMARY KEY (id, slot)
+);
+
+ALTER TABLE transactions ADD COLUMN loaded_writable TEXT;
+ALTER TABLE transactions ADD COLUMN loaded_readonly TEXT;
+
+CREATE TABLE transaction_accounts(
+    signature TEXT NOT NULL,
+    position INT NOT NULL,
+    account TEXT NOT NULL,
+    slot INT NOT NULL,
+    writable BOOLEAN NOT NULL,
+    PRIMARY KEY (signature, position)
+);
+CREATE INDEX idx_transaction_accounts_account_slot ON transaction_accounts (account, slot);
@@ -0,0 +1,26 @@
+-- inner_index is -1 for top level instructions
+CREATE TABLE instructions(
+    signature TEXT NOT NULL,
+    slot INT NOT NULL,
+    ix_index INT NOT NULL,
+    inner_index INT NOT NULL,
+    stack_height INT NOT NULL,
+    program_id TEXT NOT NULL,
+    discriminator BLOB NOT NULL,
+    data BLOB NOT NULL,
+    accounts TEXT NOT NULL,
+    PRIMARY KEY (signature, ix_index, inner_index)
+);
+CREATE INDEX idx_instructions_program_discriminator ON instructions (program_id, discriminator, slot);
+
+CREATE TABLE instruction_accounts(
+    signature TEXT NOT NULL,
+    ix_index INT NOT NULL,
+    inner_index INT NOT NULL,
+    position INT NOT NULL,
+    account TEXT NOT NULL,
+    program_id TEXT NOT NULL,
+    slot INT NOT NULL,
+    PRIMARY KEY (signature, ix_index, inner_index, position)
+);
+CREATE INDEX idx_instruction_accounts_account_program ON instruction_accounts (account, program_id, slot);
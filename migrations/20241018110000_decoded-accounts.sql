@@ -0,0 +1,10 @@
+CREATE TABLE decoded_accounts(
+    id TEXT NOT NULL,
+    slot INT NOT NULL,
+    program_id TEXT NOT NULL,
+    account_type TEXT NOT NULL,
+    data JSON NOT NULL,
+    PRIMARY KEY (id, slot)
+);
+CREATE INDEX idx_decoded_accounts_type_slot ON decoded_accounts (program_id, account_type, slot);
+CREATE INDEX idx_decoded_accounts_proof_authority ON decoded_accounts (json_extract(data, '$.authority'), slot) WHERE account_type = 'proof';
@@ -0,0 +1,20 @@
+-- inner_index is -1 for top level instructions, reward is filled once the proof
+-- versions around the slot are known
+CREATE TABLE ore_events(
+    signature TEXT NOT NULL,
+    slot INT NOT NULL,
+    ix_index INT NOT NULL,
+    inner_index INT NOT NULL,
+    kind TEXT NOT NULL,
+    signer TEXT NOT NULL,
+    proof TEXT,
+    bus TEXT,
+    amount INT,
+    difficulty INT,
+    reward INT,
+    data JSON NOT NULL,
+    PRIMARY KEY (signature, ix_index, inner_index)
+);
+CREATE INDEX idx_ore_events_proof_slot ON ore_events (proof, slot);
+CREATE INDEX idx_ore_events_signer_slot ON ore_events (signer, slot);
+CREATE INDEX idx_ore_events_kind_slot ON ore_events (kind, slot);
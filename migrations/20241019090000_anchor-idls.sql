@@ -0,0 +1,11 @@
+-- source is 'registered' for user provided IDLs, 'onchain' for the ones fetched from the IDL account
+CREATE TABLE idls(
+    program_id TEXT NOT NULL PRIMARY KEY,
+    source TEXT NOT NULL,
+    idl JSON NOT NULL,
+    updated_at INT NOT NULL
+);
+
+ALTER TABLE instructions ADD COLUMN name TEXT;
+ALTER TABLE instructions ADD COLUMN decoded JSON;
+CREATE INDEX idx_instructions_program_name ON instructions (program_id, name, slot);
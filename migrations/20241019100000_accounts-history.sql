@@ -0,0 +1,12 @@
+-- Every version of the indexed accounts, accounts_archive only keeps the latest one
+CREATE TABLE accounts_history(
+    id TEXT NOT NULL,
+    slot INT NOT NULL,
+    data BLOB NOT NULL,
+    executable BOOLEAN NOT NULL,
+    lamports INTEGER NOT NULL,
+    owner TEXT NOT NULL,
+    rent_epoch INTEGER NOT NULL,
+    PRIMARY KEY (id, slot)
+);
+CREATE INDEX idx_accounts_history_owner_slot ON accounts_history (owner, slot);
@@ -0,0 +1,14 @@
+-- Position of the transaction in its block, orders the transactions of a slot
+ALTER TABLE transactions ADD COLUMN tx_index INT;
+CREATE INDEX idx_transactions_slot_tx_index ON transactions (slot, tx_index);
+
+-- Successful transactions that had the account writable, for each slot an account version exists.
+-- The one with the highest tx_index produced the stored version.
+CREATE TABLE account_writes(
+    account TEXT NOT NULL,
+    slot INT NOT NULL,
+    tx_index INT NOT NULL,
+    signature TEXT NOT NULL,
+    PRIMARY KEY (account, slot, tx_index)
+);
+CREATE INDEX idx_account_writes_signature ON account_writes (signature);
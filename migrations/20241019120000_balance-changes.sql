@@ -0,0 +1,15 @@
+-- mint is 'SOL' for lamports, owner is the wallet of a token account
+CREATE TABLE balance_changes(
+    signature TEXT NOT NULL,
+    slot INT NOT NULL,
+    account TEXT NOT NULL,
+    mint TEXT NOT NULL,
+    owner TEXT,
+    pre INTEGER NOT NULL,
+    post INTEGER NOT NULL,
+    delta INTEGER NOT NULL,
+    decimals INT NOT NULL,
+    PRIMARY KEY (signature, account, mint)
+);
+CREATE INDEX idx_balance_changes_account_mint ON balance_changes (account, mint, slot);
+CREATE INDEX idx_balance_changes_owner_mint ON balance_changes (owner, mint, slot);
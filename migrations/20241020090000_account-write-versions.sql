@@ -0,0 +1,3 @@
+-- Order of the writes within a slot, only known when fed by the geyser plugin
+ALTER TABLE accounts_archive ADD COLUMN write_version INTEGER;
+ALTER TABLE accounts_history ADD COLUMN write_version INTEGER;
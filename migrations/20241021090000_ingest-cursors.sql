@@ -0,0 +1,6 @@
+-- Last slot each ingestion stream processed, written on shutdown
+CREATE TABLE ingest_cursors(
+    stream TEXT PRIMARY KEY NOT NULL,
+    slot INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
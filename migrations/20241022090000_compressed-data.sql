@@ -0,0 +1,15 @@
+-- How data is stored : NULL or 0 raw, 1 zstd (the frame names its dictionary), 2 zstd of the
+-- XOR against the history version at base_slot
+ALTER TABLE accounts_archive ADD COLUMN encoding INTEGER;
+ALTER TABLE accounts_history ADD COLUMN encoding INTEGER;
+ALTER TABLE accounts_history ADD COLUMN base_slot INTEGER;
+ALTER TABLE transactions ADD COLUMN encoding INTEGER;
+
+-- Trained per program, dict_id is the id zstd writes in the frame header
+CREATE TABLE zstd_dictionaries(
+    dict_id INTEGER NOT NULL PRIMARY KEY,
+    program_id TEXT NOT NULL,
+    dictionary BLOB NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX idx_zstd_dictionaries_program ON zstd_dictionaries (program_id, created_at);
@@ -0,0 +1,10 @@
+-- Lookup table addresses as the streamed transactions loaded them, with the slots they were seen at
+CREATE TABLE lookup_table_entries(
+    id TEXT NOT NULL,
+    idx INT NOT NULL,
+    address TEXT NOT NULL,
+    first_slot INT NOT NULL,
+    last_slot INT NOT NULL,
+    PRIMARY KEY (id, idx, address)
+);
+CREATE INDEX idx_lookup_table_entries_last_slot ON lookup_table_entries (id, last_slot);
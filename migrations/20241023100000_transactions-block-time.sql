@@ -0,0 +1,2 @@
+-- Time bounded queries on slot keyed tables map the bounds to slots through it
+CREATE INDEX idx_transactions_block_time ON transactions (block_time);
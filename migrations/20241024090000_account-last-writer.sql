@@ -0,0 +1,14 @@
+-- Only the last successful writer of the slot produced the stored version, one row per version
+CREATE TABLE account_writes_last(
+    account TEXT NOT NULL,
+    slot INT NOT NULL,
+    tx_index INT NOT NULL,
+    signature TEXT NOT NULL,
+    PRIMARY KEY (account, slot)
+);
+INSERT INTO account_writes_last (account, slot, tx_index, signature)
+SELECT w.account, w.slot, w.tx_index, w.signature FROM account_writes w
+WHERE w.tx_index = (SELECT max(l.tx_index) FROM account_writes l WHERE l.account = w.account AND l.slot = w.slot);
+DROP TABLE account_writes;
+ALTER TABLE account_writes_last RENAME TO account_writes;
+CREATE INDEX idx_account_writes_signature ON account_writes (signature);
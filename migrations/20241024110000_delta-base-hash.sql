@@ -0,0 +1,2 @@
+-- sha256 of the decoded version a delta was made against, a base rewritten since no longer matches
+ALTER TABLE accounts_history ADD COLUMN base_hash BLOB;
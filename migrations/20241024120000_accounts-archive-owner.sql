@@ -0,0 +1,2 @@
+-- getProgramAccounts of the indexed programs is answered from the archive
+CREATE INDEX idx_accounts_archive_owner ON accounts_archive (owner);
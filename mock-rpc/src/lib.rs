@@ -0,0 +1,339 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    routing::post,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+const MAX_SIGNATURES_LIMIT: usize = 1000;
+
+// What the server answers with, every value in the shape the RPC returns it
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Fixtures {
+    // getSlot, and the context slot of getProgramAccounts
+    pub slot: u64,
+    // Program id to its keyed accounts
+    pub program_accounts: HashMap<String, Vec<Value>>,
+    // Program id to the {"slot", "pubkey", "account"} notifications of programSubscribe
+    pub program_notifications: HashMap<String, Vec<Value>>,
+    // getBlock, getBlocks and the notifications of blockSubscribe. A block lists the
+    // signatures of the whole block, only transactionDetails "signatures" returns them.
+    pub blocks: BTreeMap<u64, Value>,
+    // Notifications of rootSubscribe
+    pub roots: Vec<u64>,
+    pub transactions: HashMap<String, Value>,
+    // Address to its signatures, newest first
+    pub signatures: HashMap<String, Vec<Value>>,
+}
+
+impl Fixtures {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = std::fs::read(path).map_err(|err| format!("{} : {}", path.display(), err))?;
+        serde_json::from_slice(&file).map_err(|err| format!("{} : {}", path.display(), err))
+    }
+}
+
+#[derive(Clone)]
+struct MockState {
+    fixtures: Arc<Fixtures>,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+// JSON-RPC over HTTP and PubSub over websocket on the same local port
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Value>>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start(fixtures: Fixtures) -> Self {
+        let state = MockState {
+            fixtures: Arc::new(fixtures),
+            requests: Arc::new(Mutex::new(vec![])),
+        };
+        let requests = state.requests.clone();
+        let app = Router::new()
+            .route("/", post(rpc).get(pubsub))
+            .with_state(state);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::task::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self {
+            addr,
+            requests,
+            handle,
+        }
+    }
+
+    pub fn rpc_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    // Every request received so far, HTTP and websocket alike
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn methods(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .filter_map(|request| request["method"].as_str().map(str::to_string))
+            .collect()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn rpc(State(state): State<MockState>, Json(request): Json<Value>) -> Json<Value> {
+    state.requests.lock().unwrap().push(request.clone());
+    let id = request["id"].clone();
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = &request["params"];
+    let resp = match answer(&state.fixtures, method, params) {
+        Ok(result) => json!({"jsonrpc":"2.0","result":result,"id":id}),
+        Err((code, message)) => json!({
+            "jsonrpc":"2.0",
+            "error":{"code":code,"message":message},
+            "id":id
+        }),
+    };
+    Json(resp)
+}
+
+fn answer(fixtures: &Fixtures, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+    match method {
+        "getSlot" => Ok(json!(fixtures.slot)),
+        "getProgramAccounts" => {
+            let program_id = params[0].as_str().unwrap_or_default();
+            let accounts = fixtures
+                .program_accounts
+                .get(program_id)
+                .cloned()
+                .unwrap_or_default();
+            if params[1]["withContext"].as_bool() == Some(true) {
+                Ok(json!({"context":{"slot":fixtures.slot},"value":accounts}))
+            } else {
+                Ok(json!(accounts))
+            }
+        }
+        "getBlock" => {
+            let slot = params[0].as_u64().ok_or(invalid_params())?;
+            let block = fixtures.blocks.get(&slot).ok_or((
+                -32009,
+                format!("Slot {} was skipped, or missing in long-term storage", slot),
+            ))?;
+            let details = params[1]["transactionDetails"].as_str().unwrap_or("full");
+            Ok(block_details(block, details))
+        }
+        "getBlocks" => {
+            let start = params[0].as_u64().ok_or(invalid_params())?;
+            let end = params[1].as_u64().unwrap_or(fixtures.slot);
+            let slots: Vec<u64> = fixtures
+                .blocks
+                .range(start..=end)
+                .map(|(slot, _)| *slot)
+                .collect();
+            Ok(json!(slots))
+        }
+        "getTransaction" => {
+            let signature = params[0].as_str().ok_or(invalid_params())?;
+            Ok(fixtures
+                .transactions
+                .get(signature)
+                .cloned()
+                .unwrap_or(Value::Null))
+        }
+        "getSignaturesForAddress" => {
+            let address = params[0].as_str().ok_or(invalid_params())?;
+            let signatures = fixtures
+                .signatures
+                .get(address)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            Ok(json!(signatures_page(signatures, &params[1])))
+        }
+        _ => Err((-32601, "Method not found".to_string())),
+    }
+}
+
+fn block_details(block: &Value, details: &str) -> Value {
+    let mut block = block.clone();
+    if let Some(fields) = block.as_object_mut() {
+        match details {
+            "signatures" => fields.remove("transactions"),
+            _ => fields.remove("signatures"),
+        };
+    }
+    block
+}
+
+fn invalid_params() -> (i64, String) {
+    (-32602, "Invalid params".to_string())
+}
+
+// Applies the before, until and limit of the config to newest first signatures
+fn signatures_page(signatures: &[Value], config: &Value) -> Vec<Value> {
+    let position = |key: &str| {
+        let signature = config[key].as_str()?;
+        signatures
+            .iter()
+            .position(|s| s["signature"].as_str() == Some(signature))
+    };
+    let start = position("before").map_or(0, |i| i + 1);
+    let end = position("until").unwrap_or(signatures.len()).max(start);
+    let limit = config["limit"]
+        .as_u64()
+        .map_or(MAX_SIGNATURES_LIMIT, |limit| limit as usize)
+        .min(MAX_SIGNATURES_LIMIT);
+    signatures[start..end].iter().take(limit).cloned().collect()
+}
+
+async fn pubsub(State(state): State<MockState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| subscriptions(socket, state))
+}
+
+// Every scripted notification is sent right after the subscription is confirmed
+async fn subscriptions(mut socket: WebSocket, state: MockState) {
+    let mut next_subscription = 0u64;
+    while let Some(Ok(msg)) = socket.recv().await {
+        let Message::Text(text) = msg else {
+            continue;
+        };
+        let Ok(request) = serde_json::from_str::<Value>(&text) else {
+            continue;
+        };
+        state.requests.lock().unwrap().push(request.clone());
+        let id = request["id"].clone();
+        let method = request["method"].as_str().unwrap_or_default();
+
+        let notifications: Vec<(&str, Value)> = match method {
+            "programSubscribe" => {
+                let program_id = request["params"][0].as_str().unwrap_or_default();
+                state
+                    .fixtures
+                    .program_notifications
+                    .get(program_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|notification| {
+                        let value = json!({
+                            "pubkey": notification["pubkey"],
+                            "account": notification["account"],
+                        });
+                        (
+                            "programNotification",
+                            json!({"context":{"slot":notification["slot"]},"value":value}),
+                        )
+                    })
+                    .collect()
+            }
+            "blockSubscribe" => state
+                .fixtures
+                .blocks
+                .iter()
+                .map(|(slot, block)| {
+                    let block = block_details(block, "full");
+                    let value = json!({"slot":slot,"block":block,"err":null});
+                    (
+                        "blockNotification",
+                        json!({"context":{"slot":slot},"value":value}),
+                    )
+                })
+                .collect(),
+            "rootSubscribe" => state
+                .fixtures
+                .roots
+                .iter()
+                .map(|root| ("rootNotification", json!(root)))
+                .collect(),
+            method if method.ends_with("Unsubscribe") => {
+                let resp = json!({"jsonrpc":"2.0","result":true,"id":id});
+                if socket.send(Message::Text(resp.to_string())).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            _ => {
+                let resp = json!({
+                    "jsonrpc":"2.0",
+                    "error":{"code":-32601,"message":"Method not found"},
+                    "id":id
+                });
+                if socket.send(Message::Text(resp.to_string())).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let subscription = next_subscription;
+        next_subscription += 1;
+        let mut messages = vec![json!({"jsonrpc":"2.0","result":subscription,"id":id})];
+        messages.extend(notifications.into_iter().map(|(method, result)| {
+            json!({
+                "jsonrpc":"2.0",
+                "method":method,
+                "params":{"result":result,"subscription":subscription}
+            })
+        }));
+        for message in messages {
+            if socket
+                .send(Message::Text(message.to_string()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_pages() {
+        let signatures: Vec<Value> = ["e", "d", "c", "b", "a"]
+            .iter()
+            .map(|signature| json!({"signature": signature}))
+            .collect();
+        let page = |config: Value| -> Vec<String> {
+            signatures_page(&signatures, &config)
+                .iter()
+                .map(|s| s["signature"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(page(json!({"limit": 2})), vec!["e", "d"]);
+        assert_eq!(page(json!({"before": "d", "limit": 2})), vec!["c", "b"]);
+        assert_eq!(page(json!({"before": "d", "until": "b"})), vec!["c"]);
+        assert_eq!(page(json!({"until": "z"})), vec!["e", "d", "c", "b", "a"]);
+        assert!(page(json!({"before": "b", "until": "c"})).is_empty());
+    }
+}